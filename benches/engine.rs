@@ -0,0 +1,119 @@
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use petri::clocks::VectorClock;
+use petri::config::Config;
+use petri::engine::{load_nets, Engine};
+use petri::generate;
+use petri::model::{ActiveEvent, NodeId, WireMessage};
+use std::path::{Path, PathBuf};
+
+/// Sizes of the generated nets, in transitions
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Transitions every firing enables
+const FANOUT: usize = 2;
+
+/// Folder with a generated net of `transitions` transitions in `subnets` segments, written
+/// once and reused by later runs.
+fn generated(transitions: usize, subnets: usize) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("petri-bench-{transitions}-{subnets}"));
+    if !folder.exists() {
+        generate::write(
+            &folder,
+            &generate::generate(transitions, FANOUT, subnets, 0),
+        )
+        .expect("Failed to write the generated net");
+    }
+    folder
+}
+
+/// A single node hosting every segment in `nets_folder`, listening on Unix socket `name`
+/// so benchmarks running side by side do not compete for a port.
+fn single_node(nets_folder: &Path, name: &str) -> Engine {
+    let socket =
+        std::env::temp_dir().join(format!("petri-bench-{name}-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let socket = socket.display().to_string();
+    let config = Config::parse_from([
+        "petri",
+        "--node",
+        &socket,
+        "--nodes",
+        &socket,
+        "--nets-folder",
+        &nets_folder.display().to_string(),
+        // ticked for as long as the benchmark takes
+        "--terminal-clock",
+        &usize::MAX.to_string(),
+    ]);
+    Engine::new(&config).expect("Failed to start the node")
+}
+
+fn loading(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load_nets");
+    for transitions in SIZES {
+        let folder = generated(transitions, 4);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(transitions),
+            &folder,
+            |b, folder| b.iter(|| load_nets(folder).expect("Failed to load the generated net")),
+        );
+    }
+    group.finish();
+}
+
+fn ticking(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    for transitions in SIZES {
+        let mut engine = single_node(&generated(transitions, 1), &transitions.to_string());
+        group.bench_function(BenchmarkId::from_parameter(transitions), |b| {
+            b.iter(|| engine.conservative_tick().expect("Failed to tick"))
+        });
+    }
+    group.finish();
+}
+
+fn serializing(c: &mut Criterion) {
+    let node = NodeId::from("127.0.0.1:7001");
+    let mut vector_clock = VectorClock::default();
+    vector_clock.tick(&node);
+    let message = WireMessage::Active(ActiveEvent {
+        feeding_node: node,
+        transition_id: 42,
+        value: 0,
+        clock: 1_000,
+        anti: false,
+        color: 0,
+        seq: 7,
+        vector_clock,
+        channel_seq: 7,
+        lamport: 7,
+        trace: None,
+    });
+    let json = serde_json::to_vec(&message).expect("Failed to serialize");
+    let bincode = bincode::serialize(&message).expect("Failed to serialize");
+    let message_pack = rmp_serde::to_vec_named(&message).expect("Failed to serialize");
+
+    let mut group = c.benchmark_group("serialize");
+    group.bench_function("json", |b| b.iter(|| serde_json::to_vec(&message)));
+    group.bench_function("bincode", |b| b.iter(|| bincode::serialize(&message)));
+    group.bench_function("message_pack", |b| {
+        b.iter(|| rmp_serde::to_vec_named(&message))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("deserialize");
+    group.bench_function("json", |b| {
+        b.iter(|| serde_json::from_slice::<WireMessage>(&json))
+    });
+    group.bench_function("bincode", |b| {
+        b.iter(|| bincode::deserialize::<WireMessage>(&bincode))
+    });
+    group.bench_function("message_pack", |b| {
+        b.iter(|| rmp_serde::from_slice::<WireMessage>(&message_pack))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, loading, ticking, serializing);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc comes with the build dependencies, so building needs nothing installed
+    #[cfg(any(feature = "grpc", feature = "protobuf"))]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        // the service only goes with --link grpc, the messages with --wire-format protobuf too
+        tonic_prost_build::configure()
+            .build_client(cfg!(feature = "grpc"))
+            .build_server(cfg!(feature = "grpc"))
+            .compile_protos(&["proto/petri.proto"], &["proto"])?;
+    }
+
+    Ok(())
+}
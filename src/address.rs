@@ -0,0 +1,123 @@
+use std::fmt::Display;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where a node listens or is dialed, parsed once from the command line so a typo fails
+/// the start rather than surfacing as an IO error on the first connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NodeAddr {
+    /// ip:port, or [ipv6]:port
+    Socket(SocketAddr),
+    /// host:port, looked up on every connection rather than once, as names may point
+    /// elsewhere by the time a peer is reconnected to
+    Host { host: String, port: u16 },
+    /// Path of a Unix socket, for same-host clusters
+    Unix(PathBuf),
+}
+
+impl NodeAddr {
+    /// Address the node is at now, looking its host name up if it has one.
+    pub fn resolve(&self) -> std::io::Result<SocketAddr> {
+        self.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("{self} resolves to no address"),
+            )
+        })
+    }
+
+    /// Host part, which the node's certificate has to be valid for, IPv6 addresses
+    /// without their brackets.
+    #[cfg_attr(not(any(feature = "tls", feature = "mqtt")), allow(dead_code))]
+    pub fn host(&self) -> String {
+        match self {
+            Self::Socket(address) => address.ip().to_string(),
+            Self::Host { host, .. } => host.clone(),
+            Self::Unix(path) => path.display().to_string(),
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Socket(address) => Some(address.port()),
+            Self::Host { port, .. } => Some(*port),
+            Self::Unix(_) => None,
+        }
+    }
+
+    /// The same address on another port, Unix socket paths staying as they are.
+    pub fn with_port(&self, port: u16) -> Self {
+        match self {
+            Self::Socket(address) => Self::Socket(SocketAddr::new(address.ip(), port)),
+            Self::Host { host, .. } => Self::Host {
+                host: host.clone(),
+                port,
+            },
+            Self::Unix(path) => Self::Unix(path.clone()),
+        }
+    }
+
+    #[cfg_attr(not(feature = "zeromq"), allow(dead_code))]
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self, Self::Socket(SocketAddr::V6(_)))
+    }
+}
+
+impl FromStr for NodeAddr {
+    type Err = std::io::Error;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        if address.contains('/') {
+            return Ok(Self::Unix(PathBuf::from(address)));
+        }
+        if let Ok(address) = address.parse::<SocketAddr>() {
+            return Ok(Self::Socket(address));
+        }
+
+        address
+            .rsplit_once(':')
+            .filter(|(host, _)| !host.is_empty() && !host.contains(':'))
+            .and_then(|(host, port)| {
+                Some(Self::Host {
+                    host: host.to_string(),
+                    port: port.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "{address} is not an address such as 10.0.0.1:5001, [::1]:5001, \
+                         sim-node-2.lab:5001 or /tmp/node.sock"
+                    ),
+                )
+            })
+    }
+}
+
+impl Display for NodeAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Socket(address) => write!(f, "{address}"),
+            Self::Host { host, port } => write!(f, "{host}:{port}"),
+            Self::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Lets the standard library connect and bind to it, Unix socket paths excepted.
+impl ToSocketAddrs for NodeAddr {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+        match self {
+            Self::Socket(address) => Ok(vec![*address].into_iter()),
+            Self::Host { host, port } => (host.as_str(), *port).to_socket_addrs(),
+            Self::Unix(path) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is a Unix socket path, not an address", path.display()),
+            )),
+        }
+    }
+}
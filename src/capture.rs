@@ -0,0 +1,218 @@
+use crate::config::Direction;
+use crate::error::Result;
+use crate::logging;
+use crate::model::{Control, WireMessage};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Every message this node sends and receives, one JSON line each in <node>.capture,
+/// for `petri inspect-capture` to tell after the fact what went over the wire.
+#[derive(Debug)]
+pub struct Capture {
+    file: Mutex<BufWriter<File>>,
+    /// Local clock, as of the last tick, for messages received on the listener's thread
+    clock: AtomicUsize,
+}
+
+/// A captured message, `M` being the message itself when written and any JSON value when
+/// read back, so captures of other versions can still be inspected.
+#[derive(Debug, Serialize, Deserialize)]
+struct Frame<M> {
+    direction: Direction,
+    /// Node the message went to or came from, unknown for messages that do not tell
+    peer: Option<String>,
+    wall_time: String,
+    clock: usize,
+    kind: String,
+    message: M,
+}
+
+impl Capture {
+    pub fn create(node: &str) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(File::create(format!("{node}.capture"))?)),
+            clock: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn set_clock(&self, clock: usize) {
+        self.clock.store(clock, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, fed_node: &str, message: &WireMessage) {
+        self.record(Direction::Sent, Some(fed_node), message);
+    }
+
+    pub fn received(&self, message: &WireMessage) {
+        self.record(Direction::Received, sender(message), message);
+    }
+
+    fn record(&self, direction: Direction, peer: Option<&str>, message: &WireMessage) {
+        let frame = Frame {
+            direction,
+            peer: peer.map(str::to_string),
+            wall_time: chrono::Local::now().to_rfc3339(),
+            clock: self.clock.load(Ordering::Relaxed),
+            kind: kind(message),
+            message,
+        };
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let written = serde_json::to_writer(&mut *file, &frame)
+            .map_err(std::io::Error::from)
+            .and_then(|_| file.write_all(b"\n"));
+        if let Err(error) = written {
+            warn!(target: logging::NETWORK, %error, "NOT CAPTURED");
+        }
+    }
+
+    /// Writes out what is captured so far, so a node that dies leaves its capture behind.
+    pub fn flush(&self) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(error) = file.flush() {
+            warn!(target: logging::NETWORK, %error, "CAPTURE NOT WRITTEN");
+        }
+    }
+}
+
+/// Which captured messages `inspect` shows, all of them by default.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub direction: Option<Direction>,
+    pub peer: Option<String>,
+    /// Kinds such as Active, Passive or GvtToken
+    pub kinds: Vec<String>,
+    pub from_clock: Option<usize>,
+    pub to_clock: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, frame: &Frame<serde_json::Value>) -> bool {
+        self.direction
+            .is_none_or(|direction| direction == frame.direction)
+            && self
+                .peer
+                .as_ref()
+                .is_none_or(|peer| frame.peer.as_ref() == Some(peer))
+            && (self.kinds.is_empty()
+                || self
+                    .kinds
+                    .iter()
+                    .any(|kind| frame.kind.split('(').next() == Some(kind)))
+            && self.from_clock.is_none_or(|clock| frame.clock >= clock)
+            && self.to_clock.is_none_or(|clock| frame.clock <= clock)
+    }
+}
+
+/// Writes every message of the capture at `path` that `filter` lets through to `output`,
+/// one line each: when, at which local clock, which way, with whom, and the message.
+pub fn inspect(path: &Path, filter: &Filter, output: &mut impl Write) -> Result<()> {
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = match serde_json::from_str::<Frame<serde_json::Value>>(&line) {
+            Ok(frame) => frame,
+            // a node killed mid-write leaves its last line cut short
+            Err(error) => {
+                warn!(
+                    path = %path.display(),
+                    line = index + 1,
+                    %error,
+                    "SKIPPED CAPTURED LINE"
+                );
+                continue;
+            }
+        };
+        if !filter.matches(&frame) {
+            continue;
+        }
+
+        let (arrow, peer) = match frame.direction {
+            Direction::Sent => ("->", frame.peer.as_deref().unwrap_or("?")),
+            Direction::Received => ("<-", frame.peer.as_deref().unwrap_or("?")),
+        };
+        let clock = match frame.clock {
+            usize::MAX => "end".to_string(),
+            clock => clock.to_string(),
+        };
+        writeln!(
+            output,
+            "{} clock={clock} {arrow} {peer} {} {}",
+            frame.wall_time,
+            frame.kind,
+            payload(&frame.message)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The message without the tags of its kind, which the line already tells.
+fn payload(message: &serde_json::Value) -> String {
+    let untagged = |value: &serde_json::Value| {
+        value
+            .as_object()
+            .filter(|object| object.len() == 1)
+            .and_then(|object| object.values().next())
+            .cloned()
+    };
+    let mut payload = message.clone();
+    // events are tagged once, control messages twice
+    while let Some(inner) = untagged(&payload).filter(|inner| inner.is_object() || inner.is_array())
+    {
+        payload = inner;
+    }
+    payload.to_string()
+}
+
+fn kind(message: &WireMessage) -> String {
+    match message {
+        WireMessage::Active(_) => "Active".to_string(),
+        WireMessage::Passive(_) => "Passive".to_string(),
+        WireMessage::Batch(messages) => format!("Batch({})", messages.len()),
+        WireMessage::Control(control) => match control {
+            Control::ClockRequest(_) => "ClockRequest",
+            Control::GvtToken(_) => "GvtToken",
+            Control::DeadlockMarker(_) => "DeadlockMarker",
+            Control::BarrierReport(_) => "BarrierReport",
+            Control::BarrierGrant(_) => "BarrierGrant",
+            Control::SnapshotMarker(_) => "SnapshotMarker",
+            Control::Ready(_) => "Ready",
+            Control::Handoff(_) => "Handoff",
+            Control::TerminationMarker(_) => "TerminationMarker",
+        }
+        .to_string(),
+    }
+}
+
+/// Sender of a message, as far as the message tells.
+fn sender(message: &WireMessage) -> Option<&str> {
+    match message {
+        WireMessage::Active(event) => Some(&event.feeding_node),
+        WireMessage::Passive(event) => Some(&event.feeding_node),
+        WireMessage::Batch(messages) => messages.first().and_then(sender),
+        WireMessage::Control(control) => match control {
+            Control::ClockRequest(request) => Some(&request.requesting_node),
+            Control::BarrierReport(report) => Some(&report.reporting_node),
+            Control::SnapshotMarker(marker) => Some(&marker.sender),
+            Control::Ready(ready) => Some(&ready.ready_node),
+            Control::Handoff(handoff) => Some(&handoff.sender),
+            Control::GvtToken(_)
+            | Control::DeadlockMarker(_)
+            | Control::TerminationMarker(_)
+            | Control::BarrierGrant(_) => None,
+        },
+    }
+}
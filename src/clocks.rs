@@ -0,0 +1,74 @@
+use crate::model::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Vector clock keyed by node name, stamped on events to reconstruct causality from logs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorClock(BTreeMap<NodeId, usize>);
+
+impl VectorClock {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Local event at `node`, e.g. a send.
+    pub fn tick(&mut self, node: &NodeId) {
+        *self.0.entry(node.clone()).or_default() += 1;
+    }
+
+    /// Receipt at `node` of a message stamped with `other`.
+    pub fn merge(&mut self, other: &VectorClock, node: &NodeId) {
+        other.0.iter().for_each(|(name, &count)| {
+            let entry = self.0.entry(name.clone()).or_default();
+            *entry = (*entry).max(count);
+        });
+        self.tick(node);
+    }
+}
+
+impl From<BTreeMap<NodeId, usize>> for VectorClock {
+    fn from(value: BTreeMap<NodeId, usize>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<VectorClock> for BTreeMap<NodeId, usize> {
+    fn from(value: VectorClock) -> Self {
+        value.0
+    }
+}
+
+impl Display for VectorClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries = self
+            .0
+            .iter()
+            .map(|(node, count)| format!("{node}:{count}"))
+            .collect::<Vec<_>>();
+
+        write!(f, "[{}]", entries.join(","))
+    }
+}
+
+/// Lamport clock, stamped on every channel message so logs show a total order
+/// consistent with causality
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LamportClock(usize);
+
+impl LamportClock {
+    pub fn value(&self) -> usize {
+        self.0
+    }
+
+    /// Local event, e.g. a send. Returns the timestamp to stamp on it.
+    pub fn tick(&mut self) -> usize {
+        self.0 += 1;
+        self.0
+    }
+
+    /// Receipt of a message stamped with `timestamp`.
+    pub fn observe(&mut self, timestamp: usize) {
+        self.0 = self.0.max(timestamp) + 1;
+    }
+}
@@ -0,0 +1,634 @@
+use crate::address::NodeAddr;
+use crate::error::AppError;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// How nodes keep their clocks causally consistent
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronization {
+    /// Block until every feeding node guarantees no earlier event (null messages)
+    Conservative,
+    /// Advance freely, saving state every tick and rolling back on stragglers (Time Warp)
+    Optimistic,
+    /// Advance in lockstep to the global next event time chosen by the first node
+    Coordinated,
+    /// Block without null messages, detecting and breaking global deadlocks instead
+    DeadlockRecovery,
+    /// Optimistic, but never further than the time window ahead of GVT
+    TimeWindow,
+}
+
+/// How null messages (passive events) reach fed nodes
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullMessages {
+    /// Every tick, to every fed node not covered by an active event
+    Eager,
+    /// Only in reply to a clock request from a blocked fed node
+    OnDemand,
+}
+
+/// How the clock moves forward when no event is pending
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advance {
+    /// By a fixed step every tick
+    Step,
+    /// Straight to the next scheduled firing or feeding node clock
+    NextEvent,
+}
+
+/// What happens to an event received while the queue of its feeding node is full
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueFull {
+    /// Wait for the engine to take some, holding back every message received after it
+    Block,
+    /// Drop it and report it, for runs that would rather lose events than stall
+    Drop,
+}
+
+/// What happens to a message from a node this one does not expect it from, such as an
+/// event from a node that does not feed it
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownSenders {
+    /// Report and drop it, carrying on with the run
+    Permissive,
+    /// Fail the run, for clusters of a fixed membership where it can only mean a
+    /// misconfiguration
+    Strict,
+}
+
+/// How messages travel between nodes
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    /// One persistent connection per peer, every message acknowledged
+    Tcp,
+    /// One datagram per message, retransmitted only until the peer first answers,
+    /// or all along with --ack-timeout
+    Udp,
+    /// One QUIC connection per peer, encrypted with the --tls-* certificates
+    /// (requires the quic feature)
+    Quic,
+    /// One WebSocket per peer, for proxies that only let HTTP upgrades through; browser
+    /// dashboards may also subscribe to /events for every message received (requires the
+    /// websocket feature)
+    #[value(name = "websocket")]
+    WebSocket,
+    /// One ZeroMQ PUSH socket per peer into its PULL socket, relying on ZeroMQ to
+    /// reconnect rather than on acknowledgements (requires the zeromq feature)
+    #[value(name = "zeromq")]
+    ZeroMq,
+    /// Through an MQTT broker, one topic per channel, so nodes only need the broker's
+    /// address rather than each other's (requires the mqtt feature)
+    Mqtt,
+    /// One gRPC event stream per peer, plus a call per control message, following
+    /// proto/petri.proto so simulators in other languages can join (requires the grpc
+    /// feature)
+    Grpc,
+}
+
+/// How messages are encoded between nodes, told by the sender when it connects
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// One JSON object per line, readable and understood by every link
+    #[default]
+    Json,
+    /// bincode, each message prefixed with its length, cheaper to encode and parse
+    /// (--link tcp only)
+    Bincode,
+    /// MessagePack maps keyed by field name, each message prefixed with its length, as
+    /// spoken by other simulators and tooling (--link tcp only)
+    #[value(name = "msgpack")]
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// Protocol Buffers following the WireMessage of proto/petri.proto, each message
+    /// prefixed with its length, for implementations in other languages (--link tcp only,
+    /// requires the protobuf feature)
+    Protobuf,
+}
+
+// What to do with the nets instead of simulating them
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Write the nets in --nets-folder to standard output, each segment a page, for
+    /// graphical editors and analysis tools
+    Export {
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+    },
+    /// Draw the nets in --nets-folder as a graph of transitions and the ones their
+    /// instructions set, grouped and colored by the node hosting them
+    Graph {
+        /// File to write, standard output if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Nodes the segments are spread over, as for a simulation; by segment if omitted
+        #[arg(long, num_args = 1..)]
+        nodes: Vec<String>,
+
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+
+        /// Draw the nodes, each pointing to those it feeds, rather than the transitions
+        #[arg(long)]
+        topology: bool,
+    },
+    /// Report the structural metrics of the nets in --nets-folder, to tell whether spreading
+    /// them over the nodes is balanced before running them
+    Stats {
+        /// Nodes the segments are spread over, as for a simulation; by segment if omitted
+        #[arg(long, num_args = 1..)]
+        nodes: Vec<String>,
+    },
+    /// Write a synthetic net of any size to --nets-folder, one file per segment, for
+    /// benchmarks and stress runs
+    GenNet {
+        #[arg(long)]
+        transitions: usize,
+
+        /// Transitions every firing enables
+        #[arg(long, default_value_t = 2)]
+        fanout: usize,
+
+        /// Segments the transitions are spread over
+        #[arg(long, default_value_t = 1)]
+        subnets: usize,
+
+        /// Same seed, same net
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Print the messages a node captured with --capture, one per line, possibly only
+    /// some of them
+    InspectCapture {
+        /// <node>.capture written by the node
+        file: PathBuf,
+
+        #[arg(long, value_enum)]
+        direction: Option<Direction>,
+
+        /// Only messages to or from this node
+        #[arg(long)]
+        peer: Option<String>,
+
+        /// Only messages of these kinds, such as Active, Passive or GvtToken
+        #[arg(long, num_args = 1..)]
+        kind: Vec<String>,
+
+        /// Only messages sent or received from this local clock on
+        #[arg(long)]
+        from_clock: Option<usize>,
+
+        /// Only messages sent or received up to this local clock
+        #[arg(long)]
+        to_clock: Option<usize>,
+    },
+}
+
+/// Languages the nets can be drawn in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz, to render with dot
+    Dot,
+    /// Mermaid flowchart, to paste into Markdown
+    Mermaid,
+}
+
+/// Formats the nets can be exported to
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// PNML place/transition net, as read back from a .pnml file in --nets-folder
+    Pnml,
+    /// The ia_red JSON of every segment, one per line, each as read back from a .json
+    /// file in --nets-folder
+    Json,
+}
+
+/// Which way a captured message went
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Where the log of the simulation goes
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    /// <node>.log in the working directory
+    File,
+    /// Standard output
+    Stdout,
+}
+
+/// How much of the simulation is logged
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    /// Start, end and synchronization milestones
+    Info,
+    /// Also the net after every step of every tick, every firing and every message
+    Debug,
+    Trace,
+}
+
+/// How the log of the simulation is written
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One line per event, for people
+    Text,
+    /// One JSON object per line and event, with its clock, node, kind and payload, so the
+    /// logs of all nodes can be merged and sorted by clock
+    Json,
+}
+
+/// Reads a size such as 1048576, 512K, 100M or 2G.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => size.split_at(index),
+        None => (size, ""),
+    };
+    let unit = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("unknown unit {unit:?}, expected K, M or G")),
+    };
+    digits
+        .parse::<u64>()
+        .map(|digits| digits * unit)
+        .map_err(|error| error.to_string())
+}
+
+#[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
+pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Last simulation clock    
+    #[arg(long, required_unless_present_any = ["until_quiescent", "serve_registry"])]
+    pub terminal_clock: Option<usize>,
+
+    /// Stop once termination detection finds nothing left to fire anywhere in the cluster
+    /// and nothing in flight between nodes, instead of at a fixed clock
+    #[arg(long)]
+    pub until_quiescent: bool,
+
+    // Executing node ip:port address, [ipv6]:port for IPv6, host:port looked up on every
+    // connection, or Unix socket path for same-host clusters. The address peers dial,
+    // which names the node in routing and events
+    #[arg(
+        long,
+        visible_alias = "advertise-addr",
+        required_unless_present = "serve_registry",
+        default_value_t = String::new(),
+        hide_default_value = true
+    )]
+    pub node: String,
+
+    /// Address to listen on when it differs from the one peers dial, e.g. 0.0.0.0:5001
+    /// behind NAT or in a container
+    #[arg(long)]
+    pub bind_addr: Option<NodeAddr>,
+
+    // List of all addresses (or Unix socket paths) that will take part in the simulation
+    #[arg(long, num_args = 1..)]
+    pub nodes: Vec<String>,
+
+    /// host:port of the registry handing out the membership, instead of --nodes. The node
+    /// registers its address, where port 0 stands for any free one
+    #[arg(long, conflicts_with = "nodes")]
+    pub registry: Option<NodeAddr>,
+
+    /// Run as the registry on this address rather than as a node, until
+    /// --expected-nodes registered
+    #[arg(long, requires = "expected_nodes")]
+    pub serve_registry: Option<NodeAddr>,
+
+    /// Nodes the registry waits for before handing out the membership
+    #[arg(long, requires = "serve_registry")]
+    pub expected_nodes: Option<usize>,
+
+    /// Discover the other nodes over mDNS on the local network instead of --nodes, one
+    /// node per net, this one running the net at --net-index. --node must then be an
+    /// address on the network, not loopback, as only those are announced
+    #[arg(long, conflicts_with_all = ["nodes", "registry"], requires = "net_index")]
+    pub mdns: bool,
+
+    /// Position of the net this node runs among the nets in --nets-folder, by file name
+    #[arg(long, requires = "mdns")]
+    pub net_index: Option<usize>,
+
+    /// Whether --nodes is already in segment order, as discovered over mDNS, rather than
+    /// to be sorted
+    #[arg(skip)]
+    pub nodes_in_segment_order: bool,
+
+    /// Let the operator change the membership with --synchronization coordinated, typing
+    /// `join <node>` or `leave <node>` on the coordinator's standard input: the segments
+    /// are spread again over the new membership at the next barrier, one change at a time
+    #[arg(long)]
+    pub dynamic_membership: bool,
+
+    /// Join a running cluster with --dynamic-membership through its coordinator, rather
+    /// than start along with --nodes, once the operator admits this node within
+    /// --startup-timeout
+    #[arg(long, conflicts_with_all = ["nodes", "registry", "mdns"])]
+    pub join: Option<String>,
+
+    /// Clock units a tick advances by
+    #[arg(long, default_value_t = 1)]
+    pub step: usize,
+
+    /// How the clock advances when no event is pending
+    #[arg(long, value_enum, default_value_t = Advance::Step)]
+    pub advance: Advance,
+
+    /// Milliseconds of wall-clock time per clock unit, runs as fast as possible if omitted
+    #[arg(long)]
+    pub real_time_factor: Option<f64>,
+
+    /// Work out what the transitions firing in a tick do on every core when there are many
+    /// of them, with the same results as firing them one after another
+    #[arg(long)]
+    pub parallel_firing: bool,
+
+    /// Worker threads the net of the node is split over, each firing the transitions of a
+    /// block of it and telling the others what they set of theirs, with the same results
+    /// as a single one
+    #[arg(long, default_value_t = 1)]
+    pub shards: usize,
+
+    /// Seconds to wait for every node to join the cluster at startup
+    #[arg(long, default_value_t = 60)]
+    pub startup_timeout: u64,
+
+    /// Attempts at connecting to a peer before giving up on it
+    #[arg(long, default_value_t = 10)]
+    pub connect_attempts: usize,
+
+    /// Milliseconds before retrying a connection, doubled after every failed attempt
+    #[arg(long, default_value_t = 50)]
+    pub connect_base_delay: u64,
+
+    /// Fraction of each retry delay randomly added or removed
+    #[arg(long, default_value_t = 0.2)]
+    pub connect_jitter: f64,
+
+    /// Milliseconds after which connecting to a peer is given up, whatever the attempts left
+    #[arg(long, default_value_t = 5000)]
+    pub connect_deadline: u64,
+
+    /// Messages queued per peer before sending blocks the simulation
+    #[arg(long, default_value_t = 1024)]
+    pub send_queue: usize,
+
+    /// Events queued per feeding node before the listener stops reading, so an engine
+    /// that falls behind holds its peers back rather than running out of memory
+    #[arg(long, default_value_t = 65536)]
+    pub receive_queue: usize,
+
+    /// What the listener does with an event for a full receive queue
+    #[arg(long, value_enum, default_value_t = QueueFull::Block)]
+    pub receive_queue_full: QueueFull,
+
+    /// What the node does with events and clock requests from nodes outside its topology
+    #[arg(long, value_enum, default_value_t = UnknownSenders::Permissive)]
+    pub unknown_senders: UnknownSenders,
+
+    /// Send small events right away instead of batching them (Nagle's algorithm off)
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub tcp_nodelay: bool,
+
+    /// Seconds of idleness before, and between, TCP keepalive probes
+    #[arg(long)]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Accept IPv4 peers as well when listening on an IPv6 address such as [::]:5001,
+    /// rather than IPv6 ones only
+    #[arg(long)]
+    pub dual_stack: bool,
+
+    /// Milliseconds without data after which an incoming connection is dropped
+    #[arg(long)]
+    pub read_timeout: Option<u64>,
+
+    /// Milliseconds a write to a peer may block before reconnecting to it
+    #[arg(long)]
+    pub write_timeout: Option<u64>,
+
+    /// Milliseconds a message may go unacknowledged before the connection is reopened and
+    /// everything unacknowledged retransmitted, for lossy links (off by default)
+    #[arg(long)]
+    pub ack_timeout: Option<u64>,
+
+    /// Compress with zstd every message longer than this many bytes, such as large batches
+    #[arg(long)]
+    pub compress_above: Option<usize>,
+
+    /// How messages travel between nodes
+    #[arg(long, value_enum, default_value_t = Link::Tcp)]
+    pub link: Link,
+
+    /// How messages are encoded between nodes, the handshake of every connection staying
+    /// JSON
+    #[arg(long, value_enum, default_value_t = WireFormat::Json)]
+    pub wire_format: WireFormat,
+
+    /// host:port of the MQTT broker every node connects to with --link mqtt
+    #[arg(long, required_if_eq("link", "mqtt"))]
+    pub broker: Option<NodeAddr>,
+
+    /// Peers on this host whose channels, both ways, go through a shared-memory ring
+    /// buffer rather than the link; every such pair must list each other
+    #[arg(long, num_args = 1..)]
+    pub shared_memory: Vec<String>,
+
+    /// Folder of the shared-memory ring buffers, ideally on a tmpfs such as /dev/shm
+    #[arg(long, default_value_os_t = std::env::temp_dir())]
+    pub shared_memory_dir: PathBuf,
+
+    /// PEM certificate chain this node presents to its peers (requires the tls feature)
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM certificate of the CA that signed every node's certificate
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Secret shared by the cluster, which peers prove knowledge of before their events
+    /// are accepted
+    #[arg(long)]
+    pub cluster_secret: Option<String>,
+
+    /// Append an HMAC under the cluster secret to every message, dropping those that fail
+    /// to verify, for when TLS is not worth its cost
+    #[arg(long, requires = "cluster_secret")]
+    pub sign_messages: bool,
+
+    /// Identifier of this run, stamped on every log line and message so nodes of another
+    /// run are rejected. Generated by the first node and adopted by the others if omitted
+    #[arg(long, env = "PETRI_RUN_ID")]
+    pub run_id: Option<String>,
+
+    /// Synchronization protocol between nodes
+    #[arg(long, value_enum, default_value_t = Synchronization::Conservative)]
+    pub synchronization: Synchronization,
+
+    /// Milliseconds between Global Virtual Time rounds (optimistic synchronization)
+    #[arg(long, default_value_t = 10)]
+    pub gvt_interval: u64,
+
+    /// How far ahead of GVT nodes may run (time-window synchronization)
+    #[arg(long, default_value_t = 10)]
+    pub time_window: usize,
+
+    /// Milliseconds between deadlock detection passes (deadlock-recovery synchronization)
+    /// and between termination detection rounds (--until-quiescent)
+    #[arg(long, default_value_t = 10)]
+    pub detection_interval: u64,
+
+    /// Stop with an error on an event older than the local clock instead of rolling back
+    /// (optimistic synchronization) or applying it late (every other synchronization)
+    #[arg(long)]
+    pub abort_on_straggler: bool,
+
+    /// Seconds a node may wait on a feeding node before logging its clock, the clocks of
+    /// its channels and its pending events, then again every as many seconds it waits
+    #[arg(long)]
+    pub stall_timeout: Option<u64>,
+
+    /// Stop with an error once --stall-timeout elapses instead of only logging
+    #[arg(long, requires = "stall_timeout")]
+    pub abort_on_stall: bool,
+
+    /// Seconds a node waits on a feeding node before failing the run, taking it for dead,
+    /// instead of waiting for as long as it takes
+    #[arg(long)]
+    pub receive_timeout: Option<u64>,
+
+    /// Append the events of every tick, and the clock they lead to, to <node>.wal before
+    /// applying them (conservative and deadlock-recovery synchronization)
+    #[arg(long)]
+    pub write_ahead_log: bool,
+
+    /// Replay <node>.wal left by a previous run of this node before carrying on, to reach
+    /// the state it had when it stopped without asking its peers, then rejoin the peers
+    /// still running, which resend from their own --write-ahead-log what it lost
+    /// (conservative synchronization with eager null messages over tcp, quic or websocket)
+    #[arg(long, requires = "write_ahead_log")]
+    pub recover: bool,
+
+    /// When to send null messages to fed nodes
+    #[arg(long, value_enum, default_value_t = NullMessages::Eager)]
+    pub null_messages: NullMessages,
+
+    /// Clock at which this node initiates a global snapshot of the cluster
+    #[arg(long)]
+    pub snapshot_at: Option<usize>,
+
+    /// Clocks at which this node writes its segments, with their values and clocks then,
+    /// as an ia_red JSON net to feed back into the reference simulator; events in flight
+    /// are not part of that format
+    #[arg(long, num_args = 1..)]
+    pub dump_net_at: Vec<usize>,
+
+    /// Where the log of the simulation goes
+    #[arg(long, value_enum, default_value_t = LogTarget::File)]
+    pub log_target: LogTarget,
+
+    /// How the log of the simulation is written
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Most detailed events of the simulation logged
+    #[arg(long, value_enum, env = "PETRI_LOG_LEVEL", default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+
+    /// RUST_LOG directives refining --log-level per component: petri::net for the net
+    /// after every step, petri::fire for firings, petri::network for messages, e.g.
+    /// petri::fire=debug or petri=debug,petri::net=off
+    #[arg(long, env = "PETRI_LOG")]
+    pub log_filter: Option<String>,
+
+    /// Size past which <node>.log is rotated to <node>.log.1, in bytes or with a K, M or G
+    /// suffix
+    #[arg(long, value_parser = parse_size)]
+    pub log_rotate_size: Option<u64>,
+
+    /// Seconds after which <node>.log is rotated to <node>.log.1
+    #[arg(long)]
+    pub log_rotate_secs: Option<u64>,
+
+    /// Rotated logs kept, <node>.log.1 being the newest; older ones are deleted
+    #[arg(long, default_value_t = 5)]
+    pub log_keep: usize,
+
+    /// Write the firings of every transition of this node, when it first and last fired
+    /// and a histogram of the clock units between its firings to <node>.firings.json at
+    /// the end of the run
+    #[arg(long)]
+    pub firing_stats: bool,
+
+    /// Write every message sent and received, with its peer, wall time and local clock,
+    /// to <node>.capture, for `petri inspect-capture`
+    #[arg(long)]
+    pub capture: bool,
+
+    /// Seconds between reports on standard error of the clock reached, firings per second
+    /// and time left until the terminal clock
+    #[arg(long)]
+    pub progress: Option<f64>,
+
+    /// Show the clocks of this node and of its feeding nodes, its pending events and the
+    /// latest of its log in a terminal dashboard, q quitting once the run is over
+    /// (requires the tui feature)
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Address to serve a web page animating the marking and clocks of this node on,
+    /// such as 0.0.0.0:8080; pages of several nodes show side by side with
+    /// ?peers=host:port,... (requires the web feature)
+    #[arg(long)]
+    pub web: Option<String>,
+
+    /// OTLP/HTTP collector receiving a trace of every token's path through the nodes,
+    /// such as http://localhost:4318 for Jaeger
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Folder with .json Petri nets, or the same in .yaml or .toml, .petri text ones, or
+    /// .pnml ones whose top-level pages are segments; required to run a node and by the
+    /// commands that read or write nets
+    #[arg(long, alias = "nets-dir", global = true)]
+    pub nets_folder: Option<PathBuf>,
+}
+
+impl Config {
+    /// Whether what was asked for reads or writes nets, which only serving the registry
+    /// and inspecting a capture do not.
+    pub fn needs_nets(&self) -> bool {
+        self.serve_registry.is_none()
+            && !matches!(self.command, Some(Command::InspectCapture { .. }))
+    }
+
+    /// --nets-folder, failing when it was not given.
+    pub fn nets_folder(&self) -> Result<&Path, AppError> {
+        self.nets_folder.as_deref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--nets-folder is required",
+            )
+            .into()
+        })
+    }
+}
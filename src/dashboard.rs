@@ -0,0 +1,226 @@
+use crate::config::Config;
+use crate::error::Result;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "tui")]
+use crate::config::LogTarget;
+#[cfg(feature = "tui")]
+use crate::logging;
+#[cfg(feature = "tui")]
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+#[cfg(feature = "tui")]
+use ratatui::layout::{Constraint, Layout};
+#[cfg(feature = "tui")]
+use ratatui::text::Line;
+#[cfg(feature = "tui")]
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+#[cfg(feature = "tui")]
+use ratatui::{DefaultTerminal, Frame};
+#[cfg(feature = "tui")]
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "tui")]
+use std::time::Duration;
+
+/// What the dashboards show of a node, as of its latest tick
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    pub node: String,
+    pub synchronization: String,
+    pub clock: usize,
+    pub terminal_clock: usize,
+    /// GVT, under optimistic synchronization
+    pub gvt: Option<usize>,
+    /// Clock of every feeding node, with the events consumed from it
+    pub feeding_nodes: Vec<(String, usize, usize)>,
+    /// Messages sent to every fed node
+    pub fed_nodes: Vec<(String, usize)>,
+    /// Clock and transition of every pending event, earliest first
+    pub pending: Vec<(usize, usize)>,
+    /// Id, value and clock of every transition of the node
+    pub marking: Vec<(usize, isize, usize)>,
+    pub finished: bool,
+}
+
+/// Draws the status of the node in the terminal from a thread of its own, until dropped
+/// once the run is over and the operator quits.
+pub struct Dashboard {
+    status: Arc<Mutex<Status>>,
+    #[cfg(feature = "tui")]
+    drawer: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    #[cfg(not(feature = "tui"))]
+    pub fn start(_config: &Config) -> Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "built without dashboard support, rebuild with --features tui",
+        )
+        .into())
+    }
+
+    #[cfg(feature = "tui")]
+    pub fn start(config: &Config) -> Result<Self> {
+        if config.log_target == LogTarget::Stdout {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--tui takes over the terminal, --log-target stdout cannot go with it",
+            )
+            .into());
+        }
+
+        let status = Arc::new(Mutex::new(Status {
+            node: config.node.clone(),
+            terminal_clock: config.terminal_clock.unwrap_or(usize::MAX),
+            ..Status::default()
+        }));
+        let terminal = ratatui::try_init()?;
+        let shown = status.clone();
+        let drawer = thread::spawn(move || draw_until_quit(terminal, &shown));
+
+        Ok(Self {
+            status,
+            drawer: Some(drawer),
+        })
+    }
+
+    pub fn update(&self, status: Status) {
+        *self.status.lock().unwrap() = status;
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.status.lock().unwrap().finished = true;
+        #[cfg(feature = "tui")]
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+            ratatui::restore();
+        }
+    }
+}
+
+/// Redraws ten times a second until q, Esc or Ctrl-C. Quitting before the run is over
+/// ends the node right away.
+#[cfg(feature = "tui")]
+fn draw_until_quit(mut terminal: DefaultTerminal, status: &Mutex<Status>) {
+    loop {
+        let shown = status.lock().unwrap().clone();
+        if terminal.draw(|frame| draw(frame, &shown)).is_err() {
+            return;
+        }
+
+        let Ok(true) = event::poll(Duration::from_millis(100)) else {
+            continue;
+        };
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        let quit = key.kind == KeyEventKind::Press
+            && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                || (key.code == KeyCode::Char('c') && key.modifiers == KeyModifiers::CONTROL));
+        if quit {
+            if shown.finished {
+                return;
+            }
+            ratatui::restore();
+            logging::flush();
+            std::process::exit(130);
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn draw(frame: &mut Frame, status: &Status) {
+    let [progress, queues, log] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Percentage(40),
+        Constraint::Min(5),
+    ])
+    .areas(frame.area());
+    let [feeding, fed, pending] = Layout::horizontal([Constraint::Ratio(1, 3); 3]).areas(queues);
+
+    let mut label = match status.terminal_clock {
+        usize::MAX => format!("clock {}", status.clock),
+        terminal_clock => format!("clock {} / {terminal_clock}", status.clock),
+    };
+    if let Some(gvt) = status.gvt {
+        label = format!("{label}  gvt {gvt}");
+    }
+    let state = if status.finished {
+        "finished, q to quit"
+    } else {
+        "running, q to stop"
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(format!(
+                " {} ({}) {state} ",
+                status.node, status.synchronization
+            )))
+            .ratio(if status.finished {
+                1.0
+            } else {
+                (status.clock as f64 / status.terminal_clock.max(1) as f64).clamp(0.0, 1.0)
+            })
+            .label(label),
+        progress,
+    );
+
+    frame.render_widget(
+        Table::new(
+            status.feeding_nodes.iter().map(|(node, clock, consumed)| {
+                Row::new([node.clone(), clock.to_string(), consumed.to_string()])
+            }),
+            [
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["node", "clock", "received"]))
+        .block(Block::bordered().title(" feeding nodes ")),
+        feeding,
+    );
+    frame.render_widget(
+        Table::new(
+            status
+                .fed_nodes
+                .iter()
+                .map(|(node, sent)| Row::new([node.clone(), sent.to_string()])),
+            [Constraint::Fill(1), Constraint::Length(8)],
+        )
+        .header(Row::new(["node", "sent"]))
+        .block(Block::bordered().title(" fed nodes ")),
+        fed,
+    );
+    frame.render_widget(
+        Table::new(
+            status
+                .pending
+                .iter()
+                .map(|(clock, transition)| Row::new([clock.to_string(), format!("t{transition}")])),
+            [Constraint::Length(8), Constraint::Fill(1)],
+        )
+        .header(Row::new(["clock", "transition"]))
+        .block(Block::bordered().title(format!(" {} pending events ", status.pending.len()))),
+        pending,
+    );
+
+    let lines = logging::tail();
+    let shown = lines
+        .len()
+        .saturating_sub(log.height.saturating_sub(2) as usize);
+    frame.render_widget(
+        Paragraph::new(
+            lines[shown..]
+                .iter()
+                .cloned()
+                .map(Line::from)
+                .collect::<Vec<_>>(),
+        )
+        .block(Block::bordered().title(" log ")),
+        log,
+    );
+}
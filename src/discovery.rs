@@ -0,0 +1,151 @@
+use crate::config::Config;
+use crate::error::Result;
+
+#[cfg(feature = "mdns")]
+use crate::error::AppError;
+#[cfg(feature = "mdns")]
+use crate::logging;
+#[cfg(feature = "mdns")]
+use crate::transport::{is_compatible, PROTOCOL_VERSION};
+#[cfg(feature = "mdns")]
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+#[cfg(feature = "mdns")]
+use std::net::ToSocketAddrs;
+#[cfg(feature = "mdns")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "mdns")]
+use tracing::warn;
+
+/// Service every node announces over mDNS, with its protocol version, net index and
+/// the address peers are to dial as TXT properties
+#[cfg(feature = "mdns")]
+const SERVICE_TYPE: &str = "_petri._tcp.local.";
+
+/// Keeps this node announced over mDNS until dropped.
+pub struct Announcement {
+    #[cfg(feature = "mdns")]
+    daemon: ServiceDaemon,
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(not(feature = "mdns"))]
+pub fn join(_config: Config) -> Result<(Config, Announcement)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "built without mDNS support, rebuild with --features mdns",
+    )
+    .into())
+}
+
+/// Announces this node over mDNS, tagged with the net it runs, then listens to the
+/// announcements of the others until every net in the folder has a node. Returns the
+/// config with the membership filled in, ordered by net.
+#[cfg(feature = "mdns")]
+pub fn join(mut config: Config) -> Result<(Config, Announcement)> {
+    let nets_folder = config.nets_folder()?.to_path_buf();
+    let expected = crate::engine::load_nets(&nets_folder)?.len();
+    let index = config.net_index.unwrap_or_default();
+    if index >= expected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "--net-index {index} out of range, {expected} nets in {}",
+                nets_folder.display()
+            ),
+        )
+        .into());
+    }
+    crate::registry::settle_port(&mut config)?;
+
+    let address = config.node.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::AddrNotAvailable,
+            format!("{} resolves to no address", config.node),
+        )
+    })?;
+    let instance = format!("petri-{index}");
+    let (version, net) = (PROTOCOL_VERSION.to_string(), index.to_string());
+    let properties = [
+        ("version", version.as_str()),
+        ("net", net.as_str()),
+        ("node", config.node.as_str()),
+    ];
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &format!("{instance}.local."),
+        address.ip(),
+        address.port(),
+        &properties[..],
+    )
+    .map_err(std::io::Error::other)?;
+
+    let daemon = ServiceDaemon::new().map_err(std::io::Error::other)?;
+    let events = daemon.browse(SERVICE_TYPE).map_err(std::io::Error::other)?;
+    daemon.register(service).map_err(std::io::Error::other)?;
+    let announcement = Announcement { daemon };
+
+    let mut nodes = vec![None; expected];
+    nodes[index] = Some(config.node.clone());
+    let deadline = Instant::now() + Duration::from_secs(config.startup_timeout);
+    while nodes.iter().any(Option::is_none) {
+        let Ok(event) = events.recv_deadline(deadline) else {
+            let missing = (0..expected)
+                .filter(|net| nodes[*net].is_none())
+                .collect::<Vec<_>>();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no node announced itself over mDNS for nets {missing:?}"),
+            )
+            .into());
+        };
+        let ServiceEvent::ServiceResolved(service) = event else {
+            continue;
+        };
+        let (Some(version), Some(net), Some(node)) = (
+            service.get_property_val_str("version"),
+            service.get_property_val_str("net"),
+            service.get_property_val_str("node"),
+        ) else {
+            continue;
+        };
+        let version = version.parse().unwrap_or_default();
+        if !is_compatible(version) {
+            return Err(AppError::ProtocolMismatch {
+                node: node.to_string(),
+                version,
+            });
+        }
+        match net.parse::<usize>() {
+            Ok(net) if net == index && node != config.node => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("net {net} is run by {node} already"),
+                )
+                .into());
+            }
+            Ok(net) if net == index => {}
+            // a node announcing itself again, e.g. once restarted, replaces its previous
+            // announcement
+            Ok(net) if net < expected => nodes[net] = Some(node.to_string()),
+            _ => warn!(
+                target: logging::NETWORK,
+                %node,
+                net,
+                reason = "announced for another net",
+                "IGNORED ANNOUNCEMENT"
+            ),
+        }
+    }
+
+    config.nodes = nodes.into_iter().flatten().collect();
+    config.nodes_in_segment_order = true;
+
+    Ok((config, announcement))
+}
@@ -0,0 +1,163 @@
+use crate::engine::assign_segments;
+use crate::model::{Instruction, Net, Transition};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Fill colors of the nodes' transitions, reused past the last one
+pub const COLORS: [&str; 8] = [
+    "lightblue",
+    "palegreen",
+    "lightyellow",
+    "lightpink",
+    "plum",
+    "lightsalmon",
+    "lightcyan",
+    "khaki",
+];
+
+/// Segments spread over nodes as a simulation would, or standing alone without nodes
+pub struct Partition {
+    /// Name of every owner, node or segment
+    pub names: Vec<String>,
+    /// Owner of every segment
+    owners: Vec<usize>,
+    /// Owner of every transition
+    owner_of: HashMap<usize, usize>,
+}
+
+impl Partition {
+    pub fn new(nets: &[Net], nodes: &[String]) -> Self {
+        let mut nodes = nodes.to_vec();
+        nodes.sort();
+        nodes.dedup();
+        let (owners, names) = if nodes.is_empty() {
+            let names = (0..nets.len()).map(|segment| format!("segment {segment}"));
+            ((0..nets.len()).collect(), names.collect())
+        } else {
+            (assign_segments(nets.len(), nodes.len()), nodes)
+        };
+        let owner_of = nets
+            .iter()
+            .zip(&owners)
+            .flat_map(|(net, &owner)| net.transitions.iter().map(move |t| (t.id, owner)))
+            .collect();
+
+        Self {
+            names,
+            owners,
+            owner_of,
+        }
+    }
+
+    /// Transitions hosted by `owner`.
+    pub fn transitions_of<'a>(
+        &'a self,
+        nets: &'a [Net],
+        owner: usize,
+    ) -> impl Iterator<Item = &'a Transition> {
+        nets.iter()
+            .zip(&self.owners)
+            .filter(move |(_, &segment_owner)| segment_owner == owner)
+            .flat_map(|(net, _)| &net.transitions)
+    }
+
+    /// Owner of `segment`.
+    pub fn owner(&self, segment: usize) -> usize {
+        self.owners[segment]
+    }
+
+    /// Owner of the transition `id`, if any segment defines it.
+    pub fn owner_of(&self, id: usize) -> Option<usize> {
+        self.owner_of.get(&id).copied()
+    }
+
+    /// Whether `instruction` of `transition` sets a transition of another owner.
+    pub fn crosses(&self, transition: &Transition, instruction: &Instruction) -> bool {
+        self.owner_of.get(&instruction.transition_id) != self.owner_of.get(&transition.id)
+    }
+
+    /// Instructions from every owner to every other, by feeding and fed owner.
+    pub fn feeds(&self, nets: &[Net]) -> BTreeMap<(usize, usize), usize> {
+        let mut feeds = BTreeMap::new();
+        for transition in nets.iter().flat_map(|net| &net.transitions) {
+            for (instruction, _) in edges(transition) {
+                if let (Some(&from), Some(&to)) = (
+                    self.owner_of.get(&transition.id),
+                    self.owner_of.get(&instruction.transition_id),
+                ) {
+                    if from != to {
+                        *feeds.entry((from, to)).or_default() += 1;
+                    }
+                }
+            }
+        }
+        feeds
+    }
+}
+
+/// Instructions of `transition`, each with whether it is immediate.
+pub fn edges(transition: &Transition) -> impl Iterator<Item = (&Instruction, bool)> {
+    transition
+        .immediate_instructions
+        .iter()
+        .map(|instruction| (instruction, true))
+        .chain(
+            transition
+                .delayed_instructions
+                .iter()
+                .map(|instruction| (instruction, false)),
+        )
+}
+
+/// Writes `nets` as a Graphviz digraph, every transition pointing to those its
+/// instructions set: solid for delayed instructions, dashed for immediate ones, and red
+/// when crossing from one node to another, so the cut between nodes stands out. With
+/// `topology`, draws the nodes instead, each pointing to those it feeds.
+pub fn write(nets: &[Net], partition: &Partition, topology: bool) -> String {
+    let mut graph = String::from("digraph petri {\n");
+    graph.push_str("  node [shape=box, style=filled];\n");
+    if topology {
+        for (owner, name) in partition.names.iter().enumerate() {
+            let color = COLORS[owner % COLORS.len()];
+            let _ = writeln!(graph, "  n{owner} [label={name:?}, fillcolor={color}];");
+        }
+        for ((from, to), instructions) in partition.feeds(nets) {
+            let _ = writeln!(graph, "  n{from} -> n{to} [label=\"{instructions}\"];");
+        }
+        graph.push_str("}\n");
+        return graph;
+    }
+
+    for (owner, name) in partition.names.iter().enumerate() {
+        let color = COLORS[owner % COLORS.len()];
+        let _ = writeln!(graph, "  subgraph cluster_{owner} {{");
+        let _ = writeln!(graph, "    label={name:?};");
+        for transition in partition.transitions_of(nets, owner) {
+            let _ = writeln!(
+                graph,
+                "    t{} [label=\"t{}\\nvalue={} dur={}\", fillcolor={color}];",
+                transition.id, transition.id, transition.value, transition.duration
+            );
+        }
+        graph.push_str("  }\n");
+    }
+
+    for transition in nets.iter().flat_map(|net| &net.transitions) {
+        for (instruction, immediate) in edges(transition) {
+            let style = if immediate { "dashed" } else { "solid" };
+            let color = if partition.crosses(transition, instruction) {
+                "red"
+            } else {
+                "black"
+            };
+            let _ = writeln!(
+                graph,
+                "  t{} -> t{} [label=\"{}\", style={style}, color={color}];",
+                transition.id, instruction.transition_id, instruction.value
+            );
+        }
+    }
+    graph.push_str("}\n");
+
+    graph
+}
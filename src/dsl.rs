@@ -0,0 +1,220 @@
+use crate::json::{Net, Transition};
+use std::collections::HashMap;
+
+/// Firing duration of a transition without a `dur` attribute
+const DEFAULT_DURATION: usize = 1;
+
+/// Parses a net written one transition per line, `#` starting a comment:
+///
+/// ```text
+/// t3 [value=1, dur=2] => t3(1) -> t5(0), @other:t7(0)
+/// ```
+///
+/// The optional attributes are `value`, `clock`, `dur`, `reward`, `cost` and the flag
+/// `output`. Every `=>` is followed by the immediate instructions and every `->` by the
+/// delayed ones, each setting a transition to a value. `@` marks a transition of another
+/// segment, optionally naming it as `@segment:t7` for whoever reads the net.
+/// Errors come with the line and column at fault, both counted from 1.
+pub fn parse(text: &str) -> Result<Net, (usize, usize, String)> {
+    let mut ia_red = Vec::new();
+    let mut defined_on = HashMap::new();
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        // every error points into the line, so where it points tells the column
+        let column = |at: &str| {
+            raw[..at.as_ptr() as usize - raw.as_ptr() as usize]
+                .chars()
+                .count()
+                + 1
+        };
+        let transition =
+            transition(line).map_err(|(at, reason)| (index + 1, column(at), reason))?;
+        if let Some(first) = defined_on.insert(transition.ii_idglobal, index + 1) {
+            return Err((
+                index + 1,
+                column(line),
+                format!(
+                    "t{} is defined on line {first} already",
+                    transition.ii_idglobal
+                ),
+            ));
+        }
+        ia_red.push(transition);
+    }
+
+    Ok(Net { ia_red })
+}
+
+/// Text at fault in the line being parsed, and why
+type Error<'a> = (&'a str, String);
+
+fn transition(line: &str) -> Result<Transition, Error<'_>> {
+    let (head, mut clauses) = line.split_at(next_arrow(line).unwrap_or(line.len()));
+    let (name, attributes) = match head.split_once('[') {
+        Some((name, attributes)) => (
+            name,
+            attributes
+                .trim_end()
+                .strip_suffix(']')
+                .ok_or((attributes, "attributes without a closing ]".to_string()))?,
+        ),
+        None => (head, ""),
+    };
+
+    let mut transition = Transition {
+        ii_idglobal: transition_id(name.trim())?,
+        ii_valor: 0,
+        ii_tiempo: 0,
+        ii_duracion_disparo: DEFAULT_DURATION,
+        ii_listactes_iul: Vec::new(),
+        ii_listactes_pul: Vec::new(),
+        ib_desalida: false,
+        reward_rate: 0.0,
+        firing_cost: 0.0,
+    };
+    for attribute in attributes
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+    {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (attribute, None),
+        };
+        match (key, value) {
+            ("value", Some(value)) => transition.ii_valor = number(value)?,
+            ("clock", Some(value)) => transition.ii_tiempo = number(value)?,
+            ("dur", Some(value)) => transition.ii_duracion_disparo = number(value)?,
+            ("reward", Some(value)) => transition.reward_rate = number(value)?,
+            ("cost", Some(value)) => transition.firing_cost = number(value)?,
+            ("output", None) => transition.ib_desalida = true,
+            _ => return Err((attribute, format!("unknown attribute {attribute}"))),
+        }
+    }
+
+    while !clauses.is_empty() {
+        let (arrow, rest) = clauses.split_at(2);
+        let (targets, rest) = rest.split_at(next_arrow(rest).unwrap_or(rest.len()));
+        let instructions = targets
+            .split(',')
+            .map(instruction)
+            .collect::<Result<Vec<_>, _>>()?;
+        if arrow == "=>" {
+            transition.ii_listactes_iul.extend(instructions);
+        } else {
+            transition.ii_listactes_pul.extend(instructions);
+        }
+        clauses = rest;
+    }
+
+    Ok(transition)
+}
+
+/// Position of the first `=>` or `->` in `text`.
+fn next_arrow(text: &str) -> Option<usize> {
+    [text.find("=>"), text.find("->")]
+        .into_iter()
+        .flatten()
+        .min()
+}
+
+/// An instruction such as `t5(0)`, or `@other:t7(0)` for a transition of another segment,
+/// as the `(id, value)` of the JSON format, whose negative ids are external.
+fn instruction(target: &str) -> Result<(isize, isize), Error<'_>> {
+    let target = target.trim();
+    let (name, value) = target
+        .strip_suffix(')')
+        .and_then(|target| target.split_once('('))
+        .ok_or_else(|| (target, format!("{target:?} is not a transition(value)")))?;
+    let (is_external, name) = match name.trim().strip_prefix('@') {
+        Some(name) => (true, name.rsplit(':').next().unwrap_or(name)),
+        None => (false, name.trim()),
+    };
+    let id = transition_id(name.trim())? as isize;
+
+    Ok((
+        if is_external { -(id + 1) } else { id },
+        number(value.trim())?,
+    ))
+}
+
+fn transition_id(name: &str) -> Result<usize, Error<'_>> {
+    name.strip_prefix('t')
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| (name, format!("{name:?} is not a transition such as t3")))
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Result<T, Error<'_>> {
+    text.parse()
+        .map_err(|_| (text, format!("{text:?} is not a number")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::model;
+    use serde_json::json;
+
+    #[test]
+    fn converts_a_small_net_to_legacy_json_and_back() {
+        let text = "\
+# a token going round two transitions, one handing it to another segment
+t0 [value=1, dur=2, reward=0.5] => t0(1) -> t1(-1), @other:t7(1)
+t1 [clock=3, output] -> t0(-1)
+";
+        let net = parse(text).expect("Failed to parse the net");
+        let legacy = serde_json::to_value(&net).unwrap();
+        assert_eq!(
+            legacy["ia_red"],
+            json!([
+                {
+                    "ii_idglobal": 0,
+                    "ii_valor": 1,
+                    "ii_tiempo": 0,
+                    "ii_duracion_disparo": 2,
+                    "ii_listactes_IUL": [[0, 1]],
+                    "ii_listactes_PUL": [[1, -1], [-8, 1]],
+                    "ib_desalida": false,
+                    "reward_rate": 0.5,
+                    "firing_cost": 0.0
+                },
+                {
+                    "ii_idglobal": 1,
+                    "ii_valor": 0,
+                    "ii_tiempo": 3,
+                    "ii_duracion_disparo": 1,
+                    "ii_listactes_IUL": [],
+                    "ii_listactes_PUL": [[0, -1]],
+                    "ib_desalida": true,
+                    "reward_rate": 0.0,
+                    "firing_cost": 0.0
+                }
+            ])
+        );
+
+        let back = crate::json::Net::from(&model::Net::from(net));
+        assert_eq!(
+            serde_json::to_value(&back).unwrap()["ia_red"],
+            legacy["ia_red"]
+        );
+    }
+
+    #[test]
+    fn points_at_a_malformed_instruction() {
+        let text = "t0 -> t1(0)\n\n  t1 => t0(1), t2 -> t0(0)\n";
+        let (line, column, reason) = parse(text).expect_err("Parsed a malformed instruction");
+        assert_eq!((line, column), (3, 16), "{reason}");
+        assert!(reason.contains("\"t2\""), "{reason}");
+    }
+
+    #[test]
+    fn rejects_a_transition_defined_twice() {
+        let text = "t0 -> t1(0)\nt1 -> t0(0)\n t0 [dur=2]\n";
+        let (line, column, reason) = parse(text).expect_err("Parsed a transition defined twice");
+        assert_eq!((line, column), (3, 2), "{reason}");
+        assert!(reason.contains("line 1"), "{reason}");
+    }
+}
@@ -1,369 +1,1985 @@
-use crate::error::Result;
-use crate::model::{ActiveEvent, FeedingNode, GenericEvent, Net, PassiveEvent, Transition};
-use chrono::Local;
+mod coordinated;
+mod deadlock;
+mod gvt;
+mod membership;
+mod optimistic;
+mod parallel;
+mod progress;
+mod queue;
+mod shards;
+mod snapshot;
+mod summary;
+mod termination;
+#[cfg(test)]
+mod testing;
+mod timing;
+mod traffic;
+mod wal;
+
+use crate::capture::Capture;
+use crate::clocks::{LamportClock, VectorClock};
+use crate::config::{
+    Advance, Config, Link, NullMessages, QueueFull, Synchronization, UnknownSenders,
+};
+use crate::dashboard::{Dashboard, Status};
+use crate::error::{AppError, Result};
+use crate::firings::FiringStats;
+use crate::logging;
+use crate::model::{
+    invalid, ActiveEvent, BarrierGrant, BarrierReport, ClockRequest, Control, DeadlockMarker,
+    FeedingClocks, FeedingNode, GvtToken, Handoff, Net, NodeId, PassiveEvent, Ready, ReorderBuffer,
+    Reordered, SegmentIndex, SnapshotMarker, TerminationMarker, Transition, WireMessage,
+};
+use crate::otel::Tracer;
+use crate::retry::RetryPolicy;
+use crate::reward::Rewards;
+use crate::transport::{Listener, Outbox, Transport};
+use crate::web::WebDashboard;
+use coordinated::Barrier;
+use deadlock::Deadlock;
 use glob::glob;
-use std::collections::HashMap;
-use std::fs::File;
+use gvt::Gvt;
+use membership::Membership;
+use optimistic::TimeWarp;
+use progress::Progress;
+use queue::EventQueue;
+use snapshot::Snapshots;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::{TcpListener, TcpStream};
-use std::path::Path;
-use std::sync::mpsc::channel;
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError,
+    TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use termination::Termination;
+use timing::Timing;
+use tracing::{debug, field, info, info_span, warn};
+use traffic::Traffic;
+use uuid::Uuid;
+use wal::WriteAheadLog;
+
+/// How often peers are checked on while waiting for the cluster to start
+const STARTUP_POLL: Duration = Duration::from_millis(100);
+
+/// Pending events a stalled node logs, the earliest ones, as there may be any number
+const STALL_PENDING_SHOWN: usize = 32;
+
+/// How long a node blocked on a feeding node waits before checking on the wait
+const RECEIVE_POLL: Duration = Duration::from_millis(100);
+
+/// Transitions firing in a tick below which they are fired one after another whatever
+/// --parallel-firing and --shards say, spreading so few over threads costing more than it
+/// saves
+const PARALLEL_FIRING_MIN: usize = 256;
 
 pub struct Engine {
     clock: usize,
     step: usize,
-    node: String,
+    advance: Advance,
+    real_time_factor: Option<f64>,
+    started: Instant,
+    node: NodeId,
+    nodes: Vec<String>,
+    /// Every segment, as loaded, to spread again whenever the membership changes
+    segments: Vec<Segment>,
     net: Net,
     terminal_clock: usize,
-    fed_nodes: Vec<String>,
+    until_quiescent: bool,
+    fed_nodes: Vec<NodeId>,
+    lookahead: HashMap<NodeId, usize>,
     feeding_nodes: Vec<FeedingNode>,
-    transition2node: HashMap<usize, String>,
-    internal_active_events: Vec<ActiveEvent>,
+    feeding_clocks: FeedingClocks,
+    null_messages: NullMessages,
+    unknown_senders: UnknownSenders,
+    synchronization: Synchronization,
+    time_window: usize,
+    abort_on_straggler: bool,
+    stall_timeout: Option<Duration>,
+    abort_on_stall: bool,
+    receive_timeout: Option<Duration>,
+    time_warp: TimeWarp,
+    gvt: Gvt,
+    progress: Progress,
+    traffic: Traffic,
+    timing: Timing,
+    gvt_tokens: Receiver<GvtToken>,
+    barrier: Barrier,
+    deadlock: Deadlock,
+    deadlock_markers: Receiver<DeadlockMarker>,
+    termination: Termination,
+    termination_markers: Receiver<TerminationMarker>,
+    barrier_reports: Receiver<BarrierReport>,
+    barrier_grants: Receiver<BarrierGrant>,
+    clock_requests: Receiver<ClockRequest>,
+    snapshots: Snapshots,
+    /// Clocks at which to dump the net, earliest first
+    dump_net_at: Vec<usize>,
+    snapshot_markers: Receiver<SnapshotMarker>,
+    readies: Receiver<Ready>,
+    /// `Ready` messages of peers that recovered from a crash, and their answers to this
+    /// node's own recovery, served while the run goes on
+    recoveries: Receiver<Ready>,
+    membership: Membership,
+    handoffs: Receiver<Handoff>,
+    feeding_node2channel: Channels,
+    /// Events each feeding node's channel holds
+    receive_queue: usize,
+    startup_timeout: Duration,
+    retry_policy: RetryPolicy,
+    transport: Transport,
+    transition2node: HashMap<usize, NodeId>,
+    internal_active_events: EventQueue,
+    /// Positions of the transitions firing this tick, kept to be reused
+    enabled: Vec<usize>,
+    parallel_firing: bool,
+    /// Worker threads a tick's firings are split over, by block of the net
+    shards: usize,
     external_active_events: Vec<ActiveEvent>,
-    pub listener: JoinHandle<Result<()>>,
-    log_file: BufWriter<File>,
+    /// Messages taken off the feeding channels this tick, kept to reuse its allocation
+    inbox: Vec<WireMessage>,
+    event_seq: usize,
+    channel_seqs: HashMap<NodeId, usize>,
+    vector_clock: VectorClock,
+    lamport: LamportClock,
+    lamport_violations: usize,
+    outboxes: HashMap<NodeId, Outbox>,
+    send_queue: usize,
+    /// Why the listener thread stopped, sent before it closes the channels it fills
+    listener_failures: Receiver<AppError>,
+    rewards: Rewards,
+    firing_stats: Option<FiringStats>,
+    capture: Option<Arc<Capture>>,
+    tracer: Option<Tracer>,
+    dashboard: Option<Dashboard>,
+    web: Option<WebDashboard>,
+    wal: Option<WriteAheadLog>,
 }
 
 impl Engine {
-    pub fn new(
-        terminal_clock: usize,
-        node: String,
-        nodes: &[String],
-        nets_folder: &Path,
-    ) -> Result<Self> {
-        let log_path = format!("{}.log", node);
-        let log_file = File::create(log_path)?;
-        let log_file = BufWriter::new(log_file);
-
-        let mut nodes = nodes.to_vec();
-        nodes.sort();
-        nodes.dedup();
-
-        let nets_folder = nets_folder.display();
-        let pattern = format!("{nets_folder}/*.json");
-        let mut paths = glob(&pattern)?
-            .filter_map(std::result::Result::ok)
-            // .map(|path| path.display().to_string())
-            .collect::<Vec<_>>();
-        paths.sort();
-        paths.dedup();
+    pub fn new(config: &Config) -> Result<Self> {
+        if (config.dynamic_membership || config.join.is_some())
+            && config.synchronization != Synchronization::Coordinated
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--dynamic-membership and --join only go with --synchronization coordinated",
+            )
+            .into());
+        }
+        if config.write_ahead_log
+            && !matches!(
+                config.synchronization,
+                Synchronization::Conservative | Synchronization::DeadlockRecovery
+            )
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--write-ahead-log only goes with --synchronization conservative or deadlock-recovery",
+            )
+            .into());
+        }
+        if config.until_quiescent
+            && !matches!(
+                config.synchronization,
+                Synchronization::Conservative | Synchronization::DeadlockRecovery
+            )
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--until-quiescent only goes with --synchronization conservative or deadlock-recovery",
+            )
+            .into());
+        }
+        if config.recover
+            && (config.synchronization != Synchronization::Conservative
+                || config.null_messages != NullMessages::Eager
+                || !matches!(config.link, Link::Tcp | Link::Quic | Link::WebSocket)
+                || !config.shared_memory.is_empty())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--recover only goes with --synchronization conservative, --null-messages eager \
+                 and a tcp, quic or websocket link",
+            )
+            .into());
+        }
 
-        let nets = paths
-            .iter()
-            .map(Net::new)
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<_>>();
+        let node = NodeId::from(config.node.as_str());
 
-        assert!(!nets.is_empty(), "No nets found at {}", nets_folder);
-        assert!(!nodes.is_empty(), "No nodes provided");
-        assert!(
-            nets.len() == nodes.len(),
-            "Number of nets differs from number of nodes"
-        );
+        // a joining node only knows the coordinator until it is admitted
+        let mut nodes = match &config.join {
+            Some(coordinator) => vec![coordinator.clone()],
+            None => config.nodes.clone(),
+        };
+        if !config.nodes_in_segment_order {
+            nodes.sort();
+            nodes.dedup();
+        }
 
-        let index = nodes.iter().position(|n| n == &node).unwrap();
-        let net = nets[index].clone();
+        // with a fixed membership the segments hosted elsewhere never move here
+        let fixed = !config.dynamic_membership && config.join.is_none();
+        check_nodes(&nodes, &node, fixed)?;
+        let index = nodes.iter().position(|member| **member == *node);
+        let nets_folder = config.nets_folder()?;
+        let segments = load_segments(nets_folder, |segment, segments| {
+            !fixed || Some(assign_segments(segments, nodes.len())[segment]) == index
+        })?;
+        check_segments(nets_folder, &segments, &nodes)?;
 
-        let transition2node = nets
-            .iter()
-            .zip(nodes.iter())
-            .flat_map(|(net, node)| {
-                net.transitions
-                    .iter()
-                    .map(|transition| (transition.id, node.into()))
-            })
-            .collect::<HashMap<usize, String>>();
-
-        let node2fed_nodes: HashMap<String, Vec<String>> =
-            nets.iter().fold(HashMap::new(), |mut acc, net| {
-                net.transitions.iter().for_each(|transition| {
-                    let node = transition2node[&transition.id].clone();
-                    transition
-                        .delayed_instructions
-                        .iter()
-                        .filter(|instruction| instruction.is_external)
-                        .for_each(|instruction| {
-                            let fed_node = transition2node[&instruction.transition_id].clone();
-                            acc.entry(node.clone()).or_default().push(fed_node);
-                        });
-                });
-                acc
-            });
-        let fed_nodes = node2fed_nodes[&node].clone();
+        let Topology {
+            net,
+            transition2node,
+            fed_nodes,
+            feeding_nodes,
+            lookahead,
+        } = Topology::new(&segments, &nodes, &node)?;
 
-        let node2feeding_nodes = reverse_hashmap(&node2fed_nodes);
-        let (feeding_node2channel, feeding_nodes): (HashMap<_, _>, Vec<_>) = node2feeding_nodes
-            [&node]
+        let feeding_node2channel = Channels::default();
+        let feeding_nodes = feeding_nodes
             .iter()
             .map(|feeding_node| {
-                let (tx, rx) = channel();
-                let feeding_node = FeedingNode {
-                    name: feeding_node.clone(),
-                    clock: 0,
-                    channel: rx,
-                };
-                ((feeding_node.name.clone(), tx), feeding_node)
+                open_channel(feeding_node, &feeding_node2channel, config.receive_queue)
             })
-            .unzip();
-
-        let node_clone = node.clone();
-        let listener = thread::spawn(move || -> Result<()> {
-            let msg = format!("Failed to listen on {}", node_clone);
-            for stream in TcpListener::bind(node_clone.clone())
-                .expect(&msg)
-                .incoming()
-            {
-                let mut reader = BufReader::new(stream?);
-                let mut event: String = Default::default();
-                reader.read_line(&mut event)?;
-
-                if let Ok(GenericEvent { feeding_node }) = serde_json::from_str(&event) {
-                    // avoided generic error
-                    let msg = format!("Failed to channel event to {}", feeding_node);
-                    feeding_node2channel[&feeding_node].send(event).expect(&msg);
-                } else {
-                    unreachable!("GenericEvent could not be parsed");
+            .collect::<Vec<_>>();
+        let feeding_clocks = FeedingClocks::new(&feeding_nodes);
+
+        let (clock_request_tx, clock_requests) = channel();
+        let (gvt_token_tx, gvt_tokens) = channel();
+        let (barrier_report_tx, barrier_reports) = channel();
+        let (deadlock_marker_tx, deadlock_markers) = channel();
+        let (termination_marker_tx, termination_markers) = channel();
+        let (barrier_grant_tx, barrier_grants) = channel();
+        let (snapshot_marker_tx, snapshot_markers) = channel();
+        let (ready_tx, readies) = channel();
+        let (recovery_tx, recoveries) = channel();
+        let (handoff_tx, handoffs) = channel();
+        let capture = config
+            .capture
+            .then(|| Capture::create(&node).map(Arc::new))
+            .transpose()?;
+        let wal = config
+            .write_ahead_log
+            .then(|| WriteAheadLog::open(&node, config.recover))
+            .transpose()?;
+        // what the previous run of this node consumed is not to be received again
+        let reorder_buffers = wal
+            .as_ref()
+            .map(WriteAheadLog::reorder_buffers)
+            .unwrap_or_default();
+        let router_capture = capture.clone();
+        let router_channels = Arc::clone(&feeding_node2channel);
+        let when_full = config.receive_queue_full;
+        let unknown_senders = config.unknown_senders;
+        // peers dial the advertised address, which may be translated to another one
+        let bind_addr = config
+            .bind_addr
+            .as_ref()
+            .map_or_else(|| node.to_string(), ToString::to_string);
+        let transport = Transport::new(config, &nodes)?;
+        // the first node starts a run of its own, the others take part in it once they
+        // hear from it
+        let run_id = config
+            .run_id
+            .clone()
+            .or_else(|| {
+                wal.as_ref()
+                    .and_then(|wal| wal.run_id().map(str::to_string))
+            })
+            .or_else(|| (*nodes[0] == *node).then(|| Uuid::new_v4().to_string()));
+        if let Some(run_id) = &run_id {
+            transport.join_run(&node, run_id)?;
+        }
+        let listener_transport = transport.clone();
+        let (listener_failure_tx, listener_failures) = channel();
+        thread::spawn(move || {
+            let mut router = Router {
+                feeding_node2channel: router_channels,
+                when_full,
+                unknown_senders,
+                reorder_buffers,
+                clock_request_tx,
+                gvt_token_tx,
+                deadlock_marker_tx,
+                termination_marker_tx,
+                barrier_report_tx,
+                barrier_grant_tx,
+                snapshot_marker_tx,
+                ready_tx,
+                recovery_tx,
+                handoff_tx,
+            };
+            let listened = panic::catch_unwind(AssertUnwindSafe(|| -> Result<()> {
+                let listener = Listener::bind(&listener_transport).map_err(|error| {
+                    AppError::ListenerFailed(format!("cannot listen on {}: {}", bind_addr, error))
+                })?;
+
+                let (message_tx, messages) = channel::<WireMessage>();
+                thread::spawn(move || listener.serve(message_tx, listener_transport));
+
+                for message in messages {
+                    if let Some(capture) = &router_capture {
+                        capture.received(&message);
+                    }
+                    for message in message.unbatch() {
+                        match router.route(message) {
+                            Err(error @ AppError::MalformedEvent { .. }) => {
+                                warn!(target: logging::NETWORK, %error, "DROPPED MESSAGE")
+                            }
+                            routed => routed?,
+                        }
+                    }
                 }
-            }
 
-            Ok(())
+                Ok(())
+            }));
+            let failure = match listened {
+                Ok(Ok(())) => AppError::ListenerFailed("stopped accepting messages".to_string()),
+                Ok(Err(error)) => error,
+                Err(panic) => {
+                    AppError::ListenerFailed(format!("panicked: {}", panic_message(&*panic)))
+                }
+            };
+
+            // the engine learns why before any of its channels closes
+            listener_failure_tx.send(failure).unwrap_or_default();
+            if let Ok(mut channels) = router.feeding_node2channel.lock() {
+                channels.clear();
+            }
+            drop(router);
         });
 
+        let rewards = Rewards::new(&net.transitions);
+        let membership = Membership::new(config, *nodes[0] == *node);
+        let tracer = config
+            .otlp_endpoint
+            .as_deref()
+            .map(|endpoint| Tracer::new(&node, endpoint))
+            .transpose()?;
+        let dashboard = config.tui.then(|| Dashboard::start(config)).transpose()?;
+        let web = config
+            .web
+            .as_deref()
+            .map(|address| WebDashboard::start(address, config))
+            .transpose()?;
+
         let engine = Self {
             clock: 0,
-            step: 1,
+            step: config.step.max(1),
+            advance: config.advance,
+            real_time_factor: config.real_time_factor,
+            started: Instant::now(),
             node,
             net,
-            terminal_clock,
+            terminal_clock: config.terminal_clock.unwrap_or(usize::MAX),
+            until_quiescent: config.until_quiescent,
             fed_nodes,
+            lookahead,
             feeding_nodes,
+            feeding_clocks,
+            null_messages: config.null_messages,
+            unknown_senders: config.unknown_senders,
+            synchronization: config.synchronization,
+            time_window: config.time_window,
+            abort_on_straggler: config.abort_on_straggler,
+            stall_timeout: config.stall_timeout.map(Duration::from_secs),
+            abort_on_stall: config.abort_on_stall,
+            receive_timeout: config.receive_timeout.map(Duration::from_secs),
+            time_warp: TimeWarp::default(),
+            gvt: Gvt::new(Duration::from_millis(config.gvt_interval)),
+            traffic: Traffic::default(),
+            timing: Timing::default(),
+            progress: Progress::new(config.progress.map(Duration::from_secs_f64)),
+            gvt_tokens,
+            barrier: Barrier::default(),
+            deadlock: Deadlock::new(Duration::from_millis(config.detection_interval)),
+            deadlock_markers,
+            termination: Termination::new(Duration::from_millis(config.detection_interval)),
+            termination_markers,
+            barrier_reports,
+            barrier_grants,
+            clock_requests,
+            snapshots: Snapshots::new(config.snapshot_at),
+            dump_net_at: {
+                let mut clocks = config.dump_net_at.clone();
+                clocks.sort_unstable();
+                clocks
+            },
+            snapshot_markers,
+            readies,
+            recoveries,
+            membership,
+            handoffs,
+            feeding_node2channel,
+            receive_queue: config.receive_queue,
+            startup_timeout: Duration::from_secs(config.startup_timeout),
+            retry_policy: RetryPolicy::new(config),
+            transport,
             transition2node,
-            internal_active_events: vec![],
+            internal_active_events: EventQueue::default(),
+            enabled: vec![],
+            parallel_firing: config.parallel_firing,
+            shards: config.shards.max(1),
             external_active_events: vec![],
-            listener,
-            log_file,
+            inbox: vec![],
+            event_seq: 0,
+            channel_seqs: HashMap::new(),
+            vector_clock: VectorClock::default(),
+            lamport: LamportClock::default(),
+            lamport_violations: 0,
+            outboxes: HashMap::new(),
+            send_queue: config.send_queue.max(1),
+            listener_failures,
+            rewards,
+            firing_stats: config.firing_stats.then(FiringStats::default),
+            capture,
+            tracer,
+            dashboard,
+            web,
+            wal,
+            nodes,
+            segments,
         };
 
         Ok(engine)
     }
 
     pub fn run(&mut self) -> Result<()> {
-        while self.clock < self.terminal_clock {
-            self.log(&format!("LOOP START            {}", self.net));
-            let clock = self.clock;
+        let node = info_span!("node", node = %self.node, run = field::Empty).entered();
+        // nodes taking part in the first node's run only know it once the cluster is ready
+        let run_id = self.transport.run_id().map(str::to_string);
+        if let Some(run_id) = &run_id {
+            node.record("run", field::display(run_id));
+        }
+        self.replay()?;
+        let admitted = if self.membership.joining {
+            self.await_admission()
+        } else {
+            self.await_cluster()
+        };
+        self.or_listener_failure(admitted)?;
+        if let (None, Some(run_id)) = (run_id, self.transport.run_id()) {
+            node.record("run", field::display(run_id));
+        }
+        self.log_run()?;
+        self.started = Instant::now();
+        self.timing.start();
+        let simulated = match self.synchronization {
+            Synchronization::Conservative | Synchronization::DeadlockRecovery => {
+                self.run_conservative()
+            }
+            Synchronization::Optimistic | Synchronization::TimeWindow => self.run_optimistic(),
+            Synchronization::Coordinated => self.run_coordinated(),
+        };
+        self.or_listener_failure(simulated)?;
 
-            self.net
-                .transitions
-                .clone()
-                .iter()
-                .filter(|transition| transition.clock == clock && transition.value <= 0)
-                .rev() // to simulate a stack
-                .for_each(|transition| {
-                    self.process_immediate_instructions(transition);
-                    self.process_delayed_instructions(transition);
-                });
-            self.log(&format!("AFTER INSTRUCTIONS    {}", self.net));
+        self.refresh_dashboard();
+        self.report_final_progress();
 
-            self.handle_external_events()?;
-            self.external_active_events.clear();
-            self.log(&format!("AFTER EXTERNAL EVENTS {}", self.net));
+        if self.lamport_violations > 0 {
+            info!(violations = self.lamport_violations, "LAMPORT");
+        }
+        if !self.rewards.is_empty() {
+            info!(rewards = %self.rewards, "REWARDS");
+        }
+        if !self.traffic.is_empty() {
+            info!(traffic = %self.traffic, "TRAFFIC");
+        }
+        info!(timing = %self.timing, "TIMING");
+        if let Some(firing_stats) = &self.firing_stats {
+            let path = format!("{}.firings.json", self.node);
+            std::fs::write(&path, firing_stats.to_json(&self.node)?)?;
+            info!(path, "FIRING STATS");
+        }
+        self.write_summary()?;
+        info!(net = %self.net, "FINISHED");
 
-            self.tick()?;
-            self.log(&format!("AFTER TICK            {}", self.net));
+        // everything queued, such as end-of-stream messages, still has to reach its peer,
+        // but a peer done with the run may be gone by now, so giving up is only reported
+        self.outboxes
+            .iter_mut()
+            .try_for_each(|(node, outbox)| match outbox.close() {
+                Err(error @ AppError::Unreachable { .. }) => {
+                    warn!(target: logging::NETWORK, to = %node, %error, "UNDELIVERED");
+                    Ok(())
+                }
+                closed => closed,
+            })?;
 
-            self.handle_internal_events();
-            self.log(&format!("AFTER INTERNAL EVENTS {}", self.net));
+        Ok(())
+    }
+
+    /// Fails with why the listener thread stopped, if it did: nothing would reach this node
+    /// any longer, and waiting on its channels would never end.
+    fn check_listener(&self) -> Result<()> {
+        match self.listener_failures.try_recv() {
+            Ok(failure) => Err(failure),
+            Err(_) => Ok(()),
         }
+    }
 
-        self.log(&format!("FINISHED              {}", self.net));
+    /// `outcome`, unless the listener thread stopped: the channels it fills closing along
+    /// with it, its failure is the one to report.
+    fn or_listener_failure<T>(&self, outcome: Result<T>) -> Result<T> {
+        self.check_listener()?;
+        outcome
+    }
+
+    fn run_conservative(&mut self) -> Result<()> {
+        while self.clock < self.terminal_clock && !self.is_quiescent() {
+            self.check_listener()?;
+            let _tick = info_span!("tick", clock = self.clock).entered();
+            self.pace();
+            self.conservative_tick()?;
+        }
+
+        // every node stops on the announcement, which an end of stream could overtake,
+        // moving a fed node that has not heard it yet to the end of time
+        if self.is_quiescent() {
+            return Ok(());
+        }
+        // fed nodes short of the terminal clock would otherwise wait on a promise to reach it
+        self.send_end_of_stream()
+    }
+
+    /// Fires what is due at the current clock, exchanges events with the neighbours and
+    /// moves on to the next clock, as a conservative run does every tick. Public so
+    /// benchmarks can time a tick by itself, on an engine that has not been run.
+    pub fn conservative_tick(&mut self) -> Result<()> {
+        debug!(target: logging::NET, net = %self.net, "LOOP START");
+        let clock = self.clock;
+
+        self.fire_transitions()?;
+        debug!(target: logging::NET, net = %self.net, "AFTER INSTRUCTIONS");
+
+        self.handle_external_events()?;
+        self.external_active_events.clear();
+        debug!(target: logging::NET, net = %self.net, "AFTER EXTERNAL EVENTS");
+
+        self.tick()?;
+        // the marking holds until the internal events of the new clock are applied
+        self.rewards.accrue(
+            &self.net.transitions,
+            self.clock.min(self.terminal_clock) - clock,
+        );
+        debug!(target: logging::NET, net = %self.net, "AFTER TICK");
+
+        self.handle_internal_events();
+        debug!(target: logging::NET, net = %self.net, "AFTER INTERNAL EVENTS");
+        self.end_tick();
 
         Ok(())
     }
 
-    fn process_immediate_instructions(&mut self, transition: &Transition) {
-        transition
-            .immediate_instructions
-            .iter()
-            .for_each(|instruction| {
-                if let Some(transition) = self
-                    .net
-                    .transitions
-                    .iter_mut()
-                    .find(|transition| transition.id == instruction.transition_id)
-                {
-                    transition.value = instruction.value;
-                } else {
-                    unreachable!("Instruction referenced a non-existing transition");
-                }
-            });
+    fn fire_transitions(&mut self) -> Result<()> {
+        let clock = self.clock;
+        // chosen before any fires, as instructions may enable or disable others; the buffer
+        // is kept from tick to tick so firing allocates nothing
+        let mut enabled = std::mem::take(&mut self.enabled);
+        enabled.extend(
+            self.net
+                .scheduled_at(clock)
+                .iter()
+                .filter(|&&position| self.net.transitions[position].value <= 0),
+        );
+        // in the order of the net, whatever order they were scheduled in
+        enabled.sort_unstable();
+
+        let fired = if enabled.len() >= PARALLEL_FIRING_MIN && self.shards > 1 {
+            self.fire_in_shards(&enabled)
+        } else if enabled.len() >= PARALLEL_FIRING_MIN && self.parallel_firing {
+            self.fire_in_parallel(&enabled)
+        } else {
+            // to simulate a stack
+            enabled.iter().rev().try_for_each(|&position| {
+                self.record_firing(position);
+                self.process_immediate_instructions(position)?;
+                self.process_delayed_instructions(position);
+                Ok(())
+            })
+        };
+
+        enabled.clear();
+        self.enabled = enabled;
+        fired
     }
 
-    fn process_delayed_instructions(&mut self, transition: &Transition) {
-        transition
-            .delayed_instructions
-            .iter()
-            .for_each(|instruction| {
-                let event = ActiveEvent {
+    /// Counts the firing of the transition at `position` in the net wherever firings are.
+    fn record_firing(&mut self, position: usize) {
+        let transition = &self.net.transitions[position];
+        debug!(target: logging::FIRE, transition = transition.id, "FIRED");
+        self.progress.fired += 1;
+        self.rewards.fire(transition);
+        if let Some(firing_stats) = &mut self.firing_stats {
+            firing_stats.fire(transition.id, self.clock);
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.fired(transition);
+        }
+    }
+
+    /// Applies the immediate instructions of the transition at `position` in the net.
+    fn process_immediate_instructions(&mut self, position: usize) -> Result<()> {
+        for index in 0..self.net.transitions[position].immediate_instructions.len() {
+            let instruction = self.net.transitions[position].immediate_instructions[index];
+            let Some(transition) = self.net.transition_mut(instruction.transition_id) else {
+                return Err(AppError::UnknownTransition {
                     transition_id: instruction.transition_id,
-                    feeding_node: self.node.clone(),
-                    value: instruction.value,
-                    clock: transition.clock + transition.duration,
-                };
-                if instruction.is_external {
-                    self.external_active_events.push(event);
-                } else {
-                    self.internal_active_events.push(event);
-                }
-            });
+                    context: format!("set by transition {}", self.net.transitions[position].id),
+                });
+            };
+            transition.value = instruction.value;
+        }
+
+        Ok(())
+    }
+
+    /// Schedules the delayed instructions of the transition at `position` in the net.
+    fn process_delayed_instructions(&mut self, position: usize) {
+        for index in 0..self.net.transitions[position].delayed_instructions.len() {
+            let transition = &self.net.transitions[position];
+            let instruction = transition.delayed_instructions[index];
+            let event = ActiveEvent {
+                transition_id: instruction.transition_id,
+                feeding_node: self.node.clone(),
+                value: instruction.value,
+                clock: transition.clock + transition.duration,
+                anti: false,
+                color: 0,
+                seq: self.event_seq,
+                vector_clock: VectorClock::default(),
+                channel_seq: 0,
+                lamport: 0,
+                trace: None,
+            };
+            self.event_seq += 1;
+            if instruction.is_external {
+                let trace = self
+                    .tracer
+                    .as_mut()
+                    .map(|tracer| tracer.sent(transition, &instruction, event.clock));
+                self.external_active_events
+                    .push(ActiveEvent { trace, ..event });
+            } else {
+                self.internal_active_events.push(event);
+            }
+        }
     }
 
     fn handle_external_events(&mut self) -> Result<()> {
-        let active_events = self
-            .external_active_events
-            .clone()
-            .into_iter()
-            .map(|event| {
-                let fed_node = &self.transition2node[&event.transition_id];
-                (fed_node.clone(), event.into())
+        // requests are left for once the node is live again, as replayed ticks send nothing
+        let replaying = self.replaying();
+        if !replaying {
+            self.serve_clock_requests()?;
+            self.serve_recovered_peers()?;
+            if self.synchronization == Synchronization::DeadlockRecovery {
+                self.poll_deadlock_markers()?;
+            }
+            self.poll_termination_markers()?;
+        }
+
+        // drained rather than cloned, the buffer keeps its allocation for the next tick
+        let mut events = std::mem::take(&mut self.external_active_events);
+        events.iter_mut().for_each(|event| {
+            self.vector_clock.tick(&self.node);
+            event.vector_clock = self.vector_clock.clone();
+        });
+
+        let active_events = events
+            .drain(..)
+            .map(|mut event| {
+                let fed_node = host(&self.transition2node, event.transition_id)?.clone();
+                event.channel_seq = self.next_channel_seq(&fed_node);
+                event.lamport = self.lamport.tick();
+                Ok((fed_node, event.into()))
             })
-            .collect::<Vec<(String, String)>>();
+            .collect::<Result<Vec<(NodeId, WireMessage)>>>();
+        self.external_active_events = events;
+        let active_events = active_events?;
 
         let covered_nodes = active_events
             .iter()
             .map(|(node, _)| node)
             .collect::<Vec<_>>();
 
-        let passive_events = self
+        let uncovered_nodes = self
             .fed_nodes
             .iter()
+            .filter(|_| {
+                // only conservative nodes wait on feeding nodes' clocks
+                self.null_messages == NullMessages::Eager
+                    && self.synchronization == Synchronization::Conservative
+            })
             .filter(|fed_node| !covered_nodes.contains(fed_node))
+            .cloned()
+            .collect::<Vec<_>>();
+        let passive_events = uncovered_nodes
+            .into_iter()
             .map(|fed_node| {
-                let event = PassiveEvent {
-                    feeding_node: self.node.clone(),
-                    clock: self.clock + self.step,
-                };
-                (fed_node.clone(), event.into())
+                let event = self.null_message(&fed_node)?;
+                Ok((fed_node, event.into()))
             })
-            .collect::<Vec<(String, String)>>();
+            .collect::<Result<Vec<(NodeId, WireMessage)>>>()?;
 
-        active_events
-            .into_iter()
-            .chain(passive_events)
-            .try_for_each(|(fed_node, event): (String, String)| -> Result<()> {
-                // not sure I really need this new line, I do this bc the listening tcp stream
-                // will consider \n as a message terminator
-                let event = format!("{event}\n");
-                let payload = event.as_bytes();
-                // at the beginning of execution we need to wait until
-                // all other nodes are ready to listen
-                match TcpStream::connect(&fed_node) {
-                    Ok(mut stream) => stream.write_all(payload)?,
-                    Err(_) => {
-                        thread::sleep(Duration::from_secs(3));
-                        let mut stream = TcpStream::connect(&fed_node)?;
-                        let msg = format!("Failed to write to {}", fed_node);
-                        stream.write_all(payload).expect(&msg);
-                        self.log(&format!("SENT {}", event));
-                    }
-                };
+        self.deadlock.sent += active_events.len();
+        for (fed_node, _) in &active_events {
+            *self.termination.sent.entry(fed_node.clone()).or_default() += 1;
+        }
 
-                Ok(())
+        // everything for the same fed node leaves in a single write
+        let batches = active_events.into_iter().chain(passive_events).fold(
+            Vec::<(NodeId, Vec<WireMessage>)>::new(),
+            |mut acc, (fed_node, event)| {
+                match acc.iter_mut().find(|(node, _)| *node == fed_node) {
+                    Some((_, events)) => events.push(event),
+                    None => acc.push((fed_node, vec![event])),
+                }
+                acc
+            },
+        );
+        self.dump_net()?;
+        // fed nodes got the events of replayed ticks the first time round, and a snapshot
+        // waits for the node to be live again
+        if replaying {
+            return Ok(());
+        }
+
+        batches.into_iter().try_for_each(|(fed_node, mut events)| {
+            let message = match events.len() {
+                1 => events.remove(0),
+                _ => WireMessage::Batch(events),
+            };
+            self.send(&fed_node, message)
+        })?;
+
+        self.poll_snapshots()
+    }
+
+    /// Writes the net once the clock reaches the next --dump-net-at clock, or several of
+    /// them at once when it skipped over them.
+    fn dump_net(&mut self) -> Result<()> {
+        let due = self
+            .dump_net_at
+            .iter()
+            .take_while(|&&clock| clock <= self.clock)
+            .count();
+        if due == 0 {
+            return Ok(());
+        }
+        self.dump_net_at.drain(..due);
+
+        let path = format!("{}.{}.net.json", self.node, self.clock);
+        std::fs::write(&path, self.net.to_legacy_json()?)?;
+        info!(path, "NET DUMPED");
+
+        Ok(())
+    }
+
+    fn null_message(&mut self, fed_node: &NodeId) -> Result<PassiveEvent> {
+        let lookahead = self.lookahead_to(fed_node)?;
+        Ok(PassiveEvent {
+            feeding_node: self.node.clone(),
+            clock: self.clock + self.step.max(lookahead),
+            lookahead,
+            channel_seq: self.next_channel_seq(fed_node),
+            lamport: self.lamport.tick(),
+        })
+    }
+
+    /// Minimum firing duration of the transitions of this node feeding `fed_node`.
+    fn lookahead_to(&self, fed_node: &str) -> Result<usize> {
+        self.lookahead
+            .get(fed_node)
+            .copied()
+            .ok_or_else(|| AppError::UnknownNode {
+                node: fed_node.to_string(),
+                context: "among the nodes this one feeds".to_string(),
             })
     }
 
-    fn tick(&mut self) -> Result<()> {
-        let earliest_clock = self
+    /// Every message to a fed node is numbered so it can restore their sending order.
+    fn next_channel_seq(&mut self, fed_node: &NodeId) -> usize {
+        let channel_seq = self.channel_seqs.entry(fed_node.clone()).or_default();
+        *channel_seq += 1;
+        *channel_seq - 1
+    }
+
+    /// Answers every pending clock request with a null message.
+    fn serve_clock_requests(&mut self) -> Result<()> {
+        while let Ok(request) = self.clock_requests.try_recv() {
+            debug!(target: logging::NETWORK, ?request, "RECEIVED");
+            let requesting_node = NodeId::from(request.requesting_node.as_str());
+            if !self.fed_nodes.contains(&requesting_node) {
+                if self.unknown_senders == UnknownSenders::Strict {
+                    return Err(AppError::UnknownNode {
+                        node: request.requesting_node,
+                        context: "requesting a clock from a node that does not feed it".to_string(),
+                    });
+                }
+                warn!(from = %requesting_node, "DROPPED CLOCK REQUEST");
+                continue;
+            }
+            let event = self.null_message(&requesting_node)?;
+            self.send(&request.requesting_node, event.into())?;
+        }
+
+        Ok(())
+    }
+
+    /// Startup handshake: tells every peer this node is listening, then waits until every
+    /// peer said the same, so the run never sends to a node that cannot receive yet.
+    fn await_cluster(&mut self) -> Result<()> {
+        let deadline = Instant::now() + self.startup_timeout;
+        // peers may take their time starting, only the startup timeout bounds the wait
+        let retry_policy = RetryPolicy {
+            max_attempts: usize::MAX,
+            deadline: self.startup_timeout,
+            ..self.retry_policy
+        };
+        let others = self
+            .nodes
+            .iter()
+            .filter(|node| **node != *self.node)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        others.iter().try_for_each(|node| {
+            let outbox = Outbox::new(
+                &self.node,
+                node,
+                retry_policy,
+                self.retry_policy,
+                self.transport.clone(),
+                self.send_queue,
+            );
+            self.outboxes.insert(node.as_str().into(), outbox);
+            let ready = Ready {
+                recovered: self.recovering(),
+                ..self.ready(node, self.recovering())
+            };
+            self.send(node, ready.into())
+        })?;
+        // the peers are live already and keep sending, waiting on their answers without
+        // consuming anything would hold them up: the answers are served as they come
+        if self.recovering() {
+            info!(nodes = self.nodes.len(), "CLUSTER REJOINED");
+            return Ok(());
+        }
+
+        let mut ready_nodes = HashSet::new();
+        while ready_nodes.len() < others.len() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.readies.recv_timeout(timeout.min(STARTUP_POLL)) {
+                Ok(ready) => {
+                    if let Some(run_id) = &ready.run_id {
+                        self.transport.join_run(&ready.ready_node, run_id)?;
+                    }
+                    ready_nodes.insert(ready.ready_node);
+                }
+                // a peer that cannot be talked to, e.g. running another protocol version,
+                // fails the startup right away rather than at the timeout
+                Err(RecvTimeoutError::Timeout) if !timeout.is_zero() => self
+                    .outboxes
+                    .values_mut()
+                    .try_for_each(|outbox| outbox.check())?,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        info!(nodes = self.nodes.len(), "CLUSTER READY");
+
+        Ok(())
+    }
+
+    /// Tells `node` this node is listening and, if `resuming` a previous run of either of
+    /// them, from where to resend what this node lost.
+    fn ready(&self, node: &str, resuming: bool) -> Ready {
+        Ready {
+            ready_node: self.node.to_string(),
+            run_id: self.transport.run_id().map(str::to_string),
+            resume_from: resuming.then(|| self.consumed_from(node)),
+            recovered: false,
+        }
+    }
+
+    /// Bookkeeping once every tick is over.
+    fn end_tick(&mut self) {
+        self.timing.end_tick();
+        if let Some(capture) = &self.capture {
+            capture.set_clock(self.clock);
+            capture.flush();
+        }
+        self.refresh_dashboard();
+        self.report_progress();
+    }
+
+    /// Shows where this node stands on the dashboards, if any.
+    fn refresh_dashboard(&self) {
+        if self.dashboard.is_none() && self.web.is_none() {
+            return;
+        }
+
+        let mut pending = self
             .internal_active_events
             .iter()
-            .map(|event| event.clock)
+            .chain(self.time_warp.unprocessed())
+            .map(|event| (event.clock, event.transition_id))
+            .collect::<Vec<_>>();
+        pending.sort();
+        let mut fed_nodes = self
+            .channel_seqs
+            .iter()
+            .map(|(node, sent)| (node.to_string(), *sent))
+            .collect::<Vec<_>>();
+        fed_nodes.sort();
+
+        let status = Status {
+            node: self.node.to_string(),
+            synchronization: format!("{:?}", self.synchronization).to_lowercase(),
+            clock: self.clock,
+            terminal_clock: self.terminal_clock,
+            gvt: matches!(
+                self.synchronization,
+                Synchronization::Optimistic | Synchronization::TimeWindow
+            )
+            .then_some(self.gvt.value),
+            feeding_nodes: self
+                .feeding_nodes
+                .iter()
+                .map(|feeding_node| {
+                    (
+                        feeding_node.name.to_string(),
+                        feeding_node.clock,
+                        feeding_node.consumed,
+                    )
+                })
+                .collect(),
+            fed_nodes,
+            pending,
+            marking: self
+                .net
+                .transitions
+                .iter()
+                .map(|transition| (transition.id, transition.value, transition.clock))
+                .collect(),
+            finished: false,
+        };
+        if let Some(web) = &self.web {
+            web.update(status.clone());
+        }
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.update(status);
+        }
+    }
+
+    /// Queues `event` for the thread writing to `node`.
+    fn send(&mut self, node: &str, message: WireMessage) -> Result<()> {
+        debug!(target: logging::NETWORK, to = node, sent = ?message, "SENT");
+        self.traffic.sent(node, &message);
+        if let Some(capture) = &self.capture {
+            capture.sent(node, &message);
+        }
+        self.log_sent(node, &message)?;
+        self.outbox(node).send(message)
+    }
+
+    /// Outbox to `node`, opened on first use.
+    fn outbox(&mut self, node: &str) -> &mut Outbox {
+        if !self.outboxes.contains_key(node) {
+            let outbox = Outbox::new(
+                &self.node,
+                node,
+                self.retry_policy,
+                self.retry_policy,
+                self.transport.clone(),
+                self.send_queue,
+            );
+            self.outboxes.insert(node.into(), outbox);
+        }
+        self.outboxes.get_mut(node).expect("Outbox opened above")
+    }
+
+    /// Tells every fed node that no further events will come from this node,
+    /// so they stop waiting on it.
+    fn send_end_of_stream(&mut self) -> Result<()> {
+        self.fed_nodes.clone().iter().try_for_each(|fed_node| {
+            let event = PassiveEvent {
+                feeding_node: self.node.clone(),
+                clock: usize::MAX,
+                lookahead: self.lookahead_to(fed_node)?,
+                channel_seq: self.next_channel_seq(fed_node),
+                lamport: self.lamport.tick(),
+            };
+            self.send(fed_node, event.into())
+        })
+    }
+
+    /// The first node initiates cluster-wide protocols such as GVT rounds.
+    fn is_leader(&self) -> bool {
+        *self.nodes[0] == *self.node
+    }
+
+    /// Successor of this node in the ring used by cluster-wide protocols.
+    fn next_node(&self) -> Result<String> {
+        let index = self
+            .nodes
+            .iter()
+            .position(|n| **n == *self.node)
+            .ok_or_else(|| AppError::UnknownNode {
+                node: self.node.to_string(),
+                context: "in the ring of cluster members".to_string(),
+            })?;
+        Ok(self.nodes[(index + 1) % self.nodes.len()].clone())
+    }
+
+    /// Earliest clock at which this node has something scheduled, `usize::MAX` if nothing is.
+    fn next_scheduled_clock(&self) -> usize {
+        self.internal_active_events
+            .next_clock()
+            .into_iter()
             .chain(
-                self.feeding_nodes
-                    .iter()
-                    .map(|feeding_node| feeding_node.clock),
+                self.clock
+                    .checked_add(1)
+                    .and_then(|from| self.net.next_enabled_clock(from)),
             )
             .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Clock to move to when no event is pending at an earlier one. When advancing to the
+    /// next event, that is the earliest scheduled firing or the earliest clock a feeding node
+    /// may still deliver at, so idle stretches are skipped instead of stepped through.
+    fn next_clock(&self) -> usize {
+        let stepped = self.clock.saturating_add(self.step);
+        match self.advance {
+            Advance::Step => stepped,
+            Advance::NextEvent => self
+                .feeding_clocks
+                .after(self.clock)
+                .into_iter()
+                .chain([self.next_scheduled_clock()])
+                .min()
+                .filter(|&clock| clock != usize::MAX)
+                .unwrap_or(stepped),
+        }
+    }
+
+    /// Holds execution back until wall-clock time catches up with the simulation clock,
+    /// one clock unit lasting `real_time_factor` milliseconds.
+    fn pace(&mut self) {
+        let Some(factor) = self.real_time_factor else {
+            return;
+        };
+        let Ok(offset) = Duration::try_from_secs_f64(self.clock as f64 * factor / 1000.0) else {
+            return;
+        };
+        if let Some(wait) = (self.started + offset).checked_duration_since(Instant::now()) {
+            let since = Instant::now();
+            thread::sleep(wait);
+            self.timing.paced(since);
+        }
+    }
+
+    /// Whether a termination round confirmed that nothing is left to do anywhere.
+    fn is_quiescent(&self) -> bool {
+        self.termination.terminated
+    }
+
+    /// Records the clock the feeding node at `index` promised, keeping `feeding_clocks` in step.
+    fn set_feeding_clock(&mut self, index: usize, clock: usize) {
+        let feeding_node = &mut self.feeding_nodes[index];
+        self.feeding_clocks.update(index, feeding_node.clock, clock);
+        feeding_node.clock = clock;
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        if self.replay_tick()? {
+            return Ok(());
+        }
+
+        let earliest_clock = self
+            .internal_active_events
+            .next_clock()
+            .into_iter()
+            .chain(self.feeding_clocks.earliest())
+            .min()
             .unwrap_or(self.clock);
 
-        let events = self
-            .feeding_nodes
-            .iter()
-            .filter(|feeding_node| feeding_node.clock == earliest_clock)
-            .map(|feeding_node| feeding_node.channel.recv())
-            .filter_map(std::result::Result::ok)
-            .chain(
+        // a feeding node at usize::MAX has finished and will not send anything else
+        let blocking = match earliest_clock {
+            usize::MAX => vec![],
+            clock => self.feeding_clocks.at(clock).collect::<Vec<_>>(),
+        };
+
+        let mut mandatory = vec![];
+        for index in blocking {
+            let since = Instant::now();
+            mandatory.push(self.receive(index)?);
+            self.timing
+                .blocked_on(&self.feeding_nodes[index].name, since);
+        }
+
+        let mut events = std::mem::take(&mut self.inbox);
+        events.extend(
+            mandatory.into_iter().flatten().chain(
                 // catches any extra events other than the above mandatory ones without blocking
                 // otherwise feeding nodes that are not at `earliest_clock` would miss events
                 self.feeding_nodes
                     .iter()
                     .map(|feeding_node| feeding_node.channel.try_recv())
                     .filter_map(std::result::Result::ok),
-            )
-            .collect::<Vec<_>>();
+            ),
+        );
+
+        // logged with the clock they lead to, before the marking takes any of them in
+        let logged = self.wal.is_some().then(|| events.clone());
+        let applied = events
+            .drain(..)
+            .try_for_each(|event| self.apply_received(event));
+        self.inbox = events;
+        applied?;
 
-        events.into_iter().for_each(|event| {
-            if let Ok(event @ ActiveEvent { .. }) = serde_json::from_str(&event) {
-                self.log(&format!("RECEIVED {:?}", event));
+        self.clock = self
+            .internal_active_events
+            .next_clock()
+            .unwrap_or_else(|| self.next_clock());
+        if let Some(events) = logged {
+            self.log_tick(&events)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies an event consumed from a feeding node: an active one is queued for the
+    /// clock it is due at, a passive one moves its feeding node's clock on.
+    fn apply_received(&mut self, event: WireMessage) -> Result<()> {
+        self.observe_channel(&event)?;
+        match event {
+            WireMessage::Active(mut event) => {
+                self.observe_received(&event);
+                if event.clock < self.clock {
+                    // without saved states the best left is applying it as soon as possible
+                    self.straggler(&event)?;
+                    event.clock = self.clock;
+                }
+                self.deadlock.received += 1;
+                *self
+                    .termination
+                    .received
+                    .entry(event.feeding_node.clone())
+                    .or_default() += 1;
                 self.internal_active_events.push(event);
-            } else if let Ok(event @ PassiveEvent { .. }) = serde_json::from_str(&event) {
-                self.log(&format!("RECEIVED {:?}", event));
-                if let Some(feeding_node) = self
+            }
+            WireMessage::Passive(event) => {
+                debug!(target: logging::NETWORK, ?event, "RECEIVED");
+                if let Some(index) = self
                     .feeding_nodes
-                    .iter_mut()
-                    .find(|feeding_node| feeding_node.name == event.feeding_node)
+                    .iter()
+                    .position(|feeding_node| feeding_node.name == event.feeding_node)
                 {
-                    feeding_node.clock = event.clock;
+                    self.set_feeding_clock(index, event.clock);
+                    self.feeding_nodes[index].requested = false;
                 }
-            } else {
-                unreachable!("Event could not be parsed");
             }
-        });
+            WireMessage::Control(_) | WireMessage::Batch(_) => {
+                return Err(AppError::MalformedEvent {
+                    reason: "a control message on an event channel".to_string(),
+                });
+            }
+        }
 
-        self.clock = self
-            .internal_active_events
+        Ok(())
+    }
+
+    /// Waits for the next event of a feeding node. In on-demand mode the feeding node
+    /// is asked for a null message, and requests from our own fed nodes keep being served
+    /// while waiting so that two nodes blocked on each other still make progress.
+    fn receive(&mut self, index: usize) -> Result<Option<WireMessage>> {
+        if self.synchronization == Synchronization::DeadlockRecovery {
+            return self.receive_or_recover(index);
+        }
+
+        let mut wait = Wait::new();
+        if self.null_messages == NullMessages::Eager {
+            // a peer this node waits on may crash and need catching up before it goes on,
+            // and the cluster may terminate while it waits
+            if self.receive_timeout.is_none()
+                && self.stall_timeout.is_none()
+                && self.wal.is_none()
+                && !self.until_quiescent
+            {
+                return Ok(self.feeding_nodes[index].channel.recv().ok());
+            }
+            loop {
+                match self.feeding_nodes[index].channel.recv_timeout(RECEIVE_POLL) {
+                    Ok(event) => return Ok(Some(event)),
+                    Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                    Err(RecvTimeoutError::Timeout) => self.watch(index, &mut wait)?,
+                }
+                if self.is_quiescent() {
+                    return Ok(None);
+                }
+            }
+        }
+
+        loop {
+            match self.feeding_nodes[index].channel.try_recv() {
+                Ok(event) => return Ok(Some(event)),
+                Err(TryRecvError::Disconnected) => return Ok(None),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if !self.feeding_nodes[index].requested {
+                let feeding_node = self.feeding_nodes[index].name.clone();
+                let request = ClockRequest {
+                    requesting_node: self.node.to_string(),
+                    clock: self.clock,
+                };
+                debug!(target: logging::NETWORK, ?request, from = %feeding_node, "REQUESTING");
+                self.send(&feeding_node, request.into())?;
+                self.feeding_nodes[index].requested = true;
+            }
+
+            self.serve_clock_requests()?;
+
+            match self.feeding_nodes[index]
+                .channel
+                .recv_timeout(Duration::from_millis(10))
+            {
+                Ok(event) => return Ok(Some(event)),
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                Err(RecvTimeoutError::Timeout) => self.watch(index, &mut wait)?,
+            }
+            if self.is_quiescent() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn handle_internal_events(&mut self) {
+        // events sharing the current clock come out in a stable order, as later ones win
+        for event in self.internal_active_events.pop_due(self.clock) {
+            self.net
+                .set_marking(event.transition_id, event.clock, event.value);
+        }
+    }
+
+    /// Logs an event received from a feeding node and merges its causal history.
+    /// Called with every message consumed from a feeding node, before it is applied.
+    /// Timestamps must grow along a channel, a message that breaks that is flagged.
+    fn observe_channel(&mut self, event: &WireMessage) -> Result<()> {
+        let Some((feeding_node, channel_seq, lamport)) = event.channel() else {
+            return Ok(());
+        };
+        let feeding_node = feeding_node.clone();
+        self.traffic.received(&feeding_node, event);
+
+        let last_lamport = self
+            .feeding_nodes
+            .iter_mut()
+            .find(|candidate| candidate.name == feeding_node)
+            .map(|feeding_node| {
+                feeding_node.consumed += 1;
+                std::mem::replace(&mut feeding_node.lamport, lamport)
+            })
+            .unwrap_or_default();
+        if lamport <= last_lamport {
+            self.lamport_violations += 1;
+            warn!(
+                channel = %feeding_node,
+                lamport,
+                last_lamport,
+                channel_seq,
+                "LAMPORT VIOLATION"
+            );
+        }
+        self.lamport.observe(lamport);
+
+        self.record_in_flight(&feeding_node, channel_seq, event)
+    }
+
+    fn observe_received(&mut self, event: &ActiveEvent) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.received(event);
+        }
+        self.vector_clock.merge(&event.vector_clock, &self.node);
+        debug!(
+            target: logging::NETWORK,
+            ?event,
+            local_vc = %self.vector_clock,
+            local_lamport = self.lamport.value(),
+            "RECEIVED"
+        );
+    }
+
+    /// Reports an event older than the local clock, along with the channel and transition
+    /// it concerns and what its feeding node had promised, failing if configured to abort.
+    fn straggler(&mut self, event: &ActiveEvent) -> Result<()> {
+        let promised_clock = self
+            .feeding_nodes
             .iter()
-            .map(|event| event.clock)
-            .min()
-            .unwrap_or(self.clock + self.step);
+            .find(|feeding_node| feeding_node.name == event.feeding_node)
+            .map(|feeding_node| feeding_node.clock);
+        warn!(
+            channel = %event.feeding_node,
+            transition = event.transition_id,
+            clock = event.clock,
+            local_clock = self.clock,
+            ?promised_clock,
+            seq = event.seq,
+            channel_seq = event.channel_seq,
+            "STRAGGLER"
+        );
+
+        if self.abort_on_straggler {
+            return Err(AppError::Straggler {
+                feeding_node: event.feeding_node.to_string(),
+                transition_id: event.transition_id,
+                clock: event.clock,
+                local_clock: self.clock,
+            });
+        }
 
         Ok(())
     }
 
-    fn handle_internal_events(&mut self) {
-        // below events are ordered from lowest clock to highest clock,
-        // but if we always handle events for the current clock then there's no need to do any sorting
-        // self.events.sort_by(|a, b| a.clock.cmp(&b.clock));
+    /// Called every now and then during `wait` on the feeding node at `index`. Fails once
+    /// the wait lasted --receive-timeout, or if the listener stopped, as nothing would
+    /// come any longer, and logs where this node stands every --stall-timeout.
+    fn watch(&mut self, index: usize, wait: &mut Wait) -> Result<()> {
+        self.check_listener()?;
+        // a feeding node that crashed and recovered has to be caught up before it can go on
+        self.serve_recovered_peers()?;
+        // a round may be waiting on this node to find that the cluster has terminated
+        self.poll_termination_markers()?;
+        let waited = wait.since.elapsed();
+        if self
+            .receive_timeout
+            .is_some_and(|timeout| waited >= timeout)
+        {
+            return Err(self.stalled(index, waited));
+        }
 
-        self.internal_active_events
+        let report = self
+            .stall_timeout
+            .is_some_and(|timeout| waited >= timeout * (wait.reports + 1));
+        if report {
+            wait.reports += 1;
+            let stalled = self.stalled(index, waited);
+            if self.abort_on_stall {
+                return Err(stalled);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Logs the clock of this node, those of its channels and its pending events after it
+    /// waited on the feeding node at `index` for `waited`, returning the error to fail with.
+    fn stalled(&self, index: usize, waited: Duration) -> AppError {
+        let channels = self
+            .feeding_nodes
             .iter()
-            .filter(|event| event.clock == self.clock)
-            .for_each(|event| {
-                if let Some(transition) = &mut self
-                    .net
-                    .transitions
-                    .iter_mut()
-                    .find(|transition| transition.id == event.transition_id)
-                {
-                    transition.clock = event.clock;
-                    transition.value = event.value;
+            .map(|feeding_node| {
+                format!(
+                    "{}@{} ({} consumed)",
+                    feeding_node.name, feeding_node.clock, feeding_node.consumed
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut pending = self
+            .internal_active_events
+            .iter()
+            .map(|event| (event.clock, event.transition_id))
+            .collect::<Vec<_>>();
+        pending.sort();
+        let feeding_node = &self.feeding_nodes[index].name;
+        warn!(
+            clock = self.clock,
+            waiting_on = %feeding_node,
+            ?waited,
+            channels,
+            pending = pending.len(),
+            earliest_pending = ?&pending[..pending.len().min(STALL_PENDING_SHOWN)],
+            "STALLED"
+        );
+
+        AppError::Stalled {
+            feeding_node: feeding_node.to_string(),
+            clock: self.clock,
+            waited,
+        }
+    }
+}
+
+/// A wait on a feeding node, timed by `Engine::watch`
+struct Wait {
+    since: Instant,
+    /// Stalls logged so far
+    reports: u32,
+}
+
+impl Wait {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            reports: 0,
+        }
+    }
+}
+
+impl Drop for Engine {
+    /// Whether the run ended or failed, the log written so far must reach the disk.
+    fn drop(&mut self) {
+        logging::flush();
+        if let Some(capture) = &self.capture {
+            capture.flush();
+        }
+    }
+}
+
+/// What a node hosts and whom it exchanges events with, given the nodes hosting the
+/// segments.
+struct Topology {
+    net: Net,
+    transition2node: HashMap<usize, NodeId>,
+    fed_nodes: Vec<NodeId>,
+    feeding_nodes: Vec<NodeId>,
+    lookahead: HashMap<NodeId, usize>,
+}
+
+impl Topology {
+    fn new(segments: &[Segment], nodes: &[String], node: &str) -> Result<Self> {
+        // each node hosts a contiguous block of segments, spread as evenly as possible
+        let segment2node = assign_segments(segments.len(), nodes.len());
+        let nodes = nodes
+            .iter()
+            .map(|node| NodeId::from(node.as_str()))
+            .collect::<Vec<_>>();
+
+        // a node not admitted yet hosts nothing
+        let index = nodes.iter().position(|n| **n == *node);
+        let mut net = Net::merge(
+            segments
+                .iter()
+                .zip(segment2node.iter())
+                .filter(|(_, &owner)| Some(owner) == index)
+                .map(|(segment, _)| {
+                    segment
+                        .net
+                        .clone()
+                        .expect("Segments hosted here are loaded in full")
+                }),
+        );
+
+        let transition2node = segments
+            .iter()
+            .zip(segment2node.iter())
+            .flat_map(|(segment, &owner)| {
+                let owner = &nodes[owner];
+                segment.index.ids.iter().map(move |&id| (id, owner.clone()))
+            })
+            .collect::<HashMap<usize, NodeId>>();
+
+        // instructions between segments hosted by this same node never leave the process
+        // an unknown target is never local, and is reported along with the feeds below
+        net.localize(|transition_id| {
+            transition2node
+                .get(&transition_id)
+                .is_some_and(|owner| **owner == *node)
+        });
+
+        let mut node2fed_nodes: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for segment in segments {
+            for (id, target) in &segment.index.feeds {
+                let node = host(&transition2node, *id)?.clone();
+                let fed_node = host(&transition2node, *target)?.clone();
+                let fed_nodes = node2fed_nodes.entry(node.clone()).or_default();
+                if fed_node != node && !fed_nodes.contains(&fed_node) {
+                    fed_nodes.push(fed_node);
                 }
-            });
+            }
+        }
+        let fed_nodes = node2fed_nodes.get(node).cloned().unwrap_or_default();
 
-        self.internal_active_events
-            .retain(|event| event.clock != self.clock);
+        // no event can reach a fed node sooner than the quickest transition feeding it
+        let mut lookahead = HashMap::new();
+        for transition in &net.transitions {
+            for instruction in &transition.delayed_instructions {
+                if !instruction.is_external {
+                    continue;
+                }
+                let fed_node = host(&transition2node, instruction.transition_id)?.clone();
+                let lookahead = lookahead.entry(fed_node).or_insert(transition.duration);
+                *lookahead = (*lookahead).min(transition.duration);
+            }
+        }
+
+        let feeding_nodes = reverse_hashmap(&node2fed_nodes)
+            .remove(node)
+            .unwrap_or_default();
+
+        Ok(Self {
+            net,
+            transition2node,
+            fed_nodes,
+            feeding_nodes,
+            lookahead,
+        })
+    }
+
+    /// Whether `node` hosts the transition `transition_id`, which no node does if unknown.
+    fn hosts(&self, node: &str, transition_id: usize) -> bool {
+        self.transition2node
+            .get(&transition_id)
+            .is_some_and(|owner| **owner == *node)
     }
+}
+
+/// Node hosting the transition `transition_id` according to `transition2node`.
+fn host(transition2node: &HashMap<usize, NodeId>, transition_id: usize) -> Result<&NodeId> {
+    transition2node
+        .get(&transition_id)
+        .ok_or_else(|| AppError::UnknownTransition {
+            transition_id,
+            context: "targeted by a delayed instruction".to_string(),
+        })
+}
+
+/// Channel from each feeding node, shared between the engine, which sets them up, and the
+/// router, which fills them
+type Channels = Arc<Mutex<HashMap<NodeId, SyncSender<WireMessage>>>>;
 
-    fn log(&mut self, msg: &str) {
-        log(&mut self.log_file, self.clock, &self.node, msg);
+/// Sets up the channel from `feeding_node`, holding up to `capacity` events.
+fn open_channel(feeding_node: &NodeId, channels: &Channels, capacity: usize) -> FeedingNode {
+    let (tx, rx) = sync_channel(capacity.max(1));
+    channels.lock().unwrap().insert(feeding_node.clone(), tx);
+    FeedingNode {
+        name: feeding_node.clone(),
+        clock: 0,
+        channel: rx,
+        requested: false,
+        consumed: 0,
+        lamport: 0,
     }
 }
 
-fn log(file: &mut BufWriter<File>, clock: usize, node: &str, msg: &str) {
-    let stamp = Local::now().format("%Y-%m-%d %H:%M:%S.%f");
-    let data = format!("[{}] [clk={}] [node={}] {}\n", stamp, clock, node, msg);
-    file.write_all(data.as_bytes()).unwrap();
+/// Hands every message received to whoever handles its kind, events through the
+/// reorder buffer of their channel.
+struct Router {
+    feeding_node2channel: Channels,
+    when_full: QueueFull,
+    unknown_senders: UnknownSenders,
+    reorder_buffers: HashMap<NodeId, ReorderBuffer>,
+    clock_request_tx: Sender<ClockRequest>,
+    gvt_token_tx: Sender<GvtToken>,
+    deadlock_marker_tx: Sender<DeadlockMarker>,
+    termination_marker_tx: Sender<TerminationMarker>,
+    barrier_report_tx: Sender<BarrierReport>,
+    barrier_grant_tx: Sender<BarrierGrant>,
+    snapshot_marker_tx: Sender<SnapshotMarker>,
+    ready_tx: Sender<Ready>,
+    recovery_tx: Sender<Ready>,
+    handoff_tx: Sender<Handoff>,
+}
+
+impl Router {
+    fn route(&mut self, message: WireMessage) -> Result<()> {
+        let control = match message {
+            WireMessage::Control(control) => control,
+            WireMessage::Batch(_) => {
+                return Err(AppError::MalformedEvent {
+                    reason: "a batch left packed".to_string(),
+                })
+            }
+            event => return self.route_event(event),
+        };
+
+        let (sent, kind) = match control {
+            Control::ClockRequest(request) => {
+                (self.clock_request_tx.send(request).is_ok(), "clock request")
+            }
+            Control::GvtToken(token) => (self.gvt_token_tx.send(token).is_ok(), "GVT token"),
+            Control::DeadlockMarker(marker) => (
+                self.deadlock_marker_tx.send(marker).is_ok(),
+                "deadlock marker",
+            ),
+            Control::BarrierReport(report) => (
+                self.barrier_report_tx.send(report).is_ok(),
+                "barrier report",
+            ),
+            Control::BarrierGrant(grant) => {
+                (self.barrier_grant_tx.send(grant).is_ok(), "barrier grant")
+            }
+            Control::SnapshotMarker(marker) => (
+                self.snapshot_marker_tx.send(marker).is_ok(),
+                "snapshot marker",
+            ),
+            Control::Ready(ready) if ready.resume_from.is_some() => {
+                (self.recovery_tx.send(ready).is_ok(), "recovery")
+            }
+            Control::Ready(ready) => (self.ready_tx.send(ready).is_ok(), "ready message"),
+            Control::Handoff(handoff) => (self.handoff_tx.send(handoff).is_ok(), "handoff"),
+            Control::TerminationMarker(marker) => (
+                self.termination_marker_tx.send(marker).is_ok(),
+                "termination marker",
+            ),
+        };
+        if !sent {
+            return Err(AppError::ListenerFailed(format!(
+                "the engine no longer takes any {}",
+                kind
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn route_event(&mut self, event: WireMessage) -> Result<()> {
+        let (feeding_node, channel_seq) = channel_of(&event)?;
+        match self
+            .reorder_buffers
+            .entry(feeding_node.clone())
+            .or_default()
+            .push(channel_seq, event)
+        {
+            Reordered::Released(events) => {
+                // not held while blocked on a full channel, so the engine can still open others
+                let channel = self
+                    .feeding_node2channel
+                    .lock()
+                    .unwrap()
+                    .get(&feeding_node)
+                    .cloned();
+                // a stray or misconfigured peer, or the membership changed since it was sent
+                let Some(channel) = channel else {
+                    if self.unknown_senders == UnknownSenders::Strict {
+                        return Err(AppError::UnknownNode {
+                            node: feeding_node.to_string(),
+                            context: "sending events to a node it does not feed".to_string(),
+                        });
+                    }
+                    warn!(
+                        target: logging::NETWORK,
+                        from = %feeding_node,
+                        reason = "not feeding this node",
+                        "DROPPED EVENTS"
+                    );
+                    return Ok(());
+                };
+                for event in events {
+                    let sent = match self.when_full {
+                        QueueFull::Block => channel.send(event).is_ok(),
+                        QueueFull::Drop => match channel.try_send(event) {
+                            Err(TrySendError::Full(event)) => {
+                                let (_, channel_seq) = channel_of(&event)?;
+                                warn!(
+                                    target: logging::NETWORK,
+                                    from = %feeding_node,
+                                    channel_seq,
+                                    reason = "receive queue full",
+                                    "DROPPED MESSAGE"
+                                );
+                                true
+                            }
+                            sent => sent.is_ok(),
+                        },
+                    };
+                    // the channel may have been closed since, as the membership changed
+                    if !sent {
+                        warn!(
+                            target: logging::NETWORK,
+                            from = %feeding_node,
+                            reason = "no longer feeding",
+                            "DROPPED EVENTS"
+                        );
+                        break;
+                    }
+                }
+            }
+            Reordered::Duplicate => warn!(
+                target: logging::NETWORK,
+                from = %feeding_node,
+                channel_seq,
+                reason = "duplicate",
+                "DROPPED MESSAGE"
+            ),
+            Reordered::Gap { missing } => warn!(
+                target: logging::NETWORK,
+                from = %feeding_node,
+                channel_seq,
+                ?missing,
+                "HELD BACK MESSAGE"
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+/// What a thread panicked with, when it is text.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// Sender and position on the channel of an event, failing for any other message.
+fn channel_of(event: &WireMessage) -> Result<(NodeId, usize)> {
+    event
+        .channel()
+        .map(|(feeding_node, channel_seq, _)| (feeding_node.clone(), channel_seq))
+        .ok_or_else(|| AppError::MalformedEvent {
+            reason: "a control message where an event was expected".to_string(),
+        })
+}
+
+/// Net files in `nets_folder`, in the order their segments are assigned to nodes.
+pub fn net_paths(nets_folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for extension in ["json", "yaml", "yml", "toml", "pnml", "petri"] {
+        let pattern = format!("{}/*.{extension}", nets_folder.display());
+        paths.extend(glob(&pattern)?.filter_map(std::result::Result::ok));
+    }
+    paths.sort();
+    paths.dedup();
+
+    Ok(paths)
+}
+
+/// Segments of the nets in `nets_folder`, skipping the files that fail to load. Every
+/// transition must be defined once across them, and every one set by an instruction
+/// defined by one of them.
+pub fn load_nets(nets_folder: &Path) -> Result<Vec<Net>> {
+    let loaded = net_paths(nets_folder)?
+        .into_iter()
+        .filter_map(|path| match Net::load(&path) {
+            Ok(nets) => Some((path, nets)),
+            Err(error) => {
+                warn!(%error, "SKIPPED NET");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let files = loaded
+        .iter()
+        .map(|(path, nets)| {
+            let transitions = nets.iter().flat_map(|net| &net.transitions);
+            let ids = transitions
+                .clone()
+                .map(|transition| transition.id)
+                .collect();
+            (path.as_path(), ids, transitions.flat_map(sets).collect())
+        })
+        .collect::<Vec<_>>();
+    check_transitions(nets_folder, &files)?;
+
+    Ok(loaded.into_iter().flat_map(|(_, nets)| nets).collect())
+}
+
+/// A segment as a node has it: loaded in full if the node may host it, otherwise only
+/// indexed
+struct Segment {
+    /// File the segment was read from, which a .pnml file may share with others
+    path: PathBuf,
+    index: SegmentIndex,
+    net: Option<Net>,
+}
+
+/// Segments of the nets in `nets_folder`, as `load_nets` has them, but only those `hosted`
+/// picks, given the position of the segment and how many there are, loaded in full. Every
+/// other one is indexed, which is all it takes to tell who hosts and feeds what, so a node
+/// of a large model neither parses nor holds the transitions others simulate. A file that
+/// indexes fine but then fails to load is an error rather than skipped, as the other nodes
+/// counted it.
+fn load_segments<F>(nets_folder: &Path, hosted: F) -> Result<Vec<Segment>>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut indexed = vec![];
+    for path in net_paths(nets_folder)? {
+        // a .pnml file may hold several segments, told apart only by loading it
+        let segments = if path
+            .extension()
+            .is_some_and(|extension| extension == "pnml")
+        {
+            Net::load(&path).map(|nets| {
+                nets.into_iter()
+                    .map(|net| Segment {
+                        path: path.clone(),
+                        index: SegmentIndex::from(&net),
+                        net: Some(net),
+                    })
+                    .collect()
+            })
+        } else {
+            Net::index(&path).map(|index| {
+                vec![Segment {
+                    path: path.clone(),
+                    index,
+                    net: None,
+                }]
+            })
+        };
+        match segments {
+            Ok(segments) => indexed.extend(segments),
+            Err(error) => warn!(%error, "SKIPPED NET"),
+        }
+    }
+
+    let count = indexed.len();
+    for (position, segment) in indexed.iter_mut().enumerate() {
+        if !hosted(position, count) {
+            segment.net = None;
+        } else if segment.net.is_none() {
+            segment.net = Some(Net::merge(Net::load(&segment.path)?));
+        }
+    }
+
+    let files = indexed
+        .iter()
+        .map(|segment| {
+            let sets = match &segment.net {
+                Some(net) => net.transitions.iter().flat_map(sets).collect(),
+                None => segment.index.feeds.clone(),
+            };
+            (segment.path.as_path(), segment.index.ids.clone(), sets)
+        })
+        .collect::<Vec<_>>();
+    check_transitions(nets_folder, &files)?;
+
+    Ok(indexed)
+}
+
+/// Fails unless there are nodes to run on, this one among them with a fixed membership.
+fn check_nodes(nodes: &[String], node: &str, fixed: bool) -> Result<()> {
+    if nodes.is_empty() {
+        return Err(AppError::TopologyMismatch(
+            "no nodes configured, give them with --nodes".to_string(),
+        ));
+    }
+    if fixed && !nodes.iter().any(|member| member == node) {
+        return Err(AppError::TopologyMismatch(format!(
+            "{} is not among the configured nodes {}, pass it with --nodes as well",
+            node,
+            nodes.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fails unless every one of `nodes` gets at least one of `segments`, naming the net files
+/// found in `nets_folder` and the nodes left without one otherwise.
+fn check_segments(nets_folder: &Path, segments: &[Segment], nodes: &[String]) -> Result<()> {
+    if segments.is_empty() {
+        let files = net_paths(nets_folder)?;
+        return Err(AppError::TopologyMismatch(if files.is_empty() {
+            format!("no net files found at {}", nets_folder.display())
+        } else {
+            format!(
+                "none of the net files at {} could be loaded: {}",
+                nets_folder.display(),
+                describe_files(files.iter().map(PathBuf::as_path))
+            )
+        }));
+    }
+    if segments.len() < nodes.len() {
+        // the first nodes take one segment each
+        return Err(AppError::TopologyMismatch(format!(
+            "{} segments for {} nodes, leaving {} without one; segments from {}; nodes {}",
+            segments.len(),
+            nodes.len(),
+            nodes[segments.len()..].join(", "),
+            describe_files(segments.iter().map(|segment| segment.path.as_path())),
+            nodes.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// `paths` separated by commas, a file holding several segments in a row listed once with
+/// their count.
+fn describe_files<'a>(paths: impl Iterator<Item = &'a Path>) -> String {
+    let mut files: Vec<(&Path, usize)> = vec![];
+    for path in paths {
+        match files.last_mut() {
+            Some((last, count)) if *last == path => *count += 1,
+            _ => files.push((path, 1)),
+        }
+    }
+    files
+        .iter()
+        .map(|(path, count)| match count {
+            1 => path.display().to_string(),
+            count => format!("{} ({} segments)", path.display(), count),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Every transition `transition` sets, after its own id.
+fn sets(transition: &Transition) -> impl Iterator<Item = (usize, usize)> + '_ {
+    transition
+        .immediate_instructions
+        .iter()
+        .chain(&transition.delayed_instructions)
+        .map(|instruction| (transition.id, instruction.transition_id))
+}
+
+/// A net file, the transitions it defines, and those it sets after the one setting each
+type Definitions<'a> = (&'a Path, Vec<usize>, Vec<(usize, usize)>);
+
+/// Checks that no transition is defined by two of `files`, and that every one they set is
+/// defined by one of them.
+fn check_transitions(nets_folder: &Path, files: &[Definitions]) -> Result<()> {
+    let mut defined_in = HashMap::new();
+    for (path, ids, _) in files {
+        for &id in ids {
+            if let Some(other) = defined_in.insert(id, path) {
+                return Err(invalid(
+                    path,
+                    format!("transition {id} is defined by {} as well", other.display()),
+                ));
+            }
+        }
+    }
+    for (path, _, sets) in files {
+        for (id, target) in sets {
+            if !defined_in.contains_key(target) {
+                return Err(invalid(
+                    path,
+                    format!(
+                        "transition {id} sets transition {target}, which no net in {} defines",
+                        nets_folder.display()
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps each segment index to the index of the node hosting it.
+/// The first `segments % nodes` nodes take one extra segment.
+pub fn assign_segments(segments: usize, nodes: usize) -> Vec<usize> {
+    let base = segments / nodes;
+    let extra = segments % nodes;
+    (0..nodes)
+        .flat_map(|node| {
+            let count = base + usize::from(node < extra);
+            std::iter::repeat_n(node, count)
+        })
+        .collect()
 }
 
 fn reverse_hashmap<K, V>(input: &HashMap<K, Vec<V>>) -> HashMap<V, Vec<K>>
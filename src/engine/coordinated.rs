@@ -0,0 +1,189 @@
+use super::{host, Engine};
+use crate::error::Result;
+use crate::logging;
+use crate::model::{BarrierGrant, BarrierReport, WireMessage};
+use std::collections::HashMap;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span};
+
+/// Bookkeeping for barrier-synchronous execution, where the first node acts as
+/// time coordinator: every round each node reports when it next has something to do,
+/// and everyone advances together to the global minimum.
+#[derive(Debug, Default)]
+pub struct Barrier {
+    /// Events received from feeding nodes since the start of the run
+    received: usize,
+    /// Events each node must have received before advancing, since the start of the run
+    /// (coordinator only)
+    expected: HashMap<String, usize>,
+    rounds: usize,
+}
+
+impl Engine {
+    pub(super) fn run_coordinated(&mut self) -> Result<()> {
+        loop {
+            self.check_listener()?;
+            let _tick = info_span!("tick", clock = self.clock).entered();
+            self.pace();
+            debug!(target: logging::NET, net = %self.net, "LOOP START");
+            let clock = self.clock;
+
+            self.fire_transitions()?;
+            debug!(target: logging::NET, net = %self.net, "AFTER INSTRUCTIONS");
+
+            let report = self.barrier_report()?;
+            self.handle_external_events()?;
+            self.external_active_events.clear();
+            debug!(target: logging::NET, net = %self.net, "AFTER EXTERNAL EVENTS");
+
+            let coordinator = self.nodes[0].clone();
+            self.send(&coordinator, report.into())?;
+            if self.is_leader() {
+                self.coordinate()?;
+            }
+
+            let since = Instant::now();
+            let grant = self.await_grant()?;
+            self.await_events(grant.expected)?;
+            self.timing.blocked_on(&coordinator, since);
+            self.barrier.rounds += 1;
+
+            self.clock = grant.granted_clock;
+            self.rewards.accrue(
+                &self.net.transitions,
+                self.clock.min(self.terminal_clock) - clock,
+            );
+            debug!(target: logging::NET, net = %self.net, "AFTER TICK");
+
+            self.handle_internal_events();
+            debug!(target: logging::NET, net = %self.net, "AFTER INTERNAL EVENTS");
+            self.end_tick();
+
+            // no event is in flight anywhere at a barrier, segments move safely
+            if let Some(nodes) = grant.membership {
+                self.rebalance(nodes)?;
+                if !self.nodes.iter().any(|node| **node == *self.node) {
+                    break;
+                }
+            }
+
+            // nothing scheduled anywhere means the whole cluster is done
+            if self.clock >= self.terminal_clock || self.clock == usize::MAX {
+                break;
+            }
+        }
+
+        info!(rounds = self.barrier.rounds, "BARRIER");
+
+        Ok(())
+    }
+
+    fn barrier_report(&self) -> Result<BarrierReport> {
+        let next_clock = self.next_scheduled_clock();
+
+        let mut sent = HashMap::new();
+        for event in &self.external_active_events {
+            let fed_node = host(&self.transition2node, event.transition_id)?.to_string();
+            *sent.entry(fed_node).or_insert(0) += 1;
+        }
+
+        Ok(BarrierReport {
+            reporting_node: self.node.to_string(),
+            next_clock,
+            sent,
+            min_sent_clock: self
+                .external_active_events
+                .iter()
+                .map(|event| event.clock)
+                .min()
+                .unwrap_or(usize::MAX),
+        })
+    }
+
+    /// Gathers one report per node and grants everyone the global next event time.
+    fn coordinate(&mut self) -> Result<()> {
+        let mut granted_clock = usize::MAX;
+        for _ in 0..self.nodes.len() {
+            let report = self.barrier_reports.recv()?;
+            granted_clock = granted_clock
+                .min(report.next_clock)
+                .min(report.min_sent_clock);
+            report.sent.into_iter().for_each(|(fed_node, count)| {
+                *self.barrier.expected.entry(fed_node).or_default() += count;
+            });
+        }
+
+        // joining nodes are granted the round as well, and leaving ones still are
+        let membership = self.next_membership();
+        let mut recipients = self.nodes.clone();
+        membership.iter().flatten().for_each(|node| {
+            if !recipients.contains(node) {
+                recipients.push(node.clone());
+            }
+        });
+        recipients.iter().try_for_each(|node| {
+            let grant = BarrierGrant {
+                granted_clock,
+                expected: self.barrier.expected.get(node).copied().unwrap_or_default(),
+                membership: membership.clone(),
+            };
+            self.send(node, grant.into())
+        })
+    }
+
+    fn await_grant(&mut self) -> Result<BarrierGrant> {
+        loop {
+            self.drain_feeding_channels()?;
+            match self.barrier_grants.recv_timeout(Duration::from_millis(1)) {
+                Ok(grant) => return Ok(grant),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(std::sync::mpsc::RecvError.into())
+                }
+            }
+        }
+    }
+
+    /// Events sent during the round may still be on the wire when the grant arrives.
+    fn await_events(&mut self, expected: usize) -> Result<()> {
+        while self.barrier.received < expected {
+            if !self.drain_feeding_channels()? {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves every received event to the internal queue, returning whether there was any.
+    fn drain_feeding_channels(&mut self) -> Result<bool> {
+        let mut events = std::mem::take(&mut self.inbox);
+        events.extend(
+            self.feeding_nodes
+                .iter()
+                .flat_map(|feeding_node| feeding_node.channel.try_iter()),
+        );
+        let any = !events.is_empty();
+
+        let applied = events.drain(..).try_for_each(|event| -> Result<()> {
+            self.observe_channel(&event)?;
+            if let WireMessage::Active(mut event) = event {
+                self.observe_received(&event);
+                // the grant already covered this clock, the event can only be applied late
+                if event.clock < self.clock {
+                    self.straggler(&event)?;
+                    event.clock = self.clock;
+                }
+                self.barrier.received += 1;
+                self.internal_active_events.push(event);
+            }
+
+            Ok(())
+        });
+        self.inbox = events;
+        applied?;
+
+        Ok(any)
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Engine, Wait};
+use crate::error::Result;
+use crate::model::{DeadlockMarker, WireMessage};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Bookkeeping for conservative synchronization without null messages. Nodes simply
+/// block; the first node periodically circulates a marker and, once two consecutive
+/// passes find every node blocked with nothing in flight, it announces the earliest
+/// scheduled clock in the cluster so every channel may safely advance to it.
+#[derive(Debug)]
+pub struct Deadlock {
+    interval: Duration,
+    last_pass: Instant,
+    /// Whether the leader is waiting for its marker to come back
+    in_progress: bool,
+    pass: usize,
+    /// Consecutive passes that found the whole cluster blocked (leader only)
+    clean_passes: usize,
+    /// Whether this node is currently waiting on a feeding node
+    blocked: bool,
+    /// Whether this node made progress since the marker last visited it
+    woke: bool,
+    pub sent: usize,
+    pub received: usize,
+    recoveries: usize,
+}
+
+impl Deadlock {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_pass: Instant::now(),
+            in_progress: false,
+            pass: 0,
+            clean_passes: 0,
+            blocked: false,
+            woke: true,
+            sent: 0,
+            received: 0,
+            recoveries: 0,
+        }
+    }
+}
+
+impl Engine {
+    /// Waits for the next event of a feeding node, taking part in deadlock detection
+    /// meanwhile. Returns `None` if a recovery advanced the channel instead.
+    pub(super) fn receive_or_recover(&mut self, index: usize) -> Result<Option<WireMessage>> {
+        let clock = self.feeding_nodes[index].clock;
+        self.deadlock.blocked = true;
+
+        let mut wait = Wait::new();
+        let event = loop {
+            match self.feeding_nodes[index].channel.try_recv() {
+                Ok(event) => break Some(event),
+                Err(TryRecvError::Disconnected) => break None,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            self.poll_deadlock_markers()?;
+            if self.feeding_nodes[index].clock != clock || self.is_quiescent() {
+                break None;
+            }
+
+            match self.feeding_nodes[index]
+                .channel
+                .recv_timeout(Duration::from_millis(1))
+            {
+                Ok(event) => break Some(event),
+                Err(RecvTimeoutError::Disconnected) => break None,
+                Err(RecvTimeoutError::Timeout) => self.watch(index, &mut wait)?,
+            }
+        };
+
+        self.deadlock.blocked = false;
+        self.deadlock.woke = true;
+
+        Ok(event)
+    }
+
+    /// Starts a detection pass when due, and handles every marker received.
+    pub(super) fn poll_deadlock_markers(&mut self) -> Result<()> {
+        if self.is_leader()
+            && !self.deadlock.in_progress
+            && self.deadlock.last_pass.elapsed() >= self.deadlock.interval
+        {
+            self.start_detection_pass()?;
+        }
+
+        while let Ok(marker) = self.deadlock_markers.try_recv() {
+            if let Some(clock) = marker.recovery {
+                self.recover(clock);
+            } else if self.is_leader() {
+                self.complete_detection_pass(marker)?;
+            } else {
+                self.forward_marker(marker)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_detection_pass(&mut self) -> Result<()> {
+        self.deadlock.in_progress = true;
+        self.deadlock.pass += 1;
+        let marker = DeadlockMarker {
+            pass: self.deadlock.pass,
+            all_blocked: true,
+            in_transit: 0,
+            min_next: usize::MAX,
+            recovery: None,
+        };
+        let next_node = self.next_node()?;
+        self.send(&next_node, marker.into())
+    }
+
+    fn add_blocked_state(&mut self, marker: &mut DeadlockMarker) {
+        marker.all_blocked &= self.deadlock.blocked && !self.deadlock.woke;
+        marker.in_transit += self.deadlock.sent as isize - self.deadlock.received as isize;
+        marker.min_next = marker.min_next.min(self.next_scheduled_clock());
+        self.deadlock.woke = false;
+    }
+
+    fn forward_marker(&mut self, mut marker: DeadlockMarker) -> Result<()> {
+        self.add_blocked_state(&mut marker);
+        let next_node = self.next_node()?;
+        self.send(&next_node, marker.into())
+    }
+
+    /// A single pass may see nodes at different moments, so only two consecutive
+    /// clean passes prove the cluster is deadlocked.
+    fn complete_detection_pass(&mut self, mut marker: DeadlockMarker) -> Result<()> {
+        self.add_blocked_state(&mut marker);
+        self.deadlock.in_progress = false;
+
+        if !marker.all_blocked || marker.in_transit != 0 {
+            self.deadlock.clean_passes = 0;
+            self.deadlock.last_pass = Instant::now();
+            return Ok(());
+        }
+
+        self.deadlock.clean_passes += 1;
+        if self.deadlock.clean_passes < 2 {
+            return self.start_detection_pass();
+        }
+
+        self.deadlock.clean_passes = 0;
+        self.deadlock.last_pass = Instant::now();
+
+        let announcement = DeadlockMarker {
+            recovery: Some(marker.min_next),
+            ..marker
+        };
+        let others = self
+            .nodes
+            .iter()
+            .filter(|node| **node != *self.node)
+            .cloned()
+            .collect::<Vec<_>>();
+        others
+            .iter()
+            .try_for_each(|node| self.send(node, announcement.clone().into()))?;
+        self.recover(marker.min_next);
+
+        Ok(())
+    }
+
+    /// Nothing anywhere is scheduled before `clock`, so no channel can deliver anything
+    /// earlier either. With nothing scheduled at all the simulation is over.
+    fn recover(&mut self, clock: usize) {
+        self.deadlock.recoveries += 1;
+        info!(
+            clock,
+            recoveries = self.deadlock.recoveries,
+            "DEADLOCK RECOVERY"
+        );
+
+        if clock == usize::MAX {
+            self.terminal_clock = self.clock;
+        }
+
+        let safe_clock = clock.saturating_add(self.step);
+        for index in 0..self.feeding_nodes.len() {
+            let clock = self.feeding_nodes[index].clock.max(safe_clock);
+            self.set_feeding_clock(index, clock);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use super::Engine;
+use crate::error::Result;
+use crate::model::{ActiveEvent, GvtToken};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Global Virtual Time bookkeeping, following Mattern's algorithm: a token circulates
+/// around the ring of nodes, each node switching to the token's round (its color) and
+/// adding how many events of the previous round it still has in flight. Once the
+/// initiator sees none left in flight, the minimum it collected is the GVT.
+#[derive(Debug)]
+pub struct Gvt {
+    interval: Duration,
+    last_round: Instant,
+    /// Round this node has joined, outgoing events are stamped with it
+    color: usize,
+    /// Events sent minus events received, per color
+    in_transit: HashMap<usize, isize>,
+    /// Lowest clock sent since joining the current round
+    min_sent: usize,
+    /// Whether the initiator is waiting for its token to come back
+    in_progress: bool,
+    /// Latest GVT known to this node, no rollback can ever go below it
+    pub value: usize,
+}
+
+impl Gvt {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_round: Instant::now(),
+            color: 0,
+            in_transit: HashMap::new(),
+            min_sent: usize::MAX,
+            in_progress: false,
+            value: 0,
+        }
+    }
+
+    fn join(&mut self, round: usize) {
+        if self.color < round {
+            self.color = round;
+            self.min_sent = usize::MAX;
+        }
+    }
+}
+
+impl Engine {
+    /// Stamps an outgoing event with the current color and counts it as in flight.
+    pub(super) fn stamp_sent(&mut self, event: &mut ActiveEvent) {
+        event.color = self.gvt.color;
+        *self.gvt.in_transit.entry(event.color).or_default() += 1;
+        self.gvt.min_sent = self.gvt.min_sent.min(event.clock);
+    }
+
+    pub(super) fn account_received(&mut self, event: &ActiveEvent) {
+        *self.gvt.in_transit.entry(event.color).or_default() -= 1;
+    }
+
+    /// Starts a new round when due, and handles every token and announcement received.
+    pub(super) fn poll_gvt(&mut self) -> Result<()> {
+        if self.is_leader()
+            && !self.gvt.in_progress
+            && self.gvt.last_round.elapsed() >= self.gvt.interval
+        {
+            self.gvt.join(self.gvt.color + 1);
+            self.gvt.in_progress = true;
+            self.pass_gvt_token(self.gvt.color)?;
+        }
+
+        while let Ok(token) = self.gvt_tokens.try_recv() {
+            if let Some(gvt) = token.gvt {
+                self.update_gvt(gvt);
+            } else if self.is_leader() {
+                self.complete_gvt_round(token)?;
+            } else {
+                self.forward_gvt_token(token)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Local virtual time: nothing this node does can happen before it. An idle node
+    /// only acts on events yet to arrive, which the other nodes and those in flight
+    /// already account for.
+    fn local_virtual_time(&self) -> usize {
+        if self.is_idle() {
+            return self.terminal_clock;
+        }
+        self.time_warp
+            .min_unprocessed_clock()
+            .map_or(self.clock, |clock| clock.min(self.clock))
+    }
+
+    fn contribute(&mut self, token: &mut GvtToken) {
+        self.gvt.join(token.round);
+        token.in_transit += self
+            .gvt
+            .in_transit
+            .get(&(token.round - 1))
+            .copied()
+            .unwrap_or_default();
+        token.min_clock = token
+            .min_clock
+            .min(self.local_virtual_time())
+            .min(self.gvt.min_sent);
+    }
+
+    fn pass_gvt_token(&mut self, round: usize) -> Result<()> {
+        let token = GvtToken {
+            round,
+            in_transit: 0,
+            min_clock: usize::MAX,
+            gvt: None,
+        };
+        let next_node = self.next_node()?;
+        self.send(&next_node, token.into())
+    }
+
+    fn forward_gvt_token(&mut self, mut token: GvtToken) -> Result<()> {
+        self.contribute(&mut token);
+        let next_node = self.next_node()?;
+        self.send(&next_node, token.into())
+    }
+
+    /// The initiator contributes last; events of the previous round still in flight
+    /// mean another pass is needed before the collected minimum can be trusted.
+    fn complete_gvt_round(&mut self, mut token: GvtToken) -> Result<()> {
+        self.contribute(&mut token);
+
+        if token.in_transit != 0 {
+            return self.pass_gvt_token(token.round);
+        }
+
+        self.gvt.in_progress = false;
+        self.gvt.last_round = Instant::now();
+        self.update_gvt(token.min_clock);
+
+        let announcement = GvtToken {
+            gvt: Some(self.gvt.value),
+            ..token
+        };
+        let others = self
+            .nodes
+            .iter()
+            .filter(|node| **node != *self.node)
+            .cloned()
+            .collect::<Vec<_>>();
+        others
+            .iter()
+            .try_for_each(|node| self.send(node, announcement.clone().into()))
+    }
+
+    fn update_gvt(&mut self, gvt: usize) {
+        if gvt > self.gvt.value {
+            self.gvt.value = gvt;
+            let reclaimed = self.time_warp.fossil_collect(gvt);
+            info!(gvt, reclaimed, "GVT");
+        }
+    }
+}
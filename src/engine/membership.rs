@@ -0,0 +1,262 @@
+use super::{open_channel, Engine, Topology};
+use crate::config::Config;
+use crate::error::Result;
+use crate::model::{FeedingClocks, Handoff};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use tracing::{info, warn};
+
+/// Bookkeeping for membership changes while the cluster runs, with --synchronization
+/// coordinated: the coordinator applies the operator's changes at the next barrier, where
+/// no event is in flight, and every node hands the state of the transitions it gives up
+/// over to their new host.
+#[derive(Debug, Default)]
+pub struct Membership {
+    /// Changes typed by the operator (coordinator only)
+    changes: Option<Receiver<Change>>,
+    /// Changes typed but not applied yet, one being applied per barrier (coordinator only)
+    pending: VecDeque<Change>,
+    /// Whether this node waits to be admitted into a running cluster
+    pub joining: bool,
+}
+
+#[derive(Debug)]
+enum Change {
+    Join(String),
+    Leave(String),
+}
+
+impl Membership {
+    pub fn new(config: &Config, is_coordinator: bool) -> Self {
+        Self {
+            changes: (config.dynamic_membership && is_coordinator).then(read_changes),
+            pending: VecDeque::new(),
+            joining: config.join.is_some(),
+        }
+    }
+}
+
+/// Reads `join <node>` and `leave <node>` lines from the standard input, on a thread of
+/// its own.
+fn read_changes() -> Receiver<Change> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for line in std::io::stdin()
+            .lock()
+            .lines()
+            .map_while(std::io::Result::ok)
+        {
+            let change = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["join", node] => Change::Join(node.to_string()),
+                ["leave", node] => Change::Leave(node.to_string()),
+                [] => continue,
+                _ => {
+                    warn!(
+                        %line,
+                        reason = "expected join <node> or leave <node>",
+                        "IGNORED MEMBERSHIP CHANGE"
+                    );
+                    continue;
+                }
+            };
+            if tx.send(change).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+impl Engine {
+    /// Nodes hosting the segments from the next barrier on, if the operator changed them
+    /// (coordinator only). A single change applies per barrier, so that a joining node,
+    /// which never knew the previous membership, hears from every other member. Joining
+    /// nodes come last, so segments move as little as possible.
+    pub(super) fn next_membership(&mut self) -> Option<Vec<String>> {
+        let changes = self.membership.changes.as_ref()?;
+        self.membership.pending.extend(changes.try_iter());
+
+        let mut nodes = self.nodes.clone();
+        while let Some(change) = self.membership.pending.pop_front() {
+            match change {
+                Change::Join(node) if nodes.contains(&node) => warn!(
+                    joining = %node,
+                    reason = "a member already",
+                    "IGNORED MEMBERSHIP CHANGE"
+                ),
+                Change::Join(node) if nodes.len() >= self.segments.len() => warn!(
+                    joining = %node,
+                    reason = "every node already hosts a single segment",
+                    "IGNORED MEMBERSHIP CHANGE"
+                ),
+                Change::Join(node) => nodes.push(node),
+                Change::Leave(node) if *node == *self.node => warn!(
+                    leaving = %node,
+                    reason = "the coordinator stays until the end",
+                    "IGNORED MEMBERSHIP CHANGE"
+                ),
+                Change::Leave(node) => match nodes.iter().position(|member| *member == node) {
+                    Some(index) => {
+                        nodes.remove(index);
+                    }
+                    None => warn!(
+                        leaving = %node,
+                        reason = "not a member",
+                        "IGNORED MEMBERSHIP CHANGE"
+                    ),
+                },
+            }
+            if nodes != self.nodes {
+                break;
+            }
+        }
+
+        // accepted before they are granted, as they tell the coordinator once admitted
+        self.transport.admit(&nodes);
+        (nodes != self.nodes).then_some(nodes)
+    }
+
+    /// Waits for the coordinator to admit this node into the running cluster, then takes
+    /// its segments over.
+    pub(super) fn await_admission(&mut self) -> Result<()> {
+        info!("JOINING awaiting admission");
+        let grant = self.barrier_grants.recv_timeout(self.startup_timeout)?;
+        self.clock = grant.granted_clock;
+
+        self.rebalance(grant.membership.unwrap_or_default())
+    }
+
+    /// Spreads the segments over `nodes` instead: hands the state of every transition
+    /// moving elsewhere over to its new host, takes over that of every transition moving
+    /// here, and sets the channels up again. Only ever called at a barrier.
+    pub(super) fn rebalance(&mut self, nodes: Vec<String>) -> Result<()> {
+        self.transport.admit(&nodes);
+        let topology = Topology::new(&self.segments, &nodes, &self.node)?;
+
+        // channels from new feeding nodes are ready before this node hands off, and no
+        // node sends events before it heard from every previous member
+        self.feeding_nodes
+            .retain(|feeding_node| topology.feeding_nodes.contains(&feeding_node.name));
+        self.feeding_node2channel
+            .lock()
+            .unwrap()
+            .retain(|feeding_node, _| topology.feeding_nodes.contains(feeding_node));
+        topology.feeding_nodes.iter().for_each(|feeding_node| {
+            if !self
+                .feeding_nodes
+                .iter()
+                .any(|candidate| candidate.name == *feeding_node)
+            {
+                let feeding_node =
+                    open_channel(feeding_node, &self.feeding_node2channel, self.receive_queue);
+                self.feeding_nodes.push(feeding_node);
+            }
+        });
+        self.feeding_clocks = FeedingClocks::new(&self.feeding_nodes);
+
+        // a joining node hears from every other member, the change being its admission
+        let previous = if self.membership.joining {
+            &nodes
+        } else {
+            &self.nodes
+        };
+        let previous = previous
+            .iter()
+            .filter(|node| **node != *self.node)
+            .cloned()
+            .collect::<Vec<_>>();
+        let coordinator = self.nodes[0].clone();
+        let mut handoffs = vec![];
+        // a joining node only accepts the coordinator until it is admitted: it tells the
+        // coordinator once it accepts everyone, the coordinator hands off once every
+        // joining node did, and everyone else hands off once the coordinator did. A joining
+        // node has nothing to hand off, and may not be accepted by everyone yet
+        if self.membership.joining {
+            let ready = self.ready(&coordinator, false);
+            self.send(&coordinator, ready.into())?;
+        } else {
+            if *self.node == coordinator {
+                let joining = nodes.iter().filter(|node| !self.nodes.contains(node));
+                for _ in joining {
+                    let ready = self.readies.recv_timeout(self.startup_timeout)?;
+                    if let Some(run_id) = &ready.run_id {
+                        self.transport.join_run(&ready.ready_node, run_id)?;
+                    }
+                }
+            } else {
+                handoffs.push(self.handoffs.recv_timeout(self.startup_timeout)?);
+            }
+
+            let peers = previous
+                .iter()
+                .chain(nodes.iter().filter(|node| !previous.contains(node)))
+                .filter(|node| **node != *self.node)
+                .cloned()
+                .collect::<Vec<_>>();
+            peers.iter().try_for_each(|peer| {
+                let handoff = Handoff {
+                    sender: self.node.to_string(),
+                    run_id: self.transport.run_id().map(str::to_string),
+                    marking: self
+                        .net
+                        .transitions
+                        .iter()
+                        .filter(|transition| topology.hosts(peer, transition.id))
+                        .map(|transition| (transition.id, (transition.clock, transition.value)))
+                        .collect::<BTreeMap<_, _>>(),
+                    internal_active_events: self
+                        .internal_active_events
+                        .iter()
+                        .filter(|event| topology.hosts(peer, event.transition_id))
+                        .cloned()
+                        .collect(),
+                };
+                self.send(peer, handoff.into())
+            })?;
+        }
+
+        let mut marking = self
+            .net
+            .transitions
+            .iter()
+            .map(|transition| (transition.id, (transition.clock, transition.value)))
+            .collect::<HashMap<_, _>>();
+        self.internal_active_events
+            .retain(|event| topology.hosts(&self.node, event.transition_id));
+        while handoffs.len() < previous.len() {
+            handoffs.push(self.handoffs.recv_timeout(self.startup_timeout)?);
+        }
+        for handoff in handoffs {
+            if let Some(run_id) = &handoff.run_id {
+                self.transport.join_run(&handoff.sender, run_id)?;
+            }
+            info!(
+                from = %handoff.sender,
+                transitions = handoff.marking.len(),
+                events = handoff.internal_active_events.len(),
+                "HANDOFF"
+            );
+            marking.extend(handoff.marking);
+            self.internal_active_events
+                .extend(handoff.internal_active_events);
+        }
+
+        let mut net = topology.net;
+        marking
+            .iter()
+            .for_each(|(&id, &(clock, value))| net.set_marking(id, clock, value));
+        self.rewards.track(&net.transitions);
+        self.net = net;
+        self.transition2node = topology.transition2node;
+        self.fed_nodes = topology.fed_nodes;
+        self.lookahead = topology.lookahead;
+        self.nodes = nodes;
+        self.membership.joining = false;
+        info!(nodes = ?self.nodes, "MEMBERSHIP");
+
+        Ok(())
+    }
+}
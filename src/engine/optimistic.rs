@@ -0,0 +1,498 @@
+use super::{host, Engine, EventQueue};
+use crate::config::Synchronization;
+use crate::error::{AppError, Result};
+use crate::firings::FiringStats;
+use crate::logging;
+use crate::model::{ActiveEvent, Net, NodeId, WireMessage};
+use crate::reward::Rewards;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span};
+
+/// Bookkeeping for optimistic (Time Warp) synchronization.
+#[derive(Debug, Default)]
+pub struct TimeWarp {
+    /// Snapshots taken on reaching every processed clock, before any of its events are
+    /// applied, oldest first
+    saved_states: Vec<SavedState>,
+    /// Events received from feeding nodes, kept so they can be replayed after a rollback
+    input_queue: Vec<ReceivedEvent>,
+    /// Events sent to fed nodes, kept so they can be cancelled after a rollback
+    output_queue: Vec<SentEvent>,
+    /// Anti-messages that arrived before the event they cancel
+    pending_anti: Vec<ActiveEvent>,
+    rollbacks: usize,
+}
+
+impl TimeWarp {
+    /// Reclaims everything no rollback can need any more once GVT reached `gvt`.
+    /// The newest state saved up to GVT is kept, as a straggler at GVT restores it.
+    /// Returns how many entries were reclaimed.
+    pub(super) fn fossil_collect(&mut self, gvt: usize) -> usize {
+        let Some(keep) = self
+            .saved_states
+            .iter()
+            .rposition(|state| state.clock <= gvt)
+        else {
+            return 0;
+        };
+        let horizon = self.saved_states[keep].clock;
+
+        let before = self.saved_states.len()
+            + self.input_queue.len()
+            + self.output_queue.len()
+            + self.pending_anti.len();
+
+        self.saved_states.drain(..keep);
+        self.input_queue
+            .retain(|received| !received.processed || received.event.clock >= horizon);
+        self.output_queue.retain(|sent| sent.clock >= horizon);
+        self.pending_anti.retain(|anti| anti.clock >= gvt);
+
+        before
+            - self.saved_states.len()
+            - self.input_queue.len()
+            - self.output_queue.len()
+            - self.pending_anti.len()
+    }
+
+    pub(super) fn min_unprocessed_clock(&self) -> Option<usize> {
+        self.unprocessed().map(|event| event.clock).min()
+    }
+
+    /// Received events not applied yet.
+    pub(super) fn unprocessed(&self) -> impl Iterator<Item = &ActiveEvent> {
+        self.input_queue
+            .iter()
+            .filter(|received| !received.processed)
+            .map(|received| &received.event)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SavedState {
+    clock: usize,
+    net: Net,
+    internal_active_events: EventQueue,
+    rewards: Rewards,
+    firing_stats: Option<FiringStats>,
+}
+
+#[derive(Debug)]
+struct ReceivedEvent {
+    event: ActiveEvent,
+    processed: bool,
+}
+
+#[derive(Debug)]
+struct SentEvent {
+    clock: usize,
+    fed_node: NodeId,
+    event: ActiveEvent,
+}
+
+impl Engine {
+    pub(super) fn run_optimistic(&mut self) -> Result<()> {
+        // the events of clock 0 can be rolled back like those of any later clock
+        self.save_state();
+        // nodes may only stop once no rollback can take any of them back before the end
+        while self.gvt.value < self.terminal_clock {
+            self.check_listener()?;
+            self.receive_optimistic()?;
+            self.poll_gvt()?;
+
+            if self.clock < self.terminal_clock
+                && self.clock <= self.optimism_horizon()
+                && !self.is_idle()
+            {
+                self.optimistic_tick()?;
+            } else {
+                // nothing left to simulate, nothing to simulate until an event arrives, or
+                // too far ahead: wait for stragglers or for GVT to catch up
+                let since = Instant::now();
+                thread::sleep(Duration::from_millis(1));
+                self.timing.idle(since);
+            }
+        }
+
+        // a node idle until the end does nothing in between
+        let clock = self.clock;
+        self.clock = self.clock.max(self.terminal_clock);
+        self.rewards
+            .accrue(&self.net.transitions, self.clock - clock);
+        info!(rollbacks = self.time_warp.rollbacks, "TIME WARP");
+
+        Ok(())
+    }
+
+    /// Fires the current clock, keeping what it sends in case of a rollback, and moves on
+    /// to the next clock with pending events.
+    fn optimistic_tick(&mut self) -> Result<()> {
+        let _tick = info_span!("tick", clock = self.clock).entered();
+        self.pace();
+        debug!(target: logging::NET, net = %self.net, "LOOP START");
+
+        self.fire_transitions()?;
+        debug!(target: logging::NET, net = %self.net, "AFTER INSTRUCTIONS");
+
+        self.record_sent_events()?;
+        self.handle_external_events()?;
+        self.external_active_events.clear();
+        debug!(target: logging::NET, net = %self.net, "AFTER EXTERNAL EVENTS");
+
+        self.advance_optimistic();
+        debug!(target: logging::NET, net = %self.net, "AFTER INTERNAL EVENTS");
+        self.end_tick();
+
+        Ok(())
+    }
+
+    /// Whether nothing is due at the current clock or after it, so only events yet to
+    /// arrive can give this node anything to do.
+    pub(super) fn is_idle(&self) -> bool {
+        self.internal_active_events.is_empty()
+            && self.time_warp.min_unprocessed_clock().is_none()
+            && self.net.next_enabled_clock(self.clock).is_none()
+    }
+
+    /// Latest clock this node may process before waiting for GVT to advance.
+    fn optimism_horizon(&self) -> usize {
+        match self.synchronization {
+            Synchronization::TimeWindow => self.gvt.value.saturating_add(self.time_window),
+            _ => usize::MAX,
+        }
+    }
+
+    fn save_state(&mut self) {
+        self.time_warp.saved_states.push(SavedState {
+            clock: self.clock,
+            net: self.net.clone(),
+            internal_active_events: self.internal_active_events.clone(),
+            rewards: self.rewards.clone(),
+            firing_stats: self.firing_stats.clone(),
+        });
+    }
+
+    fn record_sent_events(&mut self) -> Result<()> {
+        let mut events = std::mem::take(&mut self.external_active_events);
+        events.iter_mut().for_each(|event| self.stamp_sent(event));
+
+        let sent = events
+            .iter()
+            .map(|event| {
+                Ok(SentEvent {
+                    clock: self.clock,
+                    fed_node: host(&self.transition2node, event.transition_id)?.clone(),
+                    event: event.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>();
+        self.external_active_events = events;
+        self.time_warp.output_queue.extend(sent?);
+
+        Ok(())
+    }
+
+    /// Moves to the next clock with pending events and applies them, local and received alike.
+    /// With nothing pending at all the node waits on the clock right after, as running
+    /// ahead would only turn whatever arrives next into a straggler.
+    fn advance_optimistic(&mut self) {
+        let clock = self.clock;
+        self.clock = self
+            .internal_active_events
+            .next_clock()
+            .into_iter()
+            .chain(self.time_warp.min_unprocessed_clock())
+            .min()
+            .unwrap_or_else(|| match self.net.next_enabled_clock(clock + 1) {
+                Some(_) => self.next_clock(),
+                None => clock + 1,
+            });
+        self.rewards.accrue(
+            &self.net.transitions,
+            self.clock.min(self.terminal_clock) - clock,
+        );
+
+        self.save_state();
+        self.apply_due_events();
+    }
+
+    /// Applies the local and received events due at the current clock, before it fires.
+    fn apply_due_events(&mut self) {
+        self.handle_internal_events();
+        self.apply_received_events();
+    }
+
+    fn apply_received_events(&mut self) {
+        let clock = self.clock;
+        let mut received = self
+            .time_warp
+            .input_queue
+            .iter_mut()
+            .filter(|received| !received.processed && received.event.clock == clock)
+            .collect::<Vec<_>>();
+        received.sort_by(|a, b| a.event.order_key().cmp(&b.event.order_key()));
+
+        received.into_iter().for_each(|received| {
+            let event = &received.event;
+            self.net
+                .set_marking(event.transition_id, event.clock, event.value);
+            received.processed = true;
+        });
+    }
+
+    fn receive_optimistic(&mut self) -> Result<()> {
+        let mut events = std::mem::take(&mut self.inbox);
+        events.extend(
+            self.feeding_nodes
+                .iter()
+                .flat_map(|feeding_node| feeding_node.channel.try_iter()),
+        );
+
+        let applied = events.drain(..).try_for_each(|event| {
+            self.observe_channel(&event)?;
+            match event {
+                WireMessage::Active(event) => {
+                    self.observe_received(&event);
+                    self.account_received(&event);
+                    if event.anti {
+                        self.annihilate(event)
+                    } else {
+                        self.enqueue(event)
+                    }
+                }
+                WireMessage::Passive(event) => {
+                    debug!(target: logging::NETWORK, ?event, "RECEIVED");
+                    if let Some(index) = self
+                        .feeding_nodes
+                        .iter()
+                        .position(|feeding_node| feeding_node.name == event.feeding_node)
+                    {
+                        self.set_feeding_clock(index, event.clock);
+                    }
+                    Ok(())
+                }
+                WireMessage::Control(_) | WireMessage::Batch(_) => Err(AppError::MalformedEvent {
+                    reason: "a control message on an event channel".to_string(),
+                }),
+            }
+        });
+        self.inbox = events;
+        applied
+    }
+
+    fn enqueue(&mut self, event: ActiveEvent) -> Result<()> {
+        if let Some(index) = self
+            .time_warp
+            .pending_anti
+            .iter()
+            .position(|anti| anti.cancels(&event))
+        {
+            self.time_warp.pending_anti.remove(index);
+            return Ok(());
+        }
+
+        // the current clock has not fired yet, an event due at it is still on time
+        let late = event.clock < self.clock;
+        let due = event.clock <= self.clock;
+        if late {
+            self.straggler(&event)?;
+            self.rollback(event.clock)?;
+        }
+
+        self.time_warp.input_queue.push(ReceivedEvent {
+            event,
+            processed: false,
+        });
+
+        if late {
+            self.apply_due_events();
+        } else if due {
+            self.apply_received_events();
+        }
+
+        Ok(())
+    }
+
+    fn annihilate(&mut self, anti: ActiveEvent) -> Result<()> {
+        let Some(index) = self
+            .time_warp
+            .input_queue
+            .iter()
+            .position(|received| anti.cancels(&received.event))
+        else {
+            self.time_warp.pending_anti.push(anti);
+            return Ok(());
+        };
+
+        let processed = self.time_warp.input_queue[index].processed;
+        if processed {
+            self.rollback(anti.clock)?;
+        }
+        self.time_warp.input_queue.remove(index);
+        // the events left at the clock rolled back to, without the cancelled one
+        if processed {
+            self.apply_due_events();
+        }
+
+        Ok(())
+    }
+
+    /// Restores the state saved on reaching `clock`, or the latest one before it, and
+    /// cancels every event sent since. None of the events due at that clock is applied
+    /// yet, the caller applies them once it settled which ones stand.
+    fn rollback(&mut self, clock: usize) -> Result<()> {
+        let saved_states = &self.time_warp.saved_states;
+        let Some(index) = saved_states
+            .iter()
+            .rposition(|state| state.clock <= clock)
+            .or((!saved_states.is_empty()).then_some(0))
+        else {
+            return Ok(());
+        };
+
+        let from = self.clock;
+        // kept, as a later straggler may take the node back to the same clock
+        self.time_warp.saved_states.truncate(index + 1);
+        let state = self.time_warp.saved_states[index].clone();
+
+        self.clock = state.clock;
+        self.net = state.net;
+        self.internal_active_events = state.internal_active_events;
+        self.rewards = state.rewards;
+        self.firing_stats = state.firing_stats;
+        self.time_warp.rollbacks += 1;
+
+        self.time_warp
+            .input_queue
+            .iter_mut()
+            .filter(|received| received.event.clock >= state.clock)
+            .for_each(|received| received.processed = false);
+
+        let (cancelled, kept) = std::mem::take(&mut self.time_warp.output_queue)
+            .into_iter()
+            .partition::<Vec<_>, _>(|sent| sent.clock >= state.clock);
+        self.time_warp.output_queue = kept;
+
+        info!(
+            from,
+            to = self.clock,
+            cancelled = cancelled.len(),
+            "ROLLBACK"
+        );
+
+        cancelled.into_iter().try_for_each(|sent| {
+            let mut anti = ActiveEvent {
+                anti: true,
+                channel_seq: self.next_channel_seq(&sent.fed_node),
+                lamport: self.lamport.tick(),
+                ..sent.event
+            };
+            self.stamp_sent(&mut anti);
+            self.send(&sent.fed_node, anti.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing;
+    use crate::clocks::VectorClock;
+    use crate::model::{ActiveEvent, NodeId};
+    use std::time::Duration;
+
+    /// Event from a feeding node setting `transition_id` to `value` at `clock`.
+    fn received(transition_id: usize, value: isize, clock: usize) -> ActiveEvent {
+        ActiveEvent {
+            feeding_node: NodeId::from("elsewhere"),
+            transition_id,
+            value,
+            clock,
+            anti: false,
+            color: 0,
+            seq: 0,
+            vector_clock: VectorClock::default(),
+            channel_seq: 0,
+            lamport: 0,
+            trace: None,
+        }
+    }
+
+    #[test]
+    fn straggler_rolls_back_once_to_its_clock() {
+        let nets = testing::generated("straggler", 20, 2, 1, 0);
+        let mut engine = testing::single_node(
+            "straggler",
+            &nets,
+            &["--synchronization", "optimistic", "--terminal-clock", "100"],
+        );
+        engine.save_state();
+        let mut visited = vec![];
+        while visited.len() < 8 {
+            visited.push(engine.clock);
+            engine.optimistic_tick().expect("Failed to tick");
+        }
+
+        let clock = visited[visited.len() / 2];
+        let straggler = received(engine.net.transitions[0].id, 1, clock);
+        engine
+            .enqueue(straggler)
+            .expect("Failed to take the straggler");
+        assert_eq!(engine.time_warp.rollbacks, 1);
+        assert_eq!(engine.clock, clock);
+        assert!(engine
+            .time_warp
+            .input_queue
+            .iter()
+            .all(|received| received.processed));
+
+        // firing on from there meets no straggler of its own making
+        for _ in 0..8 {
+            engine.optimistic_tick().expect("Failed to tick");
+        }
+        assert_eq!(engine.time_warp.rollbacks, 1);
+    }
+
+    #[test]
+    fn two_nodes_finish_as_coordinated_ones_do() {
+        let nets = testing::generated("two-nodes", 20, 2, 2, 1);
+        let run = |synchronization| {
+            testing::run_cluster(
+                &format!("two-nodes-{synchronization}"),
+                &nets,
+                2,
+                &[
+                    "--synchronization",
+                    synchronization,
+                    "--terminal-clock",
+                    "30",
+                ],
+                Duration::from_secs(60),
+            )
+        };
+
+        let coordinated = run("coordinated");
+        assert_eq!(run("optimistic"), coordinated);
+        assert_eq!(run("time-window"), coordinated);
+    }
+
+    #[test]
+    fn idle_node_waits_for_what_arrives_next() {
+        // every transition fires once at clock 0, and nothing else ever happens
+        let nets = testing::generated("idle", 20, 0, 1, 0);
+        let mut engine = testing::single_node(
+            "idle",
+            &nets,
+            &["--synchronization", "optimistic", "--terminal-clock", "100"],
+        );
+        engine.save_state();
+        engine.optimistic_tick().expect("Failed to tick");
+        assert!(engine.is_idle());
+        assert_eq!(engine.clock, 1);
+
+        let event = received(engine.net.transitions[0].id, 0, 5);
+        engine.enqueue(event).expect("Failed to take the event");
+        assert!(!engine.is_idle());
+        assert_eq!(engine.time_warp.rollbacks, 0);
+    }
+}
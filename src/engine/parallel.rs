@@ -0,0 +1,174 @@
+use super::Engine;
+use crate::clocks::VectorClock;
+use crate::error::Result;
+use crate::model::{ActiveEvent, NodeId, Transition};
+use rayon::prelude::*;
+
+impl Engine {
+    /// Fires the transitions at `enabled` positions in the net, the events of their delayed
+    /// instructions being built on every core and then scheduled one firing after another,
+    /// in the order sequential firing would, so sequence numbers, traces and the values
+    /// immediate instructions leave behind are the same as with it.
+    pub(super) fn fire_in_parallel(&mut self, enabled: &[usize]) -> Result<()> {
+        // a firing's events only depend on its own clock and duration, which no immediate
+        // instruction changes, so they can be built before any of them is applied
+        let node = &self.node;
+        let transitions = &self.net.transitions;
+        let firings: Vec<Vec<ActiveEvent>> = enabled
+            .par_iter()
+            .rev()
+            .map(|&position| delayed_events(node, &transitions[position]))
+            .collect();
+
+        for (&position, events) in enabled.iter().rev().zip(firings) {
+            self.record_firing(position);
+            self.process_immediate_instructions(position)?;
+            self.schedule(position, events);
+        }
+
+        Ok(())
+    }
+
+    /// Numbers and queues `events`, built by `delayed_events` for the transition at
+    /// `position` in the net, as firing the transitions one after another would have.
+    pub(super) fn schedule(&mut self, position: usize, events: Vec<ActiveEvent>) {
+        for (index, event) in events.into_iter().enumerate() {
+            let event = ActiveEvent {
+                seq: self.event_seq,
+                ..event
+            };
+            self.event_seq += 1;
+            let transition = &self.net.transitions[position];
+            let instruction = transition.delayed_instructions[index];
+            if instruction.is_external {
+                let trace = self
+                    .tracer
+                    .as_mut()
+                    .map(|tracer| tracer.sent(transition, &instruction, event.clock));
+                self.external_active_events
+                    .push(ActiveEvent { trace, ..event });
+            } else {
+                self.internal_active_events.push(event);
+            }
+        }
+    }
+}
+
+/// Events of the delayed instructions of `transition` firing on `node`, in their order,
+/// left unnumbered.
+pub(super) fn delayed_events(node: &NodeId, transition: &Transition) -> Vec<ActiveEvent> {
+    transition
+        .delayed_instructions
+        .iter()
+        .map(|instruction| ActiveEvent {
+            transition_id: instruction.transition_id,
+            feeding_node: node.clone(),
+            value: instruction.value,
+            clock: transition.clock + transition.duration,
+            anti: false,
+            color: 0,
+            seq: 0,
+            vector_clock: VectorClock::default(),
+            channel_seq: 0,
+            lamport: 0,
+            trace: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Engine;
+    use crate::config::Config;
+    use crate::json::{Net, Transition};
+    use crate::reward::Rewards;
+    use clap::Parser;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::path::PathBuf;
+
+    /// Transitions in each of the two segments, enough enabled at once to fire in parallel
+    const TRANSITIONS: usize = 512;
+
+    /// Folder with two segments of `TRANSITIONS` transitions, all enabled at clock 0 and
+    /// each setting three others of either segment.
+    fn nets() -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("petri-test-fired-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).expect("Failed to create the nets folder");
+        let mut rng = StdRng::seed_from_u64(7);
+        for segment in 0..2 {
+            let ia_red = (segment * TRANSITIONS..(segment + 1) * TRANSITIONS)
+                .map(|id| Transition {
+                    ii_idglobal: id,
+                    ii_valor: 0,
+                    ii_tiempo: 0,
+                    ii_duracion_disparo: rng.gen_range(1..=5),
+                    ii_listactes_iul: vec![(id as isize, 1)],
+                    ii_listactes_pul: (0..3)
+                        .map(|_| {
+                            let target = rng.gen_range(0..2 * TRANSITIONS);
+                            if target / TRANSITIONS == segment {
+                                (target as isize, 0)
+                            } else {
+                                (-(target as isize + 1), 0)
+                            }
+                        })
+                        .collect(),
+                    ib_desalida: false,
+                    reward_rate: 0.0,
+                    firing_cost: id as f64,
+                })
+                .collect();
+            let path = folder.join(format!("subnet-{segment}.json"));
+            std::fs::write(path, serde_json::to_string(&Net { ia_red }).unwrap())
+                .expect("Failed to write the net");
+        }
+        folder
+    }
+
+    /// What firing left behind on the first of two nodes: the marking, the rewards, and
+    /// the events queued for itself and emitted to the other node.
+    fn fired(args: &[&str]) -> (Vec<(usize, usize, isize)>, String, Vec<String>) {
+        let nets = nets().display().to_string();
+        let nodes = (0..2)
+            .map(|index| {
+                let socket = std::env::temp_dir().join(format!(
+                    "petri-test-fired-{}-{index}.sock",
+                    std::process::id()
+                ));
+                let _ = std::fs::remove_file(&socket);
+                socket.display().to_string()
+            })
+            .collect::<Vec<_>>();
+        let mut command = vec!["petri", "--node", &nodes[0], "--nets-folder", &nets];
+        command.extend(["--terminal-clock", "10", "--nodes", &nodes[0], &nodes[1]]);
+        command.extend(args);
+        let mut engine =
+            Engine::new(&Config::parse_from(command)).expect("Failed to start the node");
+        engine.rewards = Rewards::new(&engine.net.transitions);
+        engine.fire_transitions().expect("Failed to fire");
+
+        let marking = engine
+            .net
+            .transitions
+            .iter()
+            .map(|transition| (transition.id, transition.clock, transition.value))
+            .collect();
+        let events = engine
+            .internal_active_events
+            .iter()
+            .chain(&engine.external_active_events)
+            .map(|event| format!("{event:?}"))
+            .collect();
+        (marking, engine.rewards.to_string(), events)
+    }
+
+    #[test]
+    fn fires_as_sequential_firing_does() {
+        let sequential = fired(&[]);
+        assert!(!sequential.2.is_empty());
+        assert_eq!(fired(&["--parallel-firing"]), sequential);
+        assert_eq!(fired(&["--shards", "4"]), sequential);
+    }
+}
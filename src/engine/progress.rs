@@ -0,0 +1,85 @@
+use super::Engine;
+use std::time::{Duration, Instant};
+
+/// Bookkeeping for the progress reported on standard error every --progress seconds.
+#[derive(Debug)]
+pub struct Progress {
+    interval: Option<Duration>,
+    last_report: Instant,
+    /// Transitions fired since the start of the run
+    pub(super) fired: usize,
+    /// Transitions fired as of the last report
+    fired_then: usize,
+}
+
+impl Progress {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            last_report: Instant::now(),
+            fired: 0,
+            fired_then: 0,
+        }
+    }
+}
+
+impl Engine {
+    /// Reports the clock reached, how fast transitions fire and when the terminal clock
+    /// should be reached, once --progress seconds went by since the last report.
+    pub(super) fn report_progress(&mut self) {
+        let Some(interval) = self.progress.interval else {
+            return;
+        };
+        if self.progress.last_report.elapsed() >= interval {
+            self.print_progress(false);
+        }
+    }
+
+    /// Reports how long the whole run took, if reporting progress at all.
+    pub(super) fn report_final_progress(&mut self) {
+        if self.progress.interval.is_some() {
+            self.print_progress(true);
+        }
+    }
+
+    fn print_progress(&mut self, finished: bool) {
+        let now = Instant::now();
+        let since_last = now - self.progress.last_report;
+        let rate = (self.progress.fired - self.progress.fired_then) as f64
+            / since_last.as_secs_f64().max(f64::EPSILON);
+        self.progress.last_report = now;
+        self.progress.fired_then = self.progress.fired;
+
+        let clock = self.clock.min(self.terminal_clock);
+        let elapsed = self.started.elapsed();
+        let mut line = if self.terminal_clock == usize::MAX {
+            format!("{}: clock {clock}", self.node)
+        } else {
+            format!(
+                "{}: clock {clock}/{} ({:.1}%)",
+                self.node,
+                self.terminal_clock,
+                100.0 * clock as f64 / self.terminal_clock.max(1) as f64
+            )
+        };
+        line = format!("{line}, {rate:.0} firings/s");
+        // the pace so far is the best guess of the pace to come
+        if finished {
+            line = format!("{line}, done in {}", human(elapsed));
+        } else if clock > 0 && self.terminal_clock != usize::MAX {
+            let remaining = elapsed.mul_f64((self.terminal_clock - clock) as f64 / clock as f64);
+            line = format!("{line}, about {} left", human(remaining));
+        }
+        eprintln!("{line}");
+    }
+}
+
+/// `duration` to the second, as 1h02m, 3m05s or 12s.
+fn human(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m{seconds:02}s"),
+        (hours, minutes, _) => format!("{hours}h{minutes:02}m"),
+    }
+}
@@ -0,0 +1,75 @@
+use crate::model::ActiveEvent;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Internal active events waiting for their clock, the earliest on top, so finding and
+/// taking the next ones costs O(log n) rather than a scan of every pending event.
+#[derive(Debug, Default, Clone)]
+pub struct EventQueue {
+    heap: BinaryHeap<Queued>,
+}
+
+/// Ordered the other way around, the earliest event, ties broken by `order_key`, being
+/// the greatest one, so it tops the max-heap.
+#[derive(Debug, Clone)]
+struct Queued(ActiveEvent);
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.0.clock, other.0.order_key()).cmp(&(self.0.clock, self.0.order_key()))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl EventQueue {
+    pub fn push(&mut self, event: ActiveEvent) {
+        self.heap.push(Queued(event));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Clock of the earliest event, if any.
+    pub fn next_clock(&self) -> Option<usize> {
+        self.heap.peek().map(|queued| queued.0.clock)
+    }
+
+    /// Takes every event due by `clock`, one at a time in the order they are to be applied.
+    pub fn pop_due(&mut self, clock: usize) -> impl Iterator<Item = ActiveEvent> + '_ {
+        std::iter::from_fn(move || {
+            self.next_clock()
+                .filter(|&next| next <= clock)
+                .and_then(|_| self.heap.pop())
+                .map(|queued| queued.0)
+        })
+    }
+
+    /// Every event, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &ActiveEvent> {
+        self.heap.iter().map(|queued| &queued.0)
+    }
+
+    pub fn retain<F: FnMut(&ActiveEvent) -> bool>(&mut self, mut keep: F) {
+        self.heap.retain(|queued| keep(&queued.0));
+    }
+}
+
+impl Extend<ActiveEvent> for EventQueue {
+    fn extend<I: IntoIterator<Item = ActiveEvent>>(&mut self, events: I) {
+        self.heap.extend(events.into_iter().map(Queued));
+    }
+}
@@ -0,0 +1,148 @@
+use super::parallel::delayed_events;
+use super::Engine;
+use crate::error::{AppError, Result};
+use crate::model::{ActiveEvent, NodeId, Transition};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+/// An immediate instruction carried out by a firing
+#[derive(Debug, Clone, Copy)]
+struct Write {
+    /// Place of the firing among those of the tick
+    rank: usize,
+    /// Place of the instruction among those of the firing
+    index: usize,
+    /// Position in the net of the transition set
+    position: usize,
+    value: isize,
+}
+
+/// A firing, as a shard hands it back: its rank, the position of the transition and the
+/// events of its delayed instructions
+type Fired = (usize, usize, Vec<ActiveEvent>);
+
+/// What every shard knows of the others
+struct Layout<'a> {
+    node: &'a NodeId,
+    /// Position of every transition by id
+    positions: &'a HashMap<usize, usize>,
+    /// Transitions per shard, the last one excepted
+    size: usize,
+}
+
+/// A block of consecutive positions of the net, fired by a worker thread of its own
+struct Shard<'a> {
+    /// Position of the first transition of the block
+    offset: usize,
+    transitions: &'a mut [Transition],
+    /// Rank and position of every transition of the block firing this tick
+    firing: Vec<(usize, usize)>,
+    /// Writes of other shards' firings to transitions of this one
+    inbox: Receiver<Write>,
+}
+
+impl Engine {
+    /// Fires the transitions at `enabled` positions in the net on --shards worker threads,
+    /// each owning a block of consecutive positions. Immediate instructions setting a
+    /// transition of another block are sent to its worker, and every worker applies the
+    /// writes to its block in the order sequential firing would, once every one fired, so
+    /// the values left behind are the same. Events are then scheduled one firing after
+    /// another, as with sequential firing.
+    pub(super) fn fire_in_shards(&mut self, enabled: &[usize]) -> Result<()> {
+        let size = self.net.transitions.len().div_ceil(self.shards).max(1);
+        let (blocks, positions) = self.net.shards_mut(size);
+        let layout = Layout {
+            node: &self.node,
+            positions,
+            size,
+        };
+
+        let mut firing = vec![vec![]; blocks.len()];
+        // to simulate a stack
+        for (rank, &position) in enabled.iter().rev().enumerate() {
+            firing[position / size].push((rank, position));
+        }
+        let (outboxes, inboxes): (Vec<_>, Vec<_>) = firing.iter().map(|_| channel()).unzip();
+
+        let mut fired = thread::scope(|scope| {
+            let workers = blocks
+                .zip(firing)
+                .zip(inboxes)
+                .enumerate()
+                .map(|(index, ((transitions, firing), inbox))| {
+                    let shard = Shard {
+                        offset: index * size,
+                        transitions,
+                        firing,
+                        inbox,
+                    };
+                    let outboxes = outboxes.clone();
+                    let layout = &layout;
+                    scope.spawn(move || shard.fire(layout, outboxes))
+                })
+                .collect::<Vec<_>>();
+            // every inbox closes once the workers are done sending
+            drop(outboxes);
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Shard worker panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        fired.sort_unstable_by_key(|&(rank, ..)| rank);
+        for (_, position, events) in fired {
+            self.record_firing(position);
+            self.schedule(position, events);
+        }
+
+        Ok(())
+    }
+}
+
+impl Shard<'_> {
+    /// Fires the transitions of the block due this tick, sending the writes to other
+    /// blocks over `outboxes`, then applies those setting transitions of this block, its
+    /// own and those received, in firing order.
+    fn fire(self, layout: &Layout, outboxes: Vec<Sender<Write>>) -> Result<Vec<Fired>> {
+        let mut writes = vec![];
+        let mut fired = Vec::with_capacity(self.firing.len());
+        for &(rank, position) in &self.firing {
+            let transition = &self.transitions[position - self.offset];
+            for (index, instruction) in transition.immediate_instructions.iter().enumerate() {
+                let Some(&position) = layout.positions.get(&instruction.transition_id) else {
+                    return Err(AppError::UnknownTransition {
+                        transition_id: instruction.transition_id,
+                        context: format!("set by transition {}", transition.id),
+                    });
+                };
+                let write = Write {
+                    rank,
+                    index,
+                    position,
+                    value: instruction.value,
+                };
+                match position / layout.size {
+                    shard if shard == self.offset / layout.size => writes.push(write),
+                    // only fails once that shard gave up on an error, reported in its place
+                    shard => outboxes[shard].send(write).unwrap_or_default(),
+                }
+            }
+            fired.push((rank, position, delayed_events(layout.node, transition)));
+        }
+        drop(outboxes);
+
+        writes.extend(self.inbox.iter());
+        // later firings win, as with sequential firing
+        writes.sort_unstable_by_key(|write| (write.rank, write.index));
+        for write in writes {
+            self.transitions[write.position - self.offset].value = write.value;
+        }
+
+        Ok(fired)
+    }
+}
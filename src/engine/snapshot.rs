@@ -0,0 +1,202 @@
+use super::Engine;
+use crate::error::Result;
+use crate::model::{ActiveEvent, SnapshotMarker, WireMessage};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use tracing::info;
+
+/// Bookkeeping for Chandy-Lamport snapshots. Every message on a channel is numbered,
+/// so a marker only has to tell how many messages its sender had sent on the channel
+/// when recording its state: those this node consumes after recording its own are the
+/// state of the channel.
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    /// Clock at which this node initiates a snapshot, if any
+    at_clock: Option<usize>,
+    /// Snapshots recorded locally whose channels are still being recorded, by id
+    in_progress: HashMap<String, Snapshot>,
+    /// Snapshots already written, so late markers do not start them again
+    done: Vec<String>,
+}
+
+impl Snapshots {
+    pub fn new(at_clock: Option<usize>) -> Self {
+        Self {
+            at_clock,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    snapshot_id: String,
+    node: String,
+    clock: usize,
+    /// Transition id to its (clock, value)
+    marking: BTreeMap<usize, (usize, isize)>,
+    internal_active_events: Vec<ActiveEvent>,
+    channels: BTreeMap<String, Channel>,
+}
+
+#[derive(Debug, Serialize)]
+struct Channel {
+    /// Messages in flight on the channel when the snapshot was taken
+    messages: Vec<WireMessage>,
+    /// Messages this node had consumed from the channel when recording its state
+    #[serde(skip)]
+    consumed: usize,
+    /// Messages the sender had sent on the channel when recording its state,
+    /// known once its marker arrives
+    #[serde(skip)]
+    sent: Option<usize>,
+}
+
+impl Channel {
+    fn is_recorded(&self) -> bool {
+        self.sent
+            .is_some_and(|sent| self.consumed + self.messages.len() >= sent)
+    }
+}
+
+impl Engine {
+    /// Initiates a snapshot when due, and handles every marker received.
+    /// Only called once this tick's events are sent, so the local state is a clean cut.
+    pub(super) fn poll_snapshots(&mut self) -> Result<()> {
+        if self
+            .snapshots
+            .at_clock
+            .is_some_and(|clock| self.clock >= clock)
+        {
+            self.snapshots.at_clock = None;
+            let snapshot_id = format!("{}@{}", self.node, self.clock);
+            info!(id = %snapshot_id, "SNAPSHOT START");
+
+            let others = self
+                .nodes
+                .iter()
+                .filter(|node| **node != *self.node)
+                .cloned()
+                .collect::<Vec<_>>();
+            others.iter().try_for_each(|node| {
+                let marker = SnapshotMarker {
+                    snapshot_id: snapshot_id.clone(),
+                    sender: self.node.to_string(),
+                    sent: None,
+                };
+                self.send(node, marker.into())
+            })?;
+            self.record_snapshot(snapshot_id)?;
+        }
+
+        while let Ok(marker) = self.snapshot_markers.try_recv() {
+            if !self.snapshots.in_progress.contains_key(&marker.snapshot_id)
+                && !self.snapshots.done.contains(&marker.snapshot_id)
+            {
+                self.record_snapshot(marker.snapshot_id.clone())?;
+            }
+
+            if let (Some(sent), Some(snapshot)) = (
+                marker.sent,
+                self.snapshots.in_progress.get_mut(&marker.snapshot_id),
+            ) {
+                if let Some(channel) = snapshot.channels.get_mut(&marker.sender) {
+                    channel.sent = Some(sent);
+                }
+            }
+            self.complete_snapshots()?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the local state and sends a marker on every outgoing channel.
+    fn record_snapshot(&mut self, snapshot_id: String) -> Result<()> {
+        let channels = self
+            .feeding_nodes
+            .iter()
+            .map(|feeding_node| {
+                let channel = Channel {
+                    messages: vec![],
+                    consumed: feeding_node.consumed,
+                    sent: None,
+                };
+                (feeding_node.name.to_string(), channel)
+            })
+            .collect();
+        let snapshot = Snapshot {
+            snapshot_id: snapshot_id.clone(),
+            node: self.node.to_string(),
+            clock: self.clock,
+            marking: self
+                .net
+                .transitions
+                .iter()
+                .map(|transition| (transition.id, (transition.clock, transition.value)))
+                .collect(),
+            internal_active_events: self.internal_active_events.iter().cloned().collect(),
+            channels,
+        };
+        self.snapshots
+            .in_progress
+            .insert(snapshot_id.clone(), snapshot);
+
+        self.fed_nodes.clone().iter().try_for_each(|fed_node| {
+            let marker = SnapshotMarker {
+                snapshot_id: snapshot_id.clone(),
+                sender: self.node.to_string(),
+                sent: Some(self.channel_seqs.get(fed_node).copied().unwrap_or_default()),
+            };
+            self.send(fed_node, marker.into())
+        })?;
+
+        self.complete_snapshots()
+    }
+
+    /// Records a message consumed from `feeding_node` in every snapshot whose channel
+    /// state it belongs to.
+    pub(super) fn record_in_flight(
+        &mut self,
+        feeding_node: &str,
+        channel_seq: usize,
+        event: &WireMessage,
+    ) -> Result<()> {
+        self.snapshots
+            .in_progress
+            .values_mut()
+            .filter_map(|snapshot| snapshot.channels.get_mut(feeding_node))
+            .filter(|channel| {
+                channel_seq >= channel.consumed
+                    && channel.sent.is_none_or(|sent| channel_seq < sent)
+            })
+            .for_each(|channel| channel.messages.push(event.clone()));
+
+        self.complete_snapshots()
+    }
+
+    /// Writes every snapshot whose channels are all recorded to `{node}.{id}.snapshot.json`.
+    fn complete_snapshots(&mut self) -> Result<()> {
+        let completed = self
+            .snapshots
+            .in_progress
+            .iter()
+            .filter(|(_, snapshot)| snapshot.channels.values().all(Channel::is_recorded))
+            .map(|(snapshot_id, _)| snapshot_id.clone())
+            .collect::<Vec<_>>();
+
+        completed.into_iter().try_for_each(|snapshot_id| {
+            let snapshot = self
+                .snapshots
+                .in_progress
+                .remove(&snapshot_id)
+                .expect("Snapshot id comes from the map");
+            let path = format!("{}.{}.snapshot.json", self.node, snapshot_id);
+            serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &snapshot)?;
+            info!(id = %snapshot_id, path, "SNAPSHOT DONE");
+            self.snapshots.done.push(snapshot_id);
+            Ok(())
+        })
+    }
+}
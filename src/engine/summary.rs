@@ -0,0 +1,70 @@
+use super::{Engine, Traffic};
+use crate::error::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use tracing::info;
+
+/// Outcome of a run, written to `{node}.summary.json` so scripts orchestrating many runs
+/// collect their results without scraping logs.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    node: &'a str,
+    run_id: Option<&'a str>,
+    exit_reason: ExitReason,
+    clock: usize,
+    wall_time_secs: f64,
+    /// Local transitions fired, rolled back firings included
+    fired: usize,
+    /// Events and null messages sent to every fed node and received from every feeding node
+    messages: &'a Traffic,
+    /// Transition id to its (clock, value)
+    marking: BTreeMap<usize, (usize, isize)>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ExitReason {
+    TerminalClock,
+    Quiescent,
+    /// Nothing was scheduled anywhere in the cluster anymore
+    Exhausted,
+    /// The node was rebalanced out of the cluster
+    Left,
+}
+
+impl Engine {
+    pub(super) fn write_summary(&self) -> Result<()> {
+        let exit_reason = if !self.nodes.iter().any(|node| **node == *self.node) {
+            ExitReason::Left
+        } else if self.is_quiescent() {
+            ExitReason::Quiescent
+        } else if self.clock == usize::MAX && self.terminal_clock == usize::MAX {
+            ExitReason::Exhausted
+        } else {
+            ExitReason::TerminalClock
+        };
+        let summary = Summary {
+            node: &self.node,
+            run_id: self.transport.run_id(),
+            exit_reason,
+            clock: self.clock.min(self.terminal_clock),
+            wall_time_secs: self.started.elapsed().as_secs_f64(),
+            fired: self.progress.fired,
+            messages: &self.traffic,
+            marking: self
+                .net
+                .transitions
+                .iter()
+                .map(|transition| (transition.id, (transition.clock, transition.value)))
+                .collect(),
+        };
+
+        let path = format!("{}.summary.json", self.node);
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &summary)?;
+        info!(path, ?exit_reason, "SUMMARY");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use super::Engine;
+use crate::error::Result;
+use crate::model::{ChannelCount, NodeId, TerminationMarker};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Bookkeeping for --until-quiescent. Every node counts the active events it sent and
+/// received on each of its channels; the first node periodically circulates a marker
+/// gathering those counts and, once two consecutive rounds find every node idle, every
+/// channel drained and not a single count changed in between, it announces that the
+/// cluster has terminated.
+#[derive(Debug)]
+pub struct Termination {
+    interval: Duration,
+    last_round: Instant,
+    /// Whether the leader is waiting for its marker to come back
+    in_progress: bool,
+    round: usize,
+    /// Counts of the previous round, if it found the whole cluster idle (leader only)
+    previous: Option<Vec<ChannelCount>>,
+    /// Active events sent to each fed node
+    pub sent: BTreeMap<NodeId, usize>,
+    /// Active events received from each feeding node
+    pub received: BTreeMap<NodeId, usize>,
+    pub terminated: bool,
+}
+
+impl Termination {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_round: Instant::now(),
+            in_progress: false,
+            round: 0,
+            previous: None,
+            sent: BTreeMap::new(),
+            received: BTreeMap::new(),
+            terminated: false,
+        }
+    }
+}
+
+impl Engine {
+    /// Starts a round when due, and handles every marker received.
+    pub(super) fn poll_termination_markers(&mut self) -> Result<()> {
+        if !self.until_quiescent {
+            return Ok(());
+        }
+
+        if self.is_leader()
+            && !self.termination.in_progress
+            && !self.termination.terminated
+            && self.termination.last_round.elapsed() >= self.termination.interval
+        {
+            self.start_termination_round()?;
+        }
+
+        while let Ok(marker) = self.termination_markers.try_recv() {
+            if marker.terminated {
+                self.terminate(marker.round);
+            } else if self.is_leader() {
+                self.complete_termination_round(marker)?;
+            } else {
+                self.forward_termination_marker(marker)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_termination_round(&mut self) -> Result<()> {
+        self.termination.in_progress = true;
+        self.termination.round += 1;
+        let marker = TerminationMarker {
+            round: self.termination.round,
+            all_idle: true,
+            channels: vec![],
+            terminated: false,
+        };
+        let next_node = self.next_node()?;
+        self.send(&next_node, marker.into())
+    }
+
+    /// Nothing is scheduled at this node, so it sends nothing more unless it receives an
+    /// active event first.
+    fn add_counts(&self, marker: &mut TerminationMarker) {
+        marker.all_idle &= self.next_scheduled_clock() == usize::MAX;
+        for (fed_node, &sent) in &self.termination.sent {
+            channel(&mut marker.channels, &self.node, fed_node).sent = sent;
+        }
+        for (feeding_node, &received) in &self.termination.received {
+            channel(&mut marker.channels, feeding_node, &self.node).received = received;
+        }
+    }
+
+    fn forward_termination_marker(&mut self, mut marker: TerminationMarker) -> Result<()> {
+        self.add_counts(&mut marker);
+        let next_node = self.next_node()?;
+        self.send(&next_node, marker.into())
+    }
+
+    /// A single round sees nodes at different moments, so an event may be received after
+    /// its sender was visited and before its receiver was. Only a second round finding the
+    /// very same counts proves that nothing was sent nor received in between, every node
+    /// having stayed idle since the first round visited it.
+    fn complete_termination_round(&mut self, mut marker: TerminationMarker) -> Result<()> {
+        self.add_counts(&mut marker);
+        self.termination.in_progress = false;
+        self.termination.last_round = Instant::now();
+
+        let drained = marker
+            .channels
+            .iter()
+            .all(|channel| channel.sent == channel.received);
+        if !marker.all_idle || !drained {
+            self.termination.previous = None;
+            return Ok(());
+        }
+
+        if self.termination.previous.as_ref() != Some(&marker.channels) {
+            self.termination.previous = Some(marker.channels);
+            return self.start_termination_round();
+        }
+
+        let announcement = TerminationMarker {
+            terminated: true,
+            ..marker
+        };
+        let others = self
+            .nodes
+            .iter()
+            .filter(|node| **node != *self.node)
+            .cloned()
+            .collect::<Vec<_>>();
+        others
+            .iter()
+            .try_for_each(|node| self.send(node, announcement.clone().into()))?;
+        self.terminate(announcement.round);
+
+        Ok(())
+    }
+
+    fn terminate(&mut self, round: usize) {
+        self.termination.terminated = true;
+        info!(round, "QUIESCENT");
+    }
+}
+
+/// Count of the channel from `feeding_node` to `fed_node` among `channels`, kept sorted so
+/// that rounds compare equal whatever order they visited nodes in.
+fn channel<'a>(
+    channels: &'a mut Vec<ChannelCount>,
+    feeding_node: &str,
+    fed_node: &str,
+) -> &'a mut ChannelCount {
+    let key = (feeding_node, fed_node);
+    let index = match channels
+        .binary_search_by(|channel| (&*channel.feeding_node, &*channel.fed_node).cmp(&key))
+    {
+        Ok(index) => index,
+        Err(index) => {
+            channels.insert(
+                index,
+                ChannelCount {
+                    feeding_node: feeding_node.to_string(),
+                    fed_node: fed_node.to_string(),
+                    sent: 0,
+                    received: 0,
+                },
+            );
+            index
+        }
+    };
+    &mut channels[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing;
+    use std::time::Duration;
+
+    #[test]
+    fn stops_where_running_past_the_last_firing_does() {
+        // every transition fires once at clock 0, and nothing else ever happens
+        let nets = testing::generated("quiescent", 20, 0, 2, 0);
+        let quiescent = testing::run_cluster(
+            "quiescent",
+            &nets,
+            2,
+            &["--until-quiescent"],
+            Duration::from_secs(60),
+        );
+        let run_out = testing::run_cluster(
+            "run-out",
+            &nets,
+            2,
+            &["--terminal-clock", "100"],
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(quiescent, run_out);
+    }
+}
@@ -0,0 +1,100 @@
+//! Engines for unit tests, on generated nets and Unix sockets so tests running side by
+//! side neither compete for a port nor need a network.
+
+use super::Engine;
+use crate::config::Config;
+use crate::generate;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Folder with a net of `transitions` transitions in `subnets` segments, every firing
+/// enabling `fanout` others, generated from `seed` and written afresh for test `name`.
+pub fn generated(
+    name: &str,
+    transitions: usize,
+    fanout: usize,
+    subnets: usize,
+    seed: u64,
+) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("petri-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&folder);
+    generate::write(
+        &folder,
+        &generate::generate(transitions, fanout, subnets, seed),
+    )
+    .expect("Failed to write the generated net");
+    folder
+}
+
+/// Addresses of `count` nodes for test `name`, Unix sockets in the temporary folder.
+pub fn nodes(name: &str, count: usize) -> Vec<String> {
+    (0..count)
+        .map(|index| {
+            let socket = std::env::temp_dir().join(format!(
+                "petri-test-{name}-{}-{index}.sock",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&socket);
+            socket.display().to_string()
+        })
+        .collect()
+}
+
+/// Configuration of `node` among `nodes`, running the net in `nets_folder` with `args`.
+pub fn config(nets_folder: &Path, nodes: &[String], node: &str, args: &[&str]) -> Config {
+    let nets_folder = nets_folder.display().to_string();
+    let mut command = vec!["petri", "--node", node, "--nets-folder", &nets_folder];
+    command.push("--nodes");
+    command.extend(nodes.iter().map(String::as_str));
+    command.extend(args);
+    Config::parse_from(command)
+}
+
+/// A single node hosting every segment in `nets_folder`, started but not run.
+pub fn single_node(name: &str, nets_folder: &Path, args: &[&str]) -> Engine {
+    let nodes = nodes(name, 1);
+    Engine::new(&config(nets_folder, &nodes, &nodes[0], args)).expect("Failed to start the node")
+}
+
+/// Runs `count` nodes on the net in `nets_folder` with `args`, each on a thread of its
+/// own, and returns the (id, clock, value) of every transition each ended with, failing
+/// the test if any node fails or takes longer than `timeout`.
+pub fn run_cluster(
+    name: &str,
+    nets_folder: &Path,
+    count: usize,
+    args: &[&str],
+    timeout: Duration,
+) -> Vec<Vec<(usize, usize, isize)>> {
+    let nodes = nodes(name, count);
+    let (finished_tx, finished) = channel();
+    for (index, node) in nodes.iter().enumerate() {
+        let config = config(nets_folder, &nodes, node, args);
+        let finished_tx = finished_tx.clone();
+        thread::spawn(move || {
+            let marking = Engine::new(&config).and_then(|mut engine| {
+                engine.run()?;
+                Ok(engine
+                    .net
+                    .transitions
+                    .iter()
+                    .map(|transition| (transition.id, transition.clock, transition.value))
+                    .collect::<Vec<_>>())
+            });
+            finished_tx.send((index, marking)).unwrap_or_default();
+        });
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut markings = vec![vec![]; count];
+    for _ in 0..count {
+        let (index, marking) = finished
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .expect("The cluster did not finish in time");
+        markings[index] = marking.expect("A node failed");
+    }
+    markings
+}
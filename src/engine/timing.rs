@@ -0,0 +1,105 @@
+use crate::model::NodeId;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// Wall-clock time of the run split into waiting for feeding nodes, waiting with nothing
+/// to simulate, holding back for --real-time-factor and the rest, useful work, so poor
+/// lookahead or partitioning shows as time blocked on the nodes responsible.
+#[derive(Debug)]
+pub struct Timing {
+    started: Instant,
+    /// Time blocked on every feeding node, or on the barrier coordinator
+    blocked: BTreeMap<NodeId, Duration>,
+    /// Time waiting for stragglers or GVT with nothing left to simulate
+    idle: Duration,
+    paced: Duration,
+    ticks: usize,
+    /// Time blocked as of the end of the previous tick
+    blocked_then: Duration,
+    /// Most time blocked in a single tick
+    max_tick_blocked: Duration,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            blocked: BTreeMap::new(),
+            idle: Duration::ZERO,
+            paced: Duration::ZERO,
+            ticks: 0,
+            blocked_then: Duration::ZERO,
+            max_tick_blocked: Duration::ZERO,
+        }
+    }
+}
+
+impl Timing {
+    /// Starts counting the run from now on, leaving out the startup.
+    pub fn start(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn blocked_on(&mut self, node: &str, since: Instant) {
+        // the name is only copied the first time the node blocks this one
+        match self.blocked.get_mut(node) {
+            Some(blocked) => *blocked += since.elapsed(),
+            None => {
+                self.blocked.insert(node.into(), since.elapsed());
+            }
+        }
+    }
+
+    pub fn idle(&mut self, since: Instant) {
+        self.idle += since.elapsed();
+    }
+
+    pub fn paced(&mut self, since: Instant) {
+        self.paced += since.elapsed();
+    }
+
+    pub fn end_tick(&mut self) {
+        let blocked = self.blocked.values().sum::<Duration>();
+        self.max_tick_blocked = self.max_tick_blocked.max(blocked - self.blocked_then);
+        self.blocked_then = blocked;
+        self.ticks += 1;
+    }
+}
+
+impl Display for Timing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elapsed = self.started.elapsed();
+        let blocked = self.blocked.values().sum::<Duration>();
+        let busy = elapsed.saturating_sub(blocked + self.idle + self.paced);
+        let share =
+            |part: Duration| 100.0 * part.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+        let mean_tick_blocked = blocked.checked_div(self.ticks as u32).unwrap_or_default();
+
+        write!(
+            f,
+            "elapsed={:.3}s busy={:.3}s ({:.1}%) blocked={:.3}s ({:.1}%) idle={:.3}s ({:.1}%) \
+             paced={:.3}s ({:.1}%) ticks={} mean_tick_blocked={:?} max_tick_blocked={:?}",
+            elapsed.as_secs_f64(),
+            busy.as_secs_f64(),
+            share(busy),
+            blocked.as_secs_f64(),
+            share(blocked),
+            self.idle.as_secs_f64(),
+            share(self.idle),
+            self.paced.as_secs_f64(),
+            share(self.paced),
+            self.ticks,
+            mean_tick_blocked,
+            self.max_tick_blocked
+        )?;
+        self.blocked.iter().try_for_each(|(node, blocked)| {
+            write!(
+                f,
+                " |___| on={node} blocked={:.3}s ({:.1}%)",
+                blocked.as_secs_f64(),
+                share(*blocked)
+            )
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use crate::model::{NodeId, WireMessage};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Events and null messages sent and received on every channel, to judge how much of its
+/// traffic conservative synchronization spends on null messages.
+#[derive(Debug, Default, Serialize)]
+pub struct Traffic {
+    sent: BTreeMap<NodeId, Counts>,
+    received: BTreeMap<NodeId, Counts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct Counts {
+    /// Active events, anti-messages included
+    events: usize,
+    /// Passive events
+    nulls: usize,
+}
+
+impl Counts {
+    fn count(&mut self, message: &WireMessage) {
+        match message {
+            WireMessage::Active(_) => self.events += 1,
+            WireMessage::Passive(_) => self.nulls += 1,
+            WireMessage::Batch(messages) => messages.iter().for_each(|message| self.count(message)),
+            WireMessage::Control(_) => {}
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            events: self.events + other.events,
+            nulls: self.nulls + other.nulls,
+        }
+    }
+
+    fn null_ratio(&self) -> f64 {
+        match self.events + self.nulls {
+            0 => 0.0,
+            total => self.nulls as f64 / total as f64,
+        }
+    }
+}
+
+impl Display for Counts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "events={} nulls={} null_ratio={:.4}",
+            self.events,
+            self.nulls,
+            self.null_ratio()
+        )
+    }
+}
+
+impl Traffic {
+    pub fn sent(&mut self, fed_node: &str, message: &WireMessage) {
+        if matches!(message, WireMessage::Control(_)) {
+            return;
+        }
+        count(&mut self.sent, fed_node, message);
+    }
+
+    pub fn received(&mut self, feeding_node: &str, message: &WireMessage) {
+        count(&mut self.received, feeding_node, message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty() && self.received.is_empty()
+    }
+}
+
+/// Counts `message` on the channel with `node`, copying its name only for a new channel.
+fn count(channels: &mut BTreeMap<NodeId, Counts>, node: &str, message: &WireMessage) {
+    match channels.get_mut(node) {
+        Some(counts) => counts.count(message),
+        None => {
+            let mut counts = Counts::default();
+            counts.count(message);
+            channels.insert(node.into(), counts);
+        }
+    }
+}
+
+impl Display for Traffic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sent = self
+            .sent
+            .iter()
+            .map(|(node, counts)| format!("to={node} {counts}"));
+        let received = self
+            .received
+            .iter()
+            .map(|(node, counts)| format!("from={node} {counts}"));
+        let lines = sent.chain(received).collect::<Vec<_>>();
+        let total = |channels: &BTreeMap<NodeId, Counts>| {
+            channels
+                .values()
+                .fold(Counts::default(), |acc, counts| acc.add(*counts))
+        };
+
+        write!(
+            f,
+            "{} |___| total sent {} |___| total received {}",
+            lines.join(" |___| "),
+            total(&self.sent),
+            total(&self.received)
+        )
+    }
+}
@@ -0,0 +1,398 @@
+use super::Engine;
+use crate::error::Result;
+use crate::logging;
+use crate::model::{NodeId, ReorderBuffer, WireMessage};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use tracing::{debug, info, info_span, warn};
+
+/// Every tick's events received from feeding nodes and the clock they led to, and every
+/// event and null message sent to fed nodes, one JSON line each in <node>.wal. Firing is
+/// deterministic given what was received, so replaying the ticks rebuilds the marking,
+/// pending events and clock a node had reached before it crashed, without asking its
+/// peers for anything; what was sent is resent from there to a peer that lost it.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: String,
+    file: File,
+    /// Whether the log was left by a previous run of this node, which this one resumes
+    recovering: bool,
+    /// Ticks read back from a previous run of this node, still to be replayed
+    replayed: VecDeque<(usize, Vec<WireMessage>)>,
+    /// Ticks replayed so far
+    ticks: usize,
+    /// Run the previous run of this node took part in, if it got to know it
+    run_id: Option<String>,
+    /// Sequence number of the first message not consumed yet from every feeding node
+    consumed: HashMap<NodeId, usize>,
+}
+
+/// A logged line, its fields borrowed when written and owned when read back.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record<'a> {
+    /// The run the node takes part in, once it knows it
+    Run { run_id: Cow<'a, str> },
+    /// Events received in a tick, in the order they were applied, and the clock they led to
+    Tick {
+        clock: usize,
+        events: Cow<'a, [WireMessage]>,
+    },
+    /// Events or null messages sent to a fed node, logged before they leave
+    Sent {
+        to: Cow<'a, str>,
+        message: Cow<'a, WireMessage>,
+    },
+}
+
+impl WriteAheadLog {
+    /// Starts <node>.wal afresh, or when recovering reads back the ticks it holds and
+    /// carries on after the last whole line.
+    pub fn open(node: &str, recover: bool) -> Result<Self> {
+        let path = format!("{node}.wal");
+        let file = if recover {
+            OpenOptions::new().append(true).create(true).open(&path)?
+        } else {
+            File::create(&path)?
+        };
+        let mut wal = Self {
+            path,
+            file,
+            recovering: recover,
+            replayed: VecDeque::new(),
+            ticks: 0,
+            run_id: None,
+            consumed: HashMap::new(),
+        };
+        if !recover {
+            return Ok(wal);
+        }
+
+        let mut kept = 0;
+        for line in lines(&wal.path)? {
+            let (length, record) = line?;
+            match record {
+                Record::Run { run_id } => wal.run_id = Some(run_id.into_owned()),
+                Record::Tick { clock, events } => {
+                    for event in events.iter() {
+                        if let Some((feeding_node, channel_seq, _)) = event.channel() {
+                            wal.consumed.insert(feeding_node.clone(), channel_seq + 1);
+                        }
+                    }
+                    wal.replayed.push_back((clock, events.into_owned()));
+                }
+                Record::Sent { .. } => {}
+            }
+            kept += length;
+        }
+        wal.file.set_len(kept)?;
+
+        Ok(wal)
+    }
+
+    /// Appends `record` as a line of its own, written straight through so it survives
+    /// the node dying right after.
+    fn append(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        !self.replayed.is_empty()
+    }
+
+    /// Run the previous run of this node took part in, to take part in again.
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    /// Reorder buffers of the channels from feeding nodes, starting after the last message
+    /// the previous run of this node consumed from each of them.
+    pub fn reorder_buffers(&self) -> HashMap<NodeId, ReorderBuffer> {
+        self.consumed
+            .iter()
+            .map(|(feeding_node, &next_seq)| {
+                (feeding_node.clone(), ReorderBuffer::resuming_at(next_seq))
+            })
+            .collect()
+    }
+
+    /// Every event and null message logged as sent to `node`, from sequence number
+    /// `resume_from` on, in order.
+    fn sent_to(&self, node: &str, resume_from: usize) -> Result<Vec<WireMessage>> {
+        let mut sent = BTreeMap::new();
+        for line in lines(&self.path)? {
+            let Record::Sent { to, message } = line?.1 else {
+                continue;
+            };
+            if to != node {
+                continue;
+            }
+            // sent again by a later run of this node, with the same sequence numbers
+            for message in message.into_owned().unbatch() {
+                if let Some((_, channel_seq, _)) = message.channel() {
+                    if channel_seq >= resume_from {
+                        sent.entry(channel_seq).or_insert(message);
+                    }
+                }
+            }
+        }
+
+        Ok(sent.into_values().collect())
+    }
+}
+
+/// Every whole line of the log at `path` with its length in bytes, up to the first that
+/// is torn or unreadable: a crash while writing leaves such a last line, which never
+/// took effect.
+fn lines(path: &str) -> Result<impl Iterator<Item = Result<(u64, Record<'static>)>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut index = 0;
+    let path = path.to_string();
+    Ok(std::iter::from_fn(move || {
+        let mut line = String::new();
+        index += 1;
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(length) => match serde_json::from_str(&line) {
+                Ok(record) if line.ends_with('\n') => Some(Ok((length as u64, record))),
+                _ => {
+                    warn!(path, line = index, "WAL TRUNCATED");
+                    None
+                }
+            },
+            Err(error) => Some(Err(error.into())),
+        }
+    }))
+}
+
+impl Engine {
+    /// Whether ticks of a previous run are still being replayed.
+    pub(super) fn replaying(&self) -> bool {
+        self.wal.as_ref().is_some_and(WriteAheadLog::is_replaying)
+    }
+
+    /// Whether this node resumes a previous run of its own, rejoining its peers.
+    pub(super) fn recovering(&self) -> bool {
+        self.wal.as_ref().is_some_and(|wal| wal.recovering)
+    }
+
+    /// Runs the ticks logged by a previous run of this node, before any peer is heard
+    /// from: nothing they send is needed to get back where the node was.
+    pub(super) fn replay(&mut self) -> Result<()> {
+        while self.replaying() {
+            let _tick = info_span!("tick", clock = self.clock).entered();
+            self.conservative_tick()?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the events of the next logged tick in place of receiving any, and moves
+    /// to the clock they led to. False once every logged tick is replayed.
+    pub(super) fn replay_tick(&mut self) -> Result<bool> {
+        let Some(wal) = &mut self.wal else {
+            return Ok(false);
+        };
+        let Some((clock, events)) = wal.replayed.pop_front() else {
+            return Ok(false);
+        };
+        wal.ticks += 1;
+        if wal.replayed.is_empty() {
+            info!(ticks = wal.ticks, clock, "WAL REPLAYED");
+        }
+
+        events
+            .into_iter()
+            .try_for_each(|event| self.apply_received(event))?;
+        self.clock = clock;
+
+        Ok(true)
+    }
+
+    /// Logs the events received this tick, before they are applied to the marking.
+    pub(super) fn log_tick(&mut self, events: &[WireMessage]) -> Result<()> {
+        match &mut self.wal {
+            Some(wal) => wal.append(&Record::Tick {
+                clock: self.clock,
+                events: Cow::Borrowed(events),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Logs `message` before it leaves for `node`, unless it is a control message, which
+    /// no peer ever needs again.
+    pub(super) fn log_sent(&mut self, node: &str, message: &WireMessage) -> Result<()> {
+        match &mut self.wal {
+            Some(wal) if !matches!(message, WireMessage::Control(_)) => wal.append(&Record::Sent {
+                to: Cow::Borrowed(node),
+                message: Cow::Borrowed(message),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Logs the run this node takes part in, for a later run recovering it to take part
+    /// in it again.
+    pub(super) fn log_run(&mut self) -> Result<()> {
+        let Some(run_id) = self.transport.run_id().map(str::to_string) else {
+            return Ok(());
+        };
+        match &mut self.wal {
+            Some(wal) => wal.append(&Record::Run {
+                run_id: Cow::Owned(run_id),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Sequence number of the first message from `node` this node has not consumed.
+    pub(super) fn consumed_from(&self, node: &str) -> usize {
+        self.feeding_nodes
+            .iter()
+            .find(|feeding_node| *feeding_node.name == *node)
+            .map_or(0, |feeding_node| feeding_node.consumed)
+    }
+
+    /// Sends `node` again every event and null message logged as sent to it from
+    /// sequence number `resume_from` on, which it lost by crashing.
+    pub(super) fn resend(&mut self, node: &str, resume_from: usize) -> Result<()> {
+        let Some(wal) = &self.wal else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{node} recovered, resending what it lost needs --write-ahead-log"),
+            )
+            .into());
+        };
+        let mut messages = wal.sent_to(node, resume_from)?;
+        info!(
+            to = node,
+            resume_from,
+            messages = messages.len(),
+            "RESENDING"
+        );
+        let message = match messages.len() {
+            0 => return Ok(()),
+            1 => messages.remove(0),
+            _ => WireMessage::Batch(messages),
+        };
+        debug!(target: logging::NETWORK, to = node, resent = ?message, "RESENT");
+        self.outbox(node).send(message)
+    }
+
+    /// Catches up every peer that recovered from a crash and announced itself again:
+    /// resends what it lost of this node's messages, and tells it from where to resend
+    /// those this node lost of its own. A peer answering this node's own recovery is
+    /// only resent to.
+    pub(super) fn serve_recovered_peers(&mut self) -> Result<()> {
+        while let Ok(ready) = self.recoveries.try_recv() {
+            if let Some(run_id) = &ready.run_id {
+                self.transport.join_run(&ready.ready_node, run_id)?;
+            }
+            if ready.recovered {
+                info!(peer = ready.ready_node, "PEER RECOVERED");
+            }
+            self.resend(&ready.ready_node, ready.resume_from.unwrap_or_default())?;
+            if ready.recovered {
+                let answer = self.ready(&ready.ready_node, true);
+                self.send(&ready.ready_node, answer.into())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Record, WriteAheadLog};
+    use crate::model::{PassiveEvent, Reordered, WireMessage};
+    use std::borrow::Cow;
+    use std::io::Write;
+
+    /// Null message `channel_seq` on the channel from `feeding_node`.
+    fn null_message(feeding_node: &str, channel_seq: usize) -> WireMessage {
+        PassiveEvent {
+            feeding_node: feeding_node.into(),
+            clock: channel_seq,
+            lookahead: 1,
+            channel_seq,
+            lamport: channel_seq,
+        }
+        .into()
+    }
+
+    #[test]
+    fn recovers_what_was_consumed_and_sent() {
+        let node = std::env::temp_dir()
+            .join(format!("petri-test-wal-{}", std::process::id()))
+            .display()
+            .to_string();
+        let mut wal = WriteAheadLog::open(&node, false).expect("Failed to create the log");
+        let records = [
+            Record::Run {
+                run_id: Cow::Borrowed("run"),
+            },
+            Record::Tick {
+                clock: 1,
+                events: Cow::Owned(vec![null_message("a", 0), null_message("a", 1)]),
+            },
+            Record::Sent {
+                to: Cow::Borrowed("a"),
+                message: Cow::Owned(WireMessage::Batch(vec![
+                    null_message(&node, 0),
+                    null_message(&node, 1),
+                ])),
+            },
+            // sent again by a later run, after replaying up to it
+            Record::Sent {
+                to: Cow::Borrowed("a"),
+                message: Cow::Owned(null_message(&node, 1)),
+            },
+            Record::Sent {
+                to: Cow::Borrowed("a"),
+                message: Cow::Owned(null_message(&node, 2)),
+            },
+        ];
+        records
+            .iter()
+            .try_for_each(|record| wal.append(record))
+            .expect("Failed to log");
+        // torn by a crash while writing
+        wal.file
+            .write_all(br#"{"tick":{"clock":2,"#)
+            .expect("Failed to log");
+
+        let mut wal = WriteAheadLog::open(&node, true).expect("Failed to read the log back");
+        assert_eq!(wal.run_id(), Some("run"));
+        assert_eq!(wal.replayed.len(), 1);
+        let mut reorder_buffers = wal.reorder_buffers();
+        let from_a = reorder_buffers.get_mut("a").expect("No channel from a");
+        assert!(matches!(
+            from_a.push(1, null_message("a", 1)),
+            Reordered::Duplicate
+        ));
+        assert!(matches!(
+            from_a.push(2, null_message("a", 2)),
+            Reordered::Released(_)
+        ));
+        let resent = wal
+            .sent_to("a", 1)
+            .expect("Failed to read what was sent")
+            .iter()
+            .filter_map(|message| message.channel().map(|(_, channel_seq, _)| channel_seq))
+            .collect::<Vec<_>>();
+        assert_eq!(resent, [1, 2]);
+
+        // the torn line is gone, what is logged next starts a line of its own
+        wal.append(&records[1]).expect("Failed to log");
+        assert!(WriteAheadLog::open(&node, true).is_ok_and(|wal| wal.replayed.len() == 2));
+        let _ = std::fs::remove_file(format!("{node}.wal"));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, path::PathBuf};
 
 pub type Result<T> = std::result::Result<T, AppError>;
 
@@ -6,10 +6,73 @@ pub type Result<T> = std::result::Result<T, AppError>;
 pub enum AppError {
     Io(std::io::Error),
     SerdeJson(serde_json::Error),
+    Bincode(bincode::Error),
+    MessagePack(rmp_serde::encode::Error),
     Glob(glob::PatternError),
     Recv(std::sync::mpsc::RecvError),
     TryRecv(std::sync::mpsc::TryRecvError),
+    RecvTimeout(std::sync::mpsc::RecvTimeoutError),
     AddrParse(std::net::AddrParseError),
+    /// TLS misconfiguration or handshake failure
+    Tls(String),
+    /// A net file that is malformed or cannot be simulated, with where in it
+    InvalidNet {
+        path: PathBuf,
+        /// Field at fault, such as ia_red[2].ii_listactes_PUL[0]
+        field: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+        reason: String,
+    },
+    /// An incoming connection that is not from a cluster member
+    Unauthenticated(String),
+    /// A peer speaks another version of the protocol
+    ProtocolMismatch {
+        node: String,
+        version: u32,
+    },
+    /// A peer, or a message, that belongs to another run
+    OtherRun {
+        node: String,
+        run_id: String,
+    },
+    /// Every connection attempt to a peer failed
+    Unreachable {
+        node: String,
+        attempts: usize,
+        error: std::io::Error,
+    },
+    /// An event arrived for a clock the receiver had already simulated
+    Straggler {
+        feeding_node: String,
+        transition_id: usize,
+        clock: usize,
+        local_clock: usize,
+    },
+    /// A node name that is not a member of the cluster, with what referred to it
+    UnknownNode {
+        node: String,
+        context: String,
+    },
+    /// A transition id no segment defines, with what referred to it
+    UnknownTransition {
+        transition_id: usize,
+        context: String,
+    },
+    /// A message that is not what it should be where it ended up
+    MalformedEvent {
+        reason: String,
+    },
+    /// Nodes and nets that cannot be fitted to one another
+    TopologyMismatch(String),
+    /// The thread receiving messages from peers stopped, with why
+    ListenerFailed(String),
+    /// Nothing came from a feeding node for longer than the stall or receive timeout
+    Stalled {
+        feeding_node: String,
+        clock: usize,
+        waited: std::time::Duration,
+    },
 }
 
 impl Error for AppError {}
@@ -19,10 +82,83 @@ impl Display for AppError {
         match self {
             Self::Io(error) => write!(f, "{}", error),
             Self::SerdeJson(error) => write!(f, "{}", error),
+            Self::Bincode(error) => write!(f, "{}", error),
+            Self::MessagePack(error) => write!(f, "{}", error),
             Self::Glob(error) => write!(f, "{}", error),
             Self::Recv(error) => write!(f, "{}", error),
             Self::TryRecv(error) => write!(f, "{}", error),
+            Self::RecvTimeout(error) => write!(f, "{}", error),
             Self::AddrParse(error) => write!(f, "{}", error),
+            Self::Tls(error) => write!(f, "TLS: {}", error),
+            Self::InvalidNet {
+                path,
+                field,
+                line,
+                column,
+                reason,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, ":{}", column)?;
+                }
+                if let Some(field) = field {
+                    write!(f, ": {}", field)?;
+                }
+                write!(f, ": {}", reason)
+            }
+            Self::Unauthenticated(error) => write!(f, "unauthenticated peer: {}", error),
+            Self::ProtocolMismatch { node, version } => write!(
+                f,
+                "protocol mismatch: {} speaks version {}, this build versions {} to {}",
+                node,
+                version,
+                crate::transport::MIN_PROTOCOL_VERSION,
+                crate::transport::PROTOCOL_VERSION
+            ),
+            Self::OtherRun { node, run_id } => {
+                write!(f, "{} belongs to another run, {}", node, run_id)
+            }
+            Self::Unreachable {
+                node,
+                attempts,
+                error,
+            } => write!(
+                f,
+                "{} unreachable after {} attempts: {}",
+                node, attempts, error
+            ),
+            Self::Straggler {
+                feeding_node,
+                transition_id,
+                clock,
+                local_clock,
+            } => write!(
+                f,
+                "straggler from {} for transition {} at clock {}, local clock was already {}",
+                feeding_node, transition_id, clock, local_clock
+            ),
+            Self::UnknownNode { node, context } => {
+                write!(f, "unknown node {} {}", node, context)
+            }
+            Self::UnknownTransition {
+                transition_id,
+                context,
+            } => write!(f, "unknown transition {} {}", transition_id, context),
+            Self::MalformedEvent { reason } => write!(f, "malformed event: {}", reason),
+            Self::TopologyMismatch(reason) => write!(f, "topology mismatch: {}", reason),
+            Self::ListenerFailed(reason) => write!(f, "listener failed: {}", reason),
+            Self::Stalled {
+                feeding_node,
+                clock,
+                waited,
+            } => write!(
+                f,
+                "waiting on {} for {:.0?} at clock {}",
+                feeding_node, waited, clock
+            ),
         }
     }
 }
@@ -39,6 +175,18 @@ impl From<serde_json::Error> for AppError {
     }
 }
 
+impl From<bincode::Error> for AppError {
+    fn from(value: bincode::Error) -> Self {
+        AppError::Bincode(value)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for AppError {
+    fn from(value: rmp_serde::encode::Error) -> Self {
+        AppError::MessagePack(value)
+    }
+}
+
 impl From<glob::PatternError> for AppError {
     fn from(value: glob::PatternError) -> Self {
         AppError::Glob(value)
@@ -57,8 +205,21 @@ impl From<std::sync::mpsc::TryRecvError> for AppError {
     }
 }
 
+impl From<std::sync::mpsc::RecvTimeoutError> for AppError {
+    fn from(value: std::sync::mpsc::RecvTimeoutError) -> Self {
+        AppError::RecvTimeout(value)
+    }
+}
+
 impl From<std::net::AddrParseError> for AppError {
     fn from(value: std::net::AddrParseError) -> Self {
         AppError::AddrParse(value)
     }
 }
+
+#[cfg(feature = "tls")]
+impl From<rustls::Error> for AppError {
+    fn from(value: rustls::Error) -> Self {
+        AppError::Tls(value.to_string())
+    }
+}
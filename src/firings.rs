@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+/// Firing counts and times of the local transitions over a run, for the performance
+/// analysis of a model.
+#[derive(Debug, Default, Clone)]
+pub struct FiringStats {
+    per_transition: BTreeMap<usize, TransitionFirings>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct TransitionFirings {
+    firings: usize,
+    first_clock: usize,
+    last_clock: usize,
+    /// How many times the transition fired that many clock units after its previous
+    /// firing
+    intervals: BTreeMap<usize, usize>,
+}
+
+impl FiringStats {
+    pub fn fire(&mut self, transition_id: usize, clock: usize) {
+        let firings = self.per_transition.entry(transition_id).or_default();
+        if firings.firings == 0 {
+            firings.first_clock = clock;
+        } else {
+            *firings
+                .intervals
+                .entry(clock - firings.last_clock)
+                .or_default() += 1;
+        }
+        firings.firings += 1;
+        firings.last_clock = clock;
+    }
+
+    /// Every transition that fired, with its firings, first and last firing clock, and the
+    /// histogram of the clock units between consecutive firings, keyed by interval.
+    pub fn to_json(&self, node: &str) -> serde_json::Result<String> {
+        let transitions = self
+            .per_transition
+            .iter()
+            .map(|(id, firings)| {
+                let intervals = firings.intervals.values().sum::<usize>();
+                let mean = if intervals == 0 {
+                    None
+                } else {
+                    Some((firings.last_clock - firings.first_clock) as f64 / intervals as f64)
+                };
+                json!({
+                    "id": id,
+                    "firings": firings.firings,
+                    "first_clock": firings.first_clock,
+                    "last_clock": firings.last_clock,
+                    "mean_interval": mean,
+                    "min_interval": firings.intervals.keys().next(),
+                    "max_interval": firings.intervals.keys().next_back(),
+                    "intervals": firings.intervals,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::to_string_pretty(&json!({
+            "node": node,
+            "transitions": transitions,
+        }))
+    }
+}
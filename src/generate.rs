@@ -0,0 +1,63 @@
+use crate::error::Result;
+use crate::json::{Net, Transition};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+
+/// Longest firing duration of a generated transition
+const MAX_DURATION: usize = 5;
+
+/// A synthetic net of `transitions` transitions spread evenly over `subnets` segments, for
+/// benchmarks and stress runs of any size. Every transition starts enabled at clock 0 and
+/// on firing disables itself and enables `fanout` others picked at random, those of other
+/// segments through external instructions, so the net keeps firing for as long as it runs.
+/// The same `seed` always gives the same net.
+pub fn generate(transitions: usize, fanout: usize, subnets: usize, seed: u64) -> Vec<Net> {
+    let subnets = subnets.clamp(1, transitions.max(1));
+    let mut rng = StdRng::seed_from_u64(seed);
+    let subnet_of = |id: usize| id * subnets / transitions;
+
+    let mut nets = (0..subnets)
+        .map(|_| Net { ia_red: vec![] })
+        .collect::<Vec<_>>();
+    for id in 0..transitions {
+        let subnet = subnet_of(id);
+        let delayed = (0..fanout)
+            .map(|_| {
+                let target = rng.gen_range(0..transitions);
+                let target_id = if subnet_of(target) == subnet {
+                    target as isize
+                } else {
+                    -(target as isize + 1)
+                };
+                (target_id, 0)
+            })
+            .collect();
+        nets[subnet].ia_red.push(Transition {
+            ii_idglobal: id,
+            ii_valor: 0,
+            ii_tiempo: 0,
+            ii_duracion_disparo: rng.gen_range(1..=MAX_DURATION),
+            ii_listactes_iul: vec![(id as isize, 1)],
+            ii_listactes_pul: delayed,
+            ib_desalida: false,
+            reward_rate: 0.0,
+            firing_cost: 0.0,
+        });
+    }
+
+    nets
+}
+
+/// Writes every segment of `nets` to `folder` as subnet-<index>.json, numbered so they
+/// load back in the same order.
+pub fn write(folder: &Path, nets: &[Net]) -> Result<()> {
+    std::fs::create_dir_all(folder)?;
+    let digits = nets.len().to_string().len();
+    for (index, net) in nets.iter().enumerate() {
+        let path = folder.join(format!("subnet-{index:0digits$}.json"));
+        std::fs::write(path, serde_json::to_string(net)?)?;
+    }
+
+    Ok(())
+}
@@ -1,22 +1,96 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
+/// A net file, keyed as by the reference simulator, or by the English alias of every key,
+/// which is always written back in the former
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Net {
+    #[serde(alias = "transitions")]
     pub ia_red: Vec<Transition>,
 }
 
+impl Net {
+    /// Checks what the format cannot tell by itself: transitions defined once, and
+    /// instructions with non-negative ids setting transitions of this net, as those of other
+    /// segments are written -(id + 1). Returns the field at fault and why.
+    pub fn validate(&self) -> Result<(), (String, String)> {
+        let mut ids = HashSet::new();
+        for (index, transition) in self.ia_red.iter().enumerate() {
+            if !ids.insert(transition.ii_idglobal) {
+                return Err((
+                    format!("ia_red[{index}].ii_idglobal"),
+                    format!("transition {} is defined twice", transition.ii_idglobal),
+                ));
+            }
+        }
+
+        for (index, transition) in self.ia_red.iter().enumerate() {
+            let lists = [
+                ("ii_listactes_IUL", &transition.ii_listactes_iul),
+                ("ii_listactes_PUL", &transition.ii_listactes_pul),
+            ];
+            for (list, instructions) in lists {
+                for (position, &(id, _)) in instructions.iter().enumerate() {
+                    if id >= 0 && !ids.contains(&(id as usize)) {
+                        return Err((
+                            format!("ia_red[{index}].{list}[{position}]"),
+                            format!(
+                                "transition {id} is not in this net, one of another segment \
+                                 is written {}",
+                                -(id + 1)
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transition {
+    #[serde(alias = "global_id")]
     pub ii_idglobal: usize,
+    #[serde(alias = "value")]
     pub ii_valor: isize,
+    #[serde(alias = "time")]
     pub ii_tiempo: usize,
+    #[serde(alias = "fire_duration")]
     pub ii_duracion_disparo: usize,
 
-    #[serde(rename = "ii_listactes_IUL")]
+    #[serde(rename = "ii_listactes_IUL", alias = "immediate_list")]
     pub ii_listactes_iul: Vec<(isize, isize)>,
 
-    #[serde(rename = "ii_listactes_PUL")]
+    #[serde(rename = "ii_listactes_PUL", alias = "delayed_list")]
     pub ii_listactes_pul: Vec<(isize, isize)>,
 
+    #[serde(alias = "is_output")]
     pub ib_desalida: bool,
+
+    /// Reward earned per clock unit while the transition is enabled
+    #[serde(default)]
+    pub reward_rate: f64,
+
+    /// Cost charged every time the transition fires
+    #[serde(default)]
+    pub firing_cost: f64,
+}
+
+/// As much of a net file as the nodes not hosting it read: the id of every transition and
+/// the transitions its delayed instructions set, every other field skipped over unparsed
+#[derive(Deserialize, Debug)]
+pub struct Index {
+    #[serde(alias = "transitions")]
+    pub ia_red: Vec<IndexedTransition>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IndexedTransition {
+    #[serde(alias = "global_id")]
+    pub ii_idglobal: usize,
+
+    #[serde(rename = "ii_listactes_PUL", alias = "delayed_list")]
+    pub ii_listactes_pul: Vec<(isize, isize)>,
 }
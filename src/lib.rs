@@ -0,0 +1,27 @@
+pub mod address;
+pub mod capture;
+pub mod clocks;
+pub mod config;
+pub mod dashboard;
+pub mod discovery;
+pub mod dot;
+pub mod dsl;
+pub mod engine;
+pub mod error;
+pub mod firings;
+pub mod generate;
+pub mod json;
+pub mod logging;
+pub mod mermaid;
+pub mod model;
+pub mod otel;
+pub mod pnml;
+#[cfg(any(feature = "grpc", feature = "protobuf"))]
+pub mod proto;
+pub mod registry;
+pub mod retry;
+pub mod reward;
+pub mod stats;
+pub mod tls;
+pub mod transport;
+pub mod web;
@@ -0,0 +1,410 @@
+use crate::config::{Config, LogFormat, LogLevel, LogTarget};
+use crate::error::Result;
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::Id;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Target of the net logged after every step of a tick
+pub const NET: &str = "petri::net";
+/// Target of every transition fired
+pub const FIRE: &str = "petri::fire";
+/// Target of every message sent or received
+pub const NETWORK: &str = "petri::network";
+
+/// Local time down to the nanosecond, as the log always had it
+struct LocalTime;
+
+impl FormatTime for LocalTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.f"))
+    }
+}
+
+/// Events kept for the dashboard
+const TAIL: usize = 200;
+
+/// Latest events, once --tui asked for them
+static LATEST: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+
+/// Latest events logged, oldest first, as one line each.
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub fn tail() -> Vec<String> {
+    LATEST
+        .get()
+        .map(|latest| latest.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Keeps the latest events for the dashboard
+struct Tail;
+
+impl<S: Subscriber> Layer<S> for Tail {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut payload = Payload(Map::new());
+        event.record(&mut payload);
+        let mut line = format!("{:>5}", event.metadata().level());
+        if let Some(Value::String(message)) = payload.0.remove("message") {
+            line = format!("{line} {message}");
+        }
+        payload
+            .0
+            .into_iter()
+            .for_each(|(field, value)| match value {
+                Value::String(value) => line = format!("{line} {field}={value}"),
+                value => line = format!("{line} {field}={value}"),
+            });
+
+        let mut latest = LATEST.get_or_init(Default::default).lock().unwrap();
+        if latest.len() == TAIL {
+            latest.pop_front();
+        }
+        latest.push_back(line);
+    }
+}
+
+/// What the thread writing the log is handed
+enum ToWriter {
+    /// A whole record, formatted
+    Record(Vec<u8>),
+    /// Write out what is buffered, telling when done if asked to wait
+    Flush(Option<SyncSender<()>>),
+}
+
+/// The thread writing the log, once `init` started it
+static WRITER: OnceLock<Sender<ToWriter>> = OnceLock::new();
+
+/// Writes out whatever of the log is still buffered, waiting until it is.
+pub fn flush() {
+    let Some(writer) = WRITER.get() else {
+        return;
+    };
+    let (done_tx, done) = sync_channel(1);
+    // a writer gone, e.g. after a panic of its own, has nothing left to write
+    if writer.send(ToWriter::Flush(Some(done_tx))).is_ok() {
+        let _ = done.recv();
+    }
+}
+
+/// Has whatever of the log is still buffered written out, without waiting for it.
+fn flush_later() {
+    if let Some(writer) = WRITER.get() {
+        let _ = writer.send(ToWriter::Flush(None));
+    }
+}
+
+/// Writes every record to `output` on a thread of its own, so neither the IO nor the
+/// rotation of the log holds up the simulation.
+fn start_writer(mut output: Box<dyn Write + Send>) -> Sender<ToWriter> {
+    let (writer, records) = channel();
+    thread::spawn(move || {
+        for record in records {
+            match record {
+                ToWriter::Record(record) => {
+                    let _ = output.write_all(&record);
+                }
+                ToWriter::Flush(done) => {
+                    let _ = output.flush();
+                    if let Some(done) = done {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        }
+        let _ = output.flush();
+    });
+    writer
+}
+
+/// Flushes the log file once the node is done, as the subscriber writing it never is
+/// dropped.
+pub struct LogGuard;
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        flush();
+    }
+}
+
+/// Has the log written out at the end of every tick, so a crash loses little more than the
+/// tick it happened in
+struct FlushEveryTick;
+
+impl<S> Layer<S> for FlushEveryTick
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if ctx.span(id).is_some_and(|span| span.name() == "tick") {
+            flush_later();
+        }
+    }
+}
+
+/// One JSON object per line and event: its timestamp, level, the clock and node of the
+/// spans it happened in, what happened and the fields that came with it, e.g.
+/// {"timestamp":"...","level":"DEBUG","node":"127.0.0.1:7001","clock":4,"event":"FIRED",
+/// "target":"petri::fire","payload":{"transition":2}}
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut record = Map::new();
+        let mut timestamp = String::new();
+        LocalTime.format_time(&mut Writer::new(&mut timestamp))?;
+        record.insert("timestamp".into(), timestamp.into());
+        record.insert("level".into(), event.metadata().level().as_str().into());
+
+        // spans are formatted by JsonFields, inner ones win
+        ctx.event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .for_each(|span| {
+                let extensions = span.extensions();
+                if let Some(Ok(Value::Object(fields))) = extensions
+                    .get::<FormattedFields<N>>()
+                    .map(|fields| serde_json::from_str::<Value>(fields))
+                {
+                    record.extend(fields);
+                }
+            });
+
+        let mut payload = Payload(Map::new());
+        event.record(&mut payload);
+        let kind = payload.0.remove("message").unwrap_or_default();
+        record.insert("event".into(), kind);
+        record.insert("target".into(), event.metadata().target().into());
+        record.insert("payload".into(), Value::Object(payload.0));
+
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+/// Fields of an event, numbers and booleans kept as such and everything else as text
+struct Payload(Map<String, Value>);
+
+impl Visit for Payload {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+/// <node>.log, moved to <node>.log.1 once it grows past `max_size` or gets older than
+/// `max_age`, the logs rotated before moving one number up, and the oldest of the `keep`
+/// deleted
+struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    written: u64,
+    opened: Instant,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn new(config: &Config) -> Result<Self> {
+        let path = PathBuf::from(format!("{}.log", config.node));
+        Ok(Self {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+            written: 0,
+            opened: Instant::now(),
+            max_size: config.log_rotate_size,
+            max_age: config.log_rotate_secs.map(Duration::from_secs),
+            keep: config.log_keep,
+        })
+    }
+
+    fn rotated(&self, generation: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{generation}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        match fs::remove_file(self.rotated(self.keep)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+        for generation in (1..self.keep).rev() {
+            match fs::rename(self.rotated(generation), self.rotated(generation + 1)) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    /// Takes a whole record at a time, so rotating never splits one over two files.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let full = self
+            .max_size
+            .is_some_and(|max_size| self.written + buf.len() as u64 > max_size);
+        let old = self
+            .max_age
+            .is_some_and(|max_age| self.opened.elapsed() >= max_age);
+        if self.written > 0 && (full || old) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Handle on the thread writing the log, handed to every event written, which is
+/// formatted in full before it is handed over
+struct ToWriterThread(Sender<ToWriter>);
+
+impl Write for ToWriterThread {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .send(ToWriter::Record(buf.to_vec()))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs warnings and errors to stderr on this thread until the guard is dropped, for what
+/// happens before `init` can name the log after the node, e.g. while it registers.
+pub fn early() -> tracing::subscriber::DefaultGuard {
+    tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new("warn"))
+            .with_timer(LocalTime)
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_target(false)
+            .with_writer(std::io::stderr)
+            .finish(),
+    )
+}
+
+/// Sends the events of the simulation to --log-target, written as --log-format, keeping
+/// those up to --log-level that --log-filter lets through. Other crates are only heard
+/// from for warnings.
+pub fn init(config: &Config) -> Result<LogGuard> {
+    let level = match config.log_level {
+        LogLevel::Error => "error",
+        LogLevel::Warn => "warn",
+        LogLevel::Info => "info",
+        LogLevel::Debug => "debug",
+        LogLevel::Trace => "trace",
+    };
+    let mut directives = format!("warn,petri={level}");
+    if let Some(filter) = &config.log_filter {
+        directives = format!("{directives},{filter}");
+    }
+    let filter = EnvFilter::try_new(&directives).map_err(|error| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "--log-filter {:?}: {error}",
+                config.log_filter.as_deref().unwrap_or_default()
+            ),
+        )
+    })?;
+
+    let output: Box<dyn Write + Send> = match config.log_target {
+        LogTarget::File => Box::new(RotatingFile::new(config)?),
+        LogTarget::Stdout => Box::new(std::io::stdout()),
+    };
+    let writer = start_writer(output);
+    let _ = WRITER.set(writer.clone());
+    let writer = BoxMakeWriter::new(move || ToWriterThread(writer.clone()));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_timer(LocalTime)
+        .with_ansi(config.log_target == LogTarget::Stdout && std::io::stdout().is_terminal())
+        .with_target(false)
+        .with_writer(writer);
+    match config.log_format {
+        LogFormat::Text => builder
+            .finish()
+            .with(FlushEveryTick)
+            .with(config.tui.then_some(Tail))
+            .try_init(),
+        LogFormat::Json => builder
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonLines)
+            .finish()
+            .with(FlushEveryTick)
+            .with(config.tui.then_some(Tail))
+            .try_init(),
+    }
+    .map_err(std::io::Error::other)?;
+
+    // a panicking thread other than the engine's never drops the guard
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        hook(info);
+        flush();
+    }));
+
+    Ok(LogGuard)
+}
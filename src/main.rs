@@ -1,42 +1,103 @@
-mod engine;
-mod error;
-mod json;
-mod model;
-
-use std::path::PathBuf;
-
-use error::Result;
-
-use crate::engine::Engine;
-use clap::Parser;
-
-#[derive(Parser, Debug)]
-struct Args {
-    /// Last simulation clock    
-    #[arg(long)]
-    terminal_clock: usize,
-
-    // Executing node ip:port address
-    #[arg(long)]
-    node: String,
-
-    // List of all ip:port addresses that will take part in the simulation
-    #[arg(long, num_args = 1..)]
-    nodes: Vec<String>,
-
-    /// Folder with .json Petri nets    
-    #[arg(long)]
-    nets_folder: PathBuf,
-}
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use petri::config::{Command, Config, ExportFormat, GraphFormat};
+use petri::engine::{self, Engine};
+use petri::error::Result;
+use petri::{capture, discovery, dot, generate, logging, mermaid, pnml, registry, stats};
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut config = Config::parse();
+    if config.needs_nets() && config.nets_folder.is_none() {
+        Config::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --nets-folder <NETS_FOLDER>",
+            )
+            .exit();
+    }
+    let early_log = logging::early();
+    match &config.command {
+        Some(Command::Export { format }) => {
+            let nets = engine::load_nets(config.nets_folder()?)?;
+            let exported = match format {
+                ExportFormat::Pnml => pnml::write(&nets),
+                ExportFormat::Json => nets
+                    .iter()
+                    .map(|net| Ok(net.to_legacy_json()? + "\n"))
+                    .collect::<Result<String>>()?,
+            };
+            print!("{exported}");
+            return Ok(());
+        }
+        Some(Command::Graph {
+            output,
+            nodes,
+            format,
+            topology,
+        }) => {
+            let nets = engine::load_nets(config.nets_folder()?)?;
+            let partition = dot::Partition::new(&nets, nodes);
+            let graph = match format {
+                GraphFormat::Dot => dot::write(&nets, &partition, *topology),
+                GraphFormat::Mermaid => mermaid::write(&nets, &partition, *topology),
+            };
+            match output {
+                Some(output) => std::fs::write(output, graph)?,
+                None => print!("{graph}"),
+            }
+            return Ok(());
+        }
+        Some(Command::Stats { nodes }) => {
+            let nets = engine::load_nets(config.nets_folder()?)?;
+            let partition = dot::Partition::new(&nets, nodes);
+            print!("{}", stats::write(&nets, &partition));
+            return Ok(());
+        }
+        Some(Command::GenNet {
+            transitions,
+            fanout,
+            subnets,
+            seed,
+        }) => {
+            let nets = generate::generate(*transitions, *fanout, *subnets, *seed);
+            return generate::write(config.nets_folder()?, &nets);
+        }
+        Some(Command::InspectCapture {
+            file,
+            direction,
+            peer,
+            kind,
+            from_clock,
+            to_clock,
+        }) => {
+            let filter = capture::Filter {
+                direction: *direction,
+                peer: peer.clone(),
+                kinds: kind.clone(),
+                from_clock: *from_clock,
+                to_clock: *to_clock,
+            };
+            return capture::inspect(file, &filter, &mut std::io::stdout().lock());
+        }
+        None => {}
+    }
+    if let Some(address) = &config.serve_registry {
+        return registry::serve(address, config.expected_nodes.unwrap_or_default());
+    }
+    if let Some(registry) = config.registry.clone() {
+        config = registry::join(config, &registry)?;
+    }
+    // announced for as long as the node runs, to peers that are late to look for it
+    let _announcement = if config.mdns {
+        let (discovered, announcement) = discovery::join(config)?;
+        config = discovered;
+        Some(announcement)
+    } else {
+        None
+    };
 
-    let mut engine = Engine::new(
-        args.terminal_clock,
-        args.node,
-        &args.nodes,
-        &args.nets_folder,
-    )?;
+    drop(early_log);
+    let _log = logging::init(&config)?;
+    let mut engine = Engine::new(&config)?;
     engine.run()
 }
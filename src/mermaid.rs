@@ -0,0 +1,72 @@
+use crate::dot::{edges, Partition, COLORS};
+use crate::model::Net;
+use std::fmt::Write;
+
+/// Writes `nets` as a Mermaid flowchart to paste into Markdown, the same drawing as
+/// [`crate::dot::write`]: solid arrows for delayed instructions, dotted ones for immediate
+/// instructions, red ones crossing from one node to another. With `topology`, draws the
+/// nodes instead, each pointing to those it feeds.
+pub fn write(nets: &[Net], partition: &Partition, topology: bool) -> String {
+    let mut chart = String::from("flowchart LR\n");
+    if topology {
+        for (owner, name) in partition.names.iter().enumerate() {
+            let _ = writeln!(chart, "  n{owner}[{name:?}]");
+            let _ = writeln!(
+                chart,
+                "  style n{owner} fill:{}",
+                COLORS[owner % COLORS.len()]
+            );
+        }
+        for ((from, to), instructions) in partition.feeds(nets) {
+            let _ = writeln!(chart, "  n{from} -->|{instructions}| n{to}");
+        }
+        return chart;
+    }
+
+    for (owner, name) in partition.names.iter().enumerate() {
+        let _ = writeln!(chart, "  subgraph n{owner} [{name:?}]");
+        for transition in partition.transitions_of(nets, owner) {
+            let _ = writeln!(
+                chart,
+                "    t{}[\"t{}<br/>value={} dur={}\"]",
+                transition.id, transition.id, transition.value, transition.duration
+            );
+        }
+        chart.push_str("  end\n");
+        let _ = writeln!(
+            chart,
+            "  classDef owner{owner} fill:{}",
+            COLORS[owner % COLORS.len()]
+        );
+        let ids = partition
+            .transitions_of(nets, owner)
+            .map(|transition| format!("t{}", transition.id))
+            .collect::<Vec<_>>();
+        if !ids.is_empty() {
+            let _ = writeln!(chart, "  class {} owner{owner}", ids.join(","));
+        }
+    }
+
+    // links are styled by their position among all links
+    let mut crossing = Vec::new();
+    let mut links = 0;
+    for transition in nets.iter().flat_map(|net| &net.transitions) {
+        for (instruction, immediate) in edges(transition) {
+            let arrow = if immediate { "-.->" } else { "-->" };
+            let _ = writeln!(
+                chart,
+                "  t{} {arrow}|{}| t{}",
+                transition.id, instruction.value, instruction.transition_id
+            );
+            if partition.crosses(transition, instruction) {
+                crossing.push(links.to_string());
+            }
+            links += 1;
+        }
+    }
+    if !crossing.is_empty() {
+        let _ = writeln!(chart, "  linkStyle {} stroke:red", crossing.join(","));
+    }
+
+    chart
+}
@@ -1,21 +1,248 @@
 use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::clocks::VectorClock;
+use crate::error::{AppError, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
+use std::path::Path;
 use std::sync::mpsc::Receiver;
-use std::{fs::File, io::BufReader, path::Path};
+use std::sync::Arc;
+
+/// Name of a node as held in memory, shared rather than copied into every event, channel
+/// and counter that refers to it. Names are plain Strings on the wire and in the config.
+pub type NodeId = Arc<str>;
 
 #[derive(Debug, Clone)]
 pub struct Net {
+    /// Never added to nor removed from once the net is built, only updated
     pub transitions: Vec<Transition>,
+    /// Position of every transition in `transitions` by id, shared by every copy of the net
+    positions: Arc<HashMap<usize, usize>>,
+    /// Positions of the transitions by their clock, so a tick only looks at those due then
+    by_clock: BTreeMap<usize, Vec<usize>>,
 }
 
 impl Net {
+    fn with_transitions(transitions: Vec<Transition>) -> Self {
+        let positions = transitions
+            .iter()
+            .enumerate()
+            .map(|(position, transition)| (transition.id, position))
+            .collect();
+        let by_clock = transitions.iter().enumerate().fold(
+            BTreeMap::<usize, Vec<usize>>::new(),
+            |mut acc, (position, transition)| {
+                acc.entry(transition.clock).or_default().push(position);
+                acc
+            },
+        );
+
+        Self {
+            transitions,
+            positions: Arc::new(positions),
+            by_clock,
+        }
+    }
+
+    /// Transition `id`, whose clock is only to be changed through `set_marking`, which
+    /// keeps the transitions indexed by clock.
+    pub fn transition_mut(&mut self, id: usize) -> Option<&mut Transition> {
+        self.positions
+            .get(&id)
+            .map(|&position| &mut self.transitions[position])
+    }
+
+    /// The transitions in blocks of `size` consecutive positions, the last one possibly
+    /// shorter, for a worker each to change the values of, along with the position of
+    /// every transition by id. Their clocks are only to be changed through `set_marking`.
+    pub fn shards_mut(
+        &mut self,
+        size: usize,
+    ) -> (
+        std::slice::ChunksMut<'_, Transition>,
+        &HashMap<usize, usize>,
+    ) {
+        (self.transitions.chunks_mut(size), &self.positions)
+    }
+
+    /// Sets the clock and value of transition `id`, if the net has it.
+    pub fn set_marking(&mut self, id: usize, clock: usize, value: isize) {
+        let Some(&position) = self.positions.get(&id) else {
+            return;
+        };
+        let transition = &mut self.transitions[position];
+        transition.value = value;
+        if transition.clock == clock {
+            return;
+        }
+
+        if let Some(positions) = self.by_clock.get_mut(&transition.clock) {
+            positions.retain(|&other| other != position);
+            if positions.is_empty() {
+                self.by_clock.remove(&transition.clock);
+            }
+        }
+        transition.clock = clock;
+        self.by_clock.entry(clock).or_default().push(position);
+    }
+
+    /// Positions of the transitions whose clock is `clock`, in no particular order.
+    pub fn scheduled_at(&self, clock: usize) -> &[usize] {
+        self.by_clock.get(&clock).map_or(&[], Vec::as_slice)
+    }
+
+    /// Earliest clock from `from` on at which some transition is enabled, if any.
+    pub fn next_enabled_clock(&self, from: usize) -> Option<usize> {
+        self.by_clock
+            .range(from..)
+            .find(|(_, positions)| {
+                positions
+                    .iter()
+                    .any(|&position| self.transitions[position].value <= 0)
+            })
+            .map(|(&clock, _)| clock)
+    }
+
+    /// Net of the `ia_red` file at `path`, in JSON, YAML (.yaml or .yml) or TOML (.toml) by
+    /// its extension. JSON is parsed as it is read, never held whole in memory.
     pub fn new<T: AsRef<Path>>(path: T) -> Result<Net> {
-        let file = File::open(path)?;
-        let file = BufReader::new(file);
-        let net: crate::json::Net = serde_json::from_reader(file)?;
+        let path = path.as_ref();
+        let text =
+            || std::fs::read_to_string(path).map_err(|error| invalid(path, error.to_string()));
+        let net: crate::json::Net = match path.extension().and_then(|extension| extension.to_str())
+        {
+            Some("yaml" | "yml") => deserialize(
+                path,
+                serde_yaml::Deserializer::from_str(&text()?),
+                |error: &serde_yaml::Error| {
+                    let position = error.location().map(|at| (at.line(), at.column()));
+                    (position, unplaced(error.to_string(), position))
+                },
+            )?,
+            Some("toml") => {
+                let text = text()?;
+                deserialize(
+                    path,
+                    toml::Deserializer::new(&text),
+                    |error: &toml::de::Error| {
+                        let position = error.span().map(|span| {
+                            let before = &text[..span.start];
+                            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+                            (
+                                before.matches('\n').count() + 1,
+                                before[line_start..].chars().count() + 1,
+                            )
+                        });
+                        (position, error.message().to_string())
+                    },
+                )?
+            }
+            _ => deserialize(path, &mut json_reader(path)?, locate_json)?,
+        };
+        net.validate()
+            .map_err(|(field, reason)| AppError::InvalidNet {
+                path: path.to_path_buf(),
+                field: Some(field),
+                line: None,
+                column: None,
+                reason,
+            })?;
 
+        Ok(net.into())
+    }
+
+    /// Index of the single segment of the net file at `path`, which is all the nodes not
+    /// hosting it need. A .json file is streamed and only its ids and delayed instructions
+    /// kept, so indexing it costs a fraction of loading it.
+    pub fn index<T: AsRef<Path>>(path: T) -> Result<SegmentIndex> {
+        let path = path.as_ref();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            let nets = Net::load(path)?;
+            return Ok(SegmentIndex::from(&Net::merge(nets)));
+        }
+
+        let index: crate::json::Index = deserialize(path, &mut json_reader(path)?, locate_json)?;
+        let ids = index
+            .ia_red
+            .iter()
+            .map(|transition| transition.ii_idglobal)
+            .collect();
+        let feeds = index
+            .ia_red
+            .iter()
+            .flat_map(|transition| {
+                transition
+                    .ii_listactes_pul
+                    .iter()
+                    .map(Instruction::new)
+                    .filter(|instruction| instruction.is_external)
+                    .map(|instruction| (transition.ii_idglobal, instruction.transition_id))
+            })
+            .collect();
+
+        Ok(SegmentIndex { ids, feeds })
+    }
+
+    /// Segments of the net file at `path`: the one of an `ia_red` file or of a .petri text
+    /// file, or one per top-level page of a .pnml file.
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Vec<Net>> {
+        let path = path.as_ref();
+        let text =
+            || std::fs::read_to_string(path).map_err(|error| invalid(path, error.to_string()));
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("pnml") => {
+                let nets = crate::pnml::parse(&text()?).map_err(|reason| invalid(path, reason))?;
+                Ok(nets.into_iter().map(Net::from).collect())
+            }
+            Some("petri") => {
+                let invalid_at = |line, column, reason| AppError::InvalidNet {
+                    path: path.to_path_buf(),
+                    field: None,
+                    line,
+                    column,
+                    reason,
+                };
+                let net = crate::dsl::parse(&text()?).map_err(|(line, column, reason)| {
+                    invalid_at(Some(line), Some(column), reason)
+                })?;
+                net.validate()
+                    .map_err(|(_, reason)| invalid_at(None, None, reason))?;
+                Ok(vec![net.into()])
+            }
+            _ => Ok(vec![Net::new(path)?]),
+        }
+    }
+
+    /// The net in the `ia_red` JSON format it is read from, with its current values and
+    /// clocks, for the reference simulator to pick up from there.
+    pub fn to_legacy_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&crate::json::Net::from(self))?)
+    }
+
+    /// Concatenates the transitions of several segments into a single net.
+    pub fn merge<I: IntoIterator<Item = Net>>(nets: I) -> Net {
+        let transitions = nets.into_iter().flat_map(|net| net.transitions).collect();
+
+        Self::with_transitions(transitions)
+    }
+
+    /// Turns external instructions whose target satisfies `is_local` into internal ones.
+    pub fn localize<F: Fn(usize) -> bool>(&mut self, is_local: F) {
+        self.transitions
+            .iter_mut()
+            .flat_map(|transition| {
+                transition
+                    .immediate_instructions
+                    .iter_mut()
+                    .chain(transition.delayed_instructions.iter_mut())
+            })
+            .filter(|instruction| instruction.is_external && is_local(instruction.transition_id))
+            .for_each(|instruction| instruction.is_external = false);
+    }
+}
+
+impl From<crate::json::Net> for Net {
+    fn from(net: crate::json::Net) -> Self {
         let transitions = net
             .ia_red
             .into_iter()
@@ -27,12 +254,98 @@ impl Net {
                 immediate_instructions: parse_instructions(&transition.ii_listactes_iul),
                 delayed_instructions: parse_instructions(&transition.ii_listactes_pul),
                 is_output: transition.ib_desalida,
+                reward_rate: transition.reward_rate,
+                firing_cost: transition.firing_cost,
             })
             .collect();
 
-        let net = Self { transitions };
+        Self::with_transitions(transitions)
+    }
+}
+
+impl From<&Net> for crate::json::Net {
+    fn from(net: &Net) -> Self {
+        let ia_red = net
+            .transitions
+            .iter()
+            .map(|transition| crate::json::Transition {
+                ii_idglobal: transition.id,
+                ii_valor: transition.value,
+                ii_tiempo: transition.clock,
+                ii_duracion_disparo: transition.duration,
+                ii_listactes_iul: unparse_instructions(&transition.immediate_instructions),
+                ii_listactes_pul: unparse_instructions(&transition.delayed_instructions),
+                ib_desalida: transition.is_output,
+                reward_rate: transition.reward_rate,
+                firing_cost: transition.firing_cost,
+            })
+            .collect();
+
+        Self { ia_red }
+    }
+}
+
+/// Deserializes the net file at `path`, `locate` telling where in it and why it failed.
+fn deserialize<'de, T, D, F>(path: &Path, deserializer: D, locate: F) -> Result<T>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+    F: FnOnce(&D::Error) -> (Option<(usize, usize)>, String),
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|error| {
+        let field = error.path().to_string();
+        let (position, reason) = locate(error.inner());
+        // some formats lead with the field already
+        let reason = match reason.strip_prefix(&format!("{field}: ")) {
+            Some(reason) => reason.to_string(),
+            None => reason,
+        };
+        AppError::InvalidNet {
+            path: path.to_path_buf(),
+            field: (field != ".").then_some(field),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            reason,
+        }
+    })
+}
 
-        Ok(net)
+/// Deserializer of the JSON net file at `path`, reading it as it goes.
+fn json_reader(
+    path: &Path,
+) -> Result<serde_json::Deserializer<serde_json::de::IoRead<std::io::BufReader<std::fs::File>>>> {
+    let file = std::fs::File::open(path).map_err(|error| invalid(path, error.to_string()))?;
+    Ok(serde_json::Deserializer::from_reader(
+        std::io::BufReader::new(file),
+    ))
+}
+
+/// Where in a JSON net file and why it failed to deserialize.
+fn locate_json(error: &serde_json::Error) -> (Option<(usize, usize)>, String) {
+    let position = Some((error.line(), error.column()));
+    (position, unplaced(error.to_string(), position))
+}
+
+/// `message` without the position it ends with, which is told apart.
+fn unplaced(message: String, position: Option<(usize, usize)>) -> String {
+    let Some((line, column)) = position else {
+        return message;
+    };
+    let suffix = format!(" at line {line} column {column}");
+    match message.strip_suffix(&suffix) {
+        Some(message) => message.to_string(),
+        None => message,
+    }
+}
+
+/// Error for the net file at `path`, without a known place in it.
+pub fn invalid(path: &Path, reason: String) -> AppError {
+    AppError::InvalidNet {
+        path: path.to_path_buf(),
+        field: None,
+        line: None,
+        column: None,
+        reason,
     }
 }
 
@@ -40,6 +353,54 @@ fn parse_instructions(instructions: &[(isize, isize)]) -> Vec<Instruction> {
     instructions.iter().map(Instruction::new).collect()
 }
 
+fn unparse_instructions(instructions: &[Instruction]) -> Vec<(isize, isize)> {
+    instructions
+        .iter()
+        .map(|instruction| {
+            let id = instruction.transition_id as isize;
+            let id = if instruction.is_external {
+                -(id + 1)
+            } else {
+                id
+            };
+            (id, instruction.value)
+        })
+        .collect()
+}
+
+/// A segment as far as the nodes not hosting it need to know it
+#[derive(Debug, Clone, Default)]
+pub struct SegmentIndex {
+    /// Every transition it defines
+    pub ids: Vec<usize>,
+    /// Every transition of another segment its delayed instructions set, after the one
+    /// setting it
+    pub feeds: Vec<(usize, usize)>,
+}
+
+impl From<&Net> for SegmentIndex {
+    fn from(net: &Net) -> Self {
+        Self {
+            ids: net
+                .transitions
+                .iter()
+                .map(|transition| transition.id)
+                .collect(),
+            feeds: net
+                .transitions
+                .iter()
+                .flat_map(|transition| {
+                    transition
+                        .delayed_instructions
+                        .iter()
+                        .filter(|instruction| instruction.is_external)
+                        .map(|instruction| (transition.id, instruction.transition_id))
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Transition {
     pub id: usize,
@@ -49,9 +410,11 @@ pub struct Transition {
     pub immediate_instructions: Vec<Instruction>,
     pub delayed_instructions: Vec<Instruction>,
     pub is_output: bool,
+    pub reward_rate: f64,
+    pub firing_cost: f64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Instruction {
     pub transition_id: usize,
     pub value: isize,
@@ -76,42 +439,432 @@ impl Instruction {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ActiveEvent {
-    pub feeding_node: String,
+    pub feeding_node: NodeId,
     pub transition_id: usize,
     pub value: isize,
     pub clock: usize,
+    /// Anti-message cancelling a previously sent event (optimistic synchronization)
+    #[serde(default)]
+    pub anti: bool,
+    /// GVT round the sender was in when sending (optimistic synchronization)
+    #[serde(default)]
+    pub color: usize,
+    /// Position among all events generated by the sender, used to break ties
+    #[serde(default)]
+    pub seq: usize,
+    /// Sender's vector clock at the time of sending
+    #[serde(default)]
+    pub vector_clock: VectorClock,
+    /// Position among all messages the sender sent on this channel
+    #[serde(default)]
+    pub channel_seq: usize,
+    /// Sender's Lamport clock at the time of sending
+    #[serde(default)]
+    pub lamport: usize,
+    /// Span that sent the event, for the receiving node to continue its trace
+    #[serde(default)]
+    pub trace: Option<TraceContext>,
+}
+
+/// OpenTelemetry trace context travelling along with an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// Unix time of sending, in nanoseconds
+    pub sent_at: u64,
+}
+
+impl ActiveEvent {
+    /// Events of the same clock are applied in this order, regardless of arrival order,
+    /// so that runs are reproducible.
+    pub fn order_key(&self) -> (&str, usize, usize) {
+        (&self.feeding_node, self.transition_id, self.seq)
+    }
+
+    /// Whether `self` is the anti-message of `other`, or vice versa.
+    pub fn cancels(&self, other: &ActiveEvent) -> bool {
+        self.anti != other.anti
+            && self.feeding_node == other.feeding_node
+            && self.transition_id == other.transition_id
+            && self.value == other.value
+            && self.clock == other.clock
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PassiveEvent {
-    pub feeding_node: String,
+    pub feeding_node: NodeId,
+    pub clock: usize,
+    /// Minimum firing duration of the sender's transitions feeding this channel
+    #[serde(default)]
+    pub lookahead: usize,
+    /// Position among all messages the sender sent on this channel
+    #[serde(default)]
+    pub channel_seq: usize,
+    /// Sender's Lamport clock at the time of sending
+    #[serde(default)]
+    pub lamport: usize,
+}
+
+/// Sent by a blocked fed node asking one of its feeding nodes for a null message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockRequest {
+    pub requesting_node: String,
     pub clock: usize,
 }
 
+/// Token circulated around the ring of nodes to compute Global Virtual Time.
+/// Once `gvt` is set it is an announcement of the result rather than a token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GvtToken {
+    pub round: usize,
+    /// Events of the previous round still in flight (sent minus received)
+    pub in_transit: isize,
+    pub min_clock: usize,
+    pub gvt: Option<usize>,
+}
+
+/// Sent by every node to the coordinator once it fired the transitions of its clock
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GenericEvent {
+pub struct BarrierReport {
+    pub reporting_node: String,
+    /// Earliest clock at which the node has something scheduled
+    pub next_clock: usize,
+    /// Events sent this round, per fed node, along with the earliest of their clocks
+    pub sent: HashMap<String, usize>,
+    pub min_sent_clock: usize,
+}
+
+/// Broadcast by the coordinator: everyone advances to `granted_clock` once
+/// `expected` events (counted since the start of the run) have been received
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BarrierGrant {
+    pub granted_clock: usize,
+    pub expected: usize,
+    /// Nodes hosting the segments from the granted clock on, when the operator changed
+    /// the membership
+    #[serde(default)]
+    pub membership: Option<Vec<String>>,
+}
+
+/// Sent by every node to every other one when the membership changes, with the state of
+/// the transitions the receiver takes over from the sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handoff {
+    pub sender: String,
+    /// Run the sender takes part in, for a joining node to take part in it as well
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Transition id to its (clock, value)
+    pub marking: BTreeMap<usize, (usize, isize)>,
+    pub internal_active_events: Vec<ActiveEvent>,
+}
+
+/// Sent to every peer once this node is listening, during the startup handshake
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ready {
+    pub ready_node: String,
+    /// Run the sender takes part in, if it knows it yet
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Set by a node that recovered from a crash, or answering one: sequence number of
+    /// the first message on the channel from the receiver the sender has not consumed,
+    /// which the receiver resends from
+    #[serde(default)]
+    pub resume_from: Option<usize>,
+    /// Set by a node that recovered from a crash, which the receiver answers with a
+    /// `Ready` of its own, rather than by a node answering one
+    #[serde(default)]
+    pub recovered: bool,
+}
+
+/// Chandy-Lamport marker. Sent on every outgoing channel right after recording the local
+/// state, with `sent` the number of messages sent on the channel so far; the initiator
+/// also sends one without `sent` to every node, so nodes it does not feed take part too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMarker {
+    pub snapshot_id: String,
+    pub sender: String,
+    pub sent: Option<usize>,
+}
+
+/// Marker circulated around the ring of nodes to detect a global deadlock.
+/// Once `recovery` is set it announces the clock every channel may advance to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadlockMarker {
+    pub pass: usize,
+    /// Whether every node visited so far stayed blocked since the previous pass
+    pub all_blocked: bool,
+    /// Active events sent minus received by the nodes visited so far
+    pub in_transit: isize,
+    /// Earliest clock any visited node has something scheduled at
+    pub min_next: usize,
+    pub recovery: Option<usize>,
+}
+
+/// Marker circulated around the ring of nodes to detect that the cluster has terminated,
+/// with nothing left to fire anywhere and nothing left in flight between nodes.
+/// Once `terminated` is set it announces that every node may stop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminationMarker {
+    pub round: usize,
+    /// Whether every node visited so far had nothing scheduled
+    pub all_idle: bool,
+    /// Active events sent and received on every channel, as the nodes visited so far count them
+    pub channels: Vec<ChannelCount>,
+    pub terminated: bool,
+}
+
+/// Active events its feeding node sent on a channel and its fed node received from it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelCount {
     pub feeding_node: String,
+    pub fed_node: String,
+    pub sent: usize,
+    pub received: usize,
+}
+
+/// Every message exchanged between nodes, tagged with its kind so it is parsed exactly once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WireMessage {
+    Active(ActiveEvent),
+    Passive(PassiveEvent),
+    Control(Control),
+    /// Several messages to the same node sent in a single write, in order
+    Batch(Vec<WireMessage>),
+}
+
+/// Messages of the cluster-wide protocols, which travel outside of any channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Control {
+    ClockRequest(ClockRequest),
+    GvtToken(GvtToken),
+    DeadlockMarker(DeadlockMarker),
+    BarrierReport(BarrierReport),
+    BarrierGrant(BarrierGrant),
+    SnapshotMarker(SnapshotMarker),
+    Ready(Ready),
+    Handoff(Handoff),
+    TerminationMarker(TerminationMarker),
 }
 
-impl From<ActiveEvent> for String {
+impl WireMessage {
+    /// This message as a peer speaking `version` of the protocol reads it, possibly as
+    /// several messages.
+    pub fn migrate(self, version: u32) -> Vec<WireMessage> {
+        match self {
+            // batches came with version 3
+            Self::Batch(_) if version < 3 => self.unbatch(),
+            // termination detection came with version 4, older builds cannot take part in it
+            Self::Control(Control::TerminationMarker(_)) if version < 4 => vec![],
+            message => vec![message],
+        }
+    }
+
+    /// Sender, position on the channel and Lamport timestamp of an event,
+    /// None for control messages
+    pub fn channel(&self) -> Option<(&NodeId, usize, usize)> {
+        match self {
+            Self::Active(event) => Some((&event.feeding_node, event.channel_seq, event.lamport)),
+            Self::Passive(event) => Some((&event.feeding_node, event.channel_seq, event.lamport)),
+            Self::Control(_) | Self::Batch(_) => None,
+        }
+    }
+
+    /// Every message carried, in order, batches unpacked.
+    pub fn unbatch(self) -> Vec<WireMessage> {
+        match self {
+            Self::Batch(messages) => messages.into_iter().flat_map(Self::unbatch).collect(),
+            message => vec![message],
+        }
+    }
+}
+
+impl From<ActiveEvent> for WireMessage {
     fn from(value: ActiveEvent) -> Self {
-        serde_json::to_string(&value).unwrap()
+        Self::Active(value)
     }
 }
 
-impl From<PassiveEvent> for String {
+impl From<PassiveEvent> for WireMessage {
     fn from(value: PassiveEvent) -> Self {
-        serde_json::to_string(&value).unwrap()
+        Self::Passive(value)
+    }
+}
+
+impl From<ClockRequest> for WireMessage {
+    fn from(value: ClockRequest) -> Self {
+        Self::Control(Control::ClockRequest(value))
+    }
+}
+
+impl From<GvtToken> for WireMessage {
+    fn from(value: GvtToken) -> Self {
+        Self::Control(Control::GvtToken(value))
+    }
+}
+
+impl From<DeadlockMarker> for WireMessage {
+    fn from(value: DeadlockMarker) -> Self {
+        Self::Control(Control::DeadlockMarker(value))
+    }
+}
+
+impl From<TerminationMarker> for WireMessage {
+    fn from(value: TerminationMarker) -> Self {
+        Self::Control(Control::TerminationMarker(value))
+    }
+}
+
+impl From<BarrierReport> for WireMessage {
+    fn from(value: BarrierReport) -> Self {
+        Self::Control(Control::BarrierReport(value))
+    }
+}
+
+impl From<BarrierGrant> for WireMessage {
+    fn from(value: BarrierGrant) -> Self {
+        Self::Control(Control::BarrierGrant(value))
+    }
+}
+
+impl From<Ready> for WireMessage {
+    fn from(value: Ready) -> Self {
+        Self::Control(Control::Ready(value))
+    }
+}
+
+impl From<SnapshotMarker> for WireMessage {
+    fn from(value: SnapshotMarker) -> Self {
+        Self::Control(Control::SnapshotMarker(value))
+    }
+}
+
+impl From<Handoff> for WireMessage {
+    fn from(value: Handoff) -> Self {
+        Self::Control(Control::Handoff(value))
     }
 }
 
 #[derive(Debug)]
 pub struct FeedingNode {
-    pub name: String,
+    pub name: NodeId,
     pub clock: usize,
-    pub channel: Receiver<String>,
+    pub channel: Receiver<WireMessage>,
+    /// Whether a clock request is outstanding (on-demand null messages only)
+    pub requested: bool,
+    /// Messages consumed from the channel so far
+    pub consumed: usize,
+    /// Lamport timestamp of the last message consumed from the channel
+    pub lamport: usize,
+}
+
+/// The feeding nodes by the clock each promised, kept up to date as promises come in so
+/// the earliest of them is known without going over every feeding node each tick.
+#[derive(Debug, Default)]
+pub struct FeedingClocks {
+    /// Positions among the feeding nodes of those at every clock
+    by_clock: BTreeMap<usize, BTreeSet<usize>>,
+}
+
+impl FeedingClocks {
+    pub fn new(feeding_nodes: &[FeedingNode]) -> Self {
+        let mut clocks = Self::default();
+        for (index, feeding_node) in feeding_nodes.iter().enumerate() {
+            clocks.insert(index, feeding_node.clock);
+        }
+        clocks
+    }
+
+    /// Moves the feeding node at `index` from clock `from` to clock `to`.
+    pub fn update(&mut self, index: usize, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        if let Some(indexes) = self.by_clock.get_mut(&from) {
+            indexes.remove(&index);
+            if indexes.is_empty() {
+                self.by_clock.remove(&from);
+            }
+        }
+        self.insert(index, to);
+    }
+
+    fn insert(&mut self, index: usize, clock: usize) {
+        self.by_clock.entry(clock).or_default().insert(index);
+    }
+
+    /// Earliest clock promised by any feeding node
+    pub fn earliest(&self) -> Option<usize> {
+        self.by_clock.keys().next().copied()
+    }
+
+    /// Earliest clock promised by any feeding node later than `clock`
+    pub fn after(&self, clock: usize) -> Option<usize> {
+        self.by_clock
+            .range(clock.checked_add(1)?..)
+            .next()
+            .map(|(&clock, _)| clock)
+    }
+
+    /// Positions of the feeding nodes at `clock`
+    pub fn at(&self, clock: usize) -> impl Iterator<Item = usize> + '_ {
+        self.by_clock.get(&clock).into_iter().flatten().copied()
+    }
+}
+
+/// Holds back messages of a feeding node that arrive ahead of their turn, so the engine
+/// sees every channel in the order it was sent no matter how the messages travelled.
+#[derive(Debug, Default)]
+pub struct ReorderBuffer {
+    next_seq: usize,
+    pending: BTreeMap<usize, WireMessage>,
+}
+
+/// What became of a message pushed into a `ReorderBuffer`
+#[derive(Debug)]
+pub enum Reordered {
+    /// Every message now in order, oldest first
+    Released(Vec<WireMessage>),
+    /// Already released or already held back, such as a retransmit
+    Duplicate,
+    /// Held back until the messages before it arrive
+    Gap { missing: Vec<usize> },
+}
+
+impl ReorderBuffer {
+    /// Buffer of a channel whose messages before `next_seq` were consumed already, by a
+    /// previous run of this node.
+    pub fn resuming_at(next_seq: usize) -> Self {
+        Self {
+            next_seq,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, channel_seq: usize, event: WireMessage) -> Reordered {
+        if channel_seq < self.next_seq || self.pending.contains_key(&channel_seq) {
+            return Reordered::Duplicate;
+        }
+        self.pending.insert(channel_seq, event);
+
+        if channel_seq > self.next_seq {
+            let missing = (self.next_seq..channel_seq)
+                .filter(|seq| !self.pending.contains_key(seq))
+                .collect();
+            return Reordered::Gap { missing };
+        }
+
+        let mut released = vec![];
+        while let Some(event) = self.pending.remove(&self.next_seq) {
+            released.push(event);
+            self.next_seq += 1;
+        }
+        Reordered::Released(released)
+    }
 }
 
 impl Display for Transition {
@@ -0,0 +1,236 @@
+use crate::error::Result;
+use crate::model::{ActiveEvent, Instruction, TraceContext, Transition};
+use crate::transport::hex;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Spans sent to the collector at once
+const BATCH: usize = 512;
+
+/// OTLP span kinds
+const PRODUCER: u8 = 4;
+const CONSUMER: u8 = 5;
+
+/// Follows tokens through the net as OpenTelemetry traces exported over OTLP/HTTP:
+/// every event sent to another node is a producer span, continued on the receiving node
+/// by a consumer span lasting from sending to receiving, network latency included. The
+/// transitions an event sets carry its trace on to the events they send in turn.
+pub struct Tracer {
+    node: String,
+    /// Context of the latest traced event that set every transition
+    causes: HashMap<usize, TraceContext>,
+    spans: Vec<Value>,
+    exporter: Option<(Sender<String>, JoinHandle<()>)>,
+}
+
+impl Tracer {
+    /// Exports to the collector at `endpoint`, such as http://localhost:4318.
+    pub fn new(node: &str, endpoint: &str) -> Result<Self> {
+        let Some(endpoint) = endpoint.strip_prefix("http://") else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("--otlp-endpoint {endpoint:?}: only http:// collectors are supported"),
+            )
+            .into());
+        };
+        let (address, path) = match endpoint.find('/') {
+            Some(index) => endpoint.split_at(index),
+            None => (endpoint, ""),
+        };
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{address}:80")
+        };
+        let path = format!("{}/v1/traces", path.trim_end_matches('/'));
+
+        let (tx, rx) = channel::<String>();
+        let exporter = thread::spawn(move || {
+            rx.into_iter().for_each(|body| {
+                if let Err(error) = post(&address, &path, &body) {
+                    warn!(%error, "SPANS LOST");
+                }
+            })
+        });
+
+        Ok(Self {
+            node: node.to_string(),
+            causes: HashMap::new(),
+            spans: Vec::new(),
+            exporter: Some((tx, exporter)),
+        })
+    }
+
+    /// Hands the trace that set `transition` on to the local transitions it sets.
+    pub fn fired(&mut self, transition: &Transition) {
+        let Some(cause) = self.causes.get(&transition.id).copied() else {
+            return;
+        };
+        transition
+            .immediate_instructions
+            .iter()
+            .chain(&transition.delayed_instructions)
+            .filter(|instruction| !instruction.is_external)
+            .for_each(|instruction| {
+                self.causes.insert(instruction.transition_id, cause);
+            });
+    }
+
+    /// Opens the span of the event `instruction` of `transition` sends to another node,
+    /// in the trace that set `transition` or in a new one, returning its context.
+    pub fn sent(
+        &mut self,
+        transition: &Transition,
+        instruction: &Instruction,
+        clock: usize,
+    ) -> TraceContext {
+        let cause = self.causes.get(&transition.id);
+        let context = TraceContext {
+            trace_id: cause.map_or_else(rand::random, |cause| cause.trace_id),
+            span_id: rand::random(),
+            sent_at: now(),
+        };
+        self.record(json!({
+            "traceId": hex(&context.trace_id),
+            "spanId": hex(&context.span_id),
+            "parentSpanId": cause.map(|cause| hex(&cause.span_id)).unwrap_or_default(),
+            "name": format!("t{} -> t{}", transition.id, instruction.transition_id),
+            "kind": PRODUCER,
+            "startTimeUnixNano": context.sent_at.to_string(),
+            "endTimeUnixNano": context.sent_at.to_string(),
+            "attributes": [
+                attribute("petri.transition", transition.id),
+                attribute("petri.target", instruction.transition_id),
+                attribute("petri.clock", clock),
+            ],
+        }));
+
+        context
+    }
+
+    /// Continues the trace of `event`, from its sending until now.
+    pub fn received(&mut self, event: &ActiveEvent) {
+        let Some(parent) = event.trace else {
+            return;
+        };
+        let context = TraceContext {
+            span_id: rand::random(),
+            sent_at: now(),
+            ..parent
+        };
+        self.record(json!({
+            "traceId": hex(&context.trace_id),
+            "spanId": hex(&context.span_id),
+            "parentSpanId": hex(&parent.span_id),
+            "name": format!("t{}", event.transition_id),
+            "kind": CONSUMER,
+            "startTimeUnixNano": parent.sent_at.to_string(),
+            "endTimeUnixNano": context.sent_at.to_string(),
+            "attributes": [
+                attribute("petri.feeding_node", &*event.feeding_node),
+                attribute("petri.target", event.transition_id),
+                attribute("petri.clock", event.clock),
+                attribute("petri.anti", event.anti),
+            ],
+        }));
+
+        // an anti-message undoes a setting, it does not cause anything
+        if !event.anti {
+            self.causes.insert(event.transition_id, context);
+        }
+    }
+
+    fn record(&mut self, span: Value) {
+        self.spans.push(span);
+        if self.spans.len() >= BATCH {
+            self.flush();
+        }
+    }
+
+    /// Sends the spans recorded so far to the collector.
+    pub fn flush(&mut self) {
+        if self.spans.is_empty() {
+            return;
+        }
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        attribute("service.name", self.node.as_str()),
+                        attribute("service.namespace", "petri"),
+                    ],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "petri" },
+                    "spans": std::mem::take(&mut self.spans),
+                }],
+            }],
+        });
+        if let Some((tx, _)) = &self.exporter {
+            let _ = tx.send(body.to_string());
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.flush();
+        if let Some((tx, exporter)) = self.exporter.take() {
+            drop(tx);
+            let _ = exporter.join();
+        }
+    }
+}
+
+fn attribute(key: &str, value: impl Into<Value>) -> Value {
+    let value = match value.into() {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        // OTLP JSON writes 64-bit integers as strings
+        Value::Number(value) => json!({ "intValue": value.to_string() }),
+        value => json!({ "stringValue": value.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+/// POSTs `body` as JSON to `path` of the collector at `address`.
+fn post(address: &str, path: &str, body: &str) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    // a status line such as HTTP/1.1 200 OK, anything but a 2xx being a rejected batch
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    let mut words = status.split_whitespace();
+    let version = words.next().unwrap_or_default();
+    let code = words.next().unwrap_or_default();
+    if version.starts_with("HTTP/1.")
+        && code.len() == 3
+        && code.starts_with('2')
+        && code.bytes().all(|digit| digit.is_ascii_digit())
+    {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "collector answered {:?}",
+            status.trim_end()
+        )))
+    }
+}
@@ -0,0 +1,416 @@
+use crate::json::{Net, Transition};
+use crate::model;
+use roxmltree::{Document, Node};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Tool named by the <toolspecific> elements this simulator reads
+const TOOL: &str = "petri";
+
+/// Firing duration of a transition without a <toolspecific tool="petri"><duration>
+const DEFAULT_DURATION: usize = 1;
+
+/// A transition of the document, in document order
+struct Declared<'a> {
+    id: &'a str,
+    segment: usize,
+    duration: usize,
+}
+
+/// Maps the place/transition nets of a PNML document onto the transitions of the
+/// simulator, one segment per top-level page, numbering transitions in document order.
+///
+/// A transition's value counts its unmarked input places, and instructions set values
+/// rather than add to them, so the net must be safe (never more than one token per place)
+/// and no transition may have more than one input place. Firing a transition then
+/// disables every transition sharing its input place, which must all be on its page,
+/// and enables those fed by its output places once its duration elapsed.
+pub fn parse(text: &str) -> Result<Vec<Net>, String> {
+    let document = Document::parse(text).map_err(|error| error.to_string())?;
+
+    let mut segments = 0;
+    let mut marking = HashMap::new();
+    let mut transitions = Vec::new();
+    let mut references = HashMap::new();
+    let mut arcs = Vec::new();
+    let pages = document
+        .root_element()
+        .children()
+        .filter(|node| node.has_tag_name("net"))
+        .flat_map(|net| net.children().filter(|node| node.has_tag_name("page")));
+    for page in pages {
+        for node in page.descendants() {
+            let id = || {
+                node.attribute("id")
+                    .ok_or_else(|| format!("<{}> without an id", node.tag_name().name()))
+            };
+            match node.tag_name().name() {
+                "place" => {
+                    let tokens = label(node, "initialMarking")?.unwrap_or_default();
+                    if tokens > 1 {
+                        return Err(format!(
+                            "place {} holds {tokens} tokens, more than one",
+                            id()?
+                        ));
+                    }
+                    marking.insert(id()?, tokens == 1);
+                }
+                "transition" => transitions.push(Declared {
+                    id: id()?,
+                    segment: segments,
+                    duration: duration(node)?,
+                }),
+                "referencePlace" | "referenceTransition" => {
+                    let id = id()?;
+                    let target = node
+                        .attribute("ref")
+                        .ok_or_else(|| format!("reference {id} without a ref"))?;
+                    references.insert(id, target);
+                }
+                "arc" => {
+                    let id = id()?;
+                    let end = |name| {
+                        node.attribute(name)
+                            .ok_or_else(|| format!("arc {id} without a {name}"))
+                    };
+                    let weight = label(node, "inscription")?.unwrap_or(1);
+                    if weight != 1 {
+                        return Err(format!("arc {id} weighs {weight}, not one"));
+                    }
+                    arcs.push((id, end("source")?, end("target")?));
+                }
+                _ => {}
+            }
+        }
+        segments += 1;
+    }
+
+    let index = transitions
+        .iter()
+        .enumerate()
+        .map(|(global, transition)| (transition.id, (global, transition.segment)))
+        .collect::<HashMap<_, _>>();
+    let mut inputs = HashMap::<&str, Vec<&str>>::new();
+    let mut outputs = HashMap::<&str, Vec<&str>>::new();
+    let mut consumers = HashMap::<&str, Vec<&str>>::new();
+    for (id, source, target) in arcs {
+        let (source, target) = (resolve(&references, source)?, resolve(&references, target)?);
+        for (end, node) in [("source", source), ("target", target)] {
+            if !marking.contains_key(node) && !index.contains_key(node) {
+                return Err(format!(
+                    "arc {id} has {node} as its {end}, no place or transition"
+                ));
+            }
+        }
+        if marking.contains_key(source) && index.contains_key(target) {
+            inputs.entry(target).or_default().push(source);
+            consumers.entry(source).or_default().push(target);
+        } else if index.contains_key(source) && marking.contains_key(target) {
+            outputs.entry(source).or_default().push(target);
+        } else {
+            return Err(format!("arc {id} does not join a place and a transition"));
+        }
+    }
+
+    for (transition, places) in &inputs {
+        if places.len() > 1 {
+            return Err(format!(
+                "transition {transition} has {} input places, at most one is supported",
+                places.len()
+            ));
+        }
+    }
+    for (place, transitions) in &consumers {
+        let segment = index[transitions[0]].1;
+        if transitions
+            .iter()
+            .any(|transition| index[transition].1 != segment)
+        {
+            return Err(format!(
+                "place {place} feeds transitions on different pages"
+            ));
+        }
+    }
+
+    let mut nets = (0..segments)
+        .map(|_| Net { ia_red: Vec::new() })
+        .collect::<Vec<_>>();
+    for transition in &transitions {
+        let (id, segment) = index[transition.id];
+        // the id of an instruction's target, negative when on another page
+        let target = |other: &str| {
+            let (global, other_segment) = index[other];
+            if other_segment == segment {
+                global as isize
+            } else {
+                -(global as isize + 1)
+            }
+        };
+        let input = inputs.get(transition.id).and_then(|places| places.first());
+        let value = match input {
+            Some(place) if !marking[place] => 1,
+            _ => 0,
+        };
+        let disabled = input
+            .into_iter()
+            .flat_map(|place| &consumers[place])
+            .map(|other| (target(other), 1))
+            .collect();
+        let enabled = outputs
+            .get(transition.id)
+            .into_iter()
+            .flatten()
+            .flat_map(|place| consumers.get(place).into_iter().flatten())
+            .map(|other| (target(other), 0))
+            .collect();
+
+        nets[segment].ia_red.push(Transition {
+            ii_idglobal: id,
+            ii_valor: value,
+            ii_tiempo: 0,
+            ii_duracion_disparo: transition.duration,
+            ii_listactes_iul: disabled,
+            ii_listactes_pul: enabled,
+            ib_desalida: false,
+            reward_rate: 0.0,
+            firing_cost: 0.0,
+        });
+    }
+    nets.retain(|net| !net.ia_red.is_empty());
+
+    Ok(nets)
+}
+
+/// Node a reference points to, through any chain of references.
+fn resolve<'a>(references: &HashMap<&'a str, &'a str>, id: &'a str) -> Result<&'a str, String> {
+    let mut resolved = id;
+    for _ in 0..=references.len() {
+        match references.get(resolved) {
+            Some(target) => resolved = target,
+            None => return Ok(resolved),
+        }
+    }
+    Err(format!("reference {id} refers back to itself"))
+}
+
+/// Number held by the `name` label of `node`, as <text>1</text> in standard PNML or as
+/// <value>Default,1</value> as saved by PIPE.
+fn label(node: Node, name: &str) -> Result<Option<usize>, String> {
+    let Some(text) = node
+        .children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|label| {
+            label
+                .children()
+                .find(|child| child.has_tag_name("text") || child.has_tag_name("value"))
+        })
+        .and_then(|text| text.text())
+    else {
+        return Ok(None);
+    };
+    let number = text.rsplit(',').next().unwrap_or(text).trim();
+    number
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{name} {text:?} is not a number"))
+}
+
+/// Firing duration of `transition`, from <toolspecific tool="petri"><duration>.
+fn duration(transition: Node) -> Result<usize, String> {
+    let Some(duration) = transition
+        .children()
+        .filter(|child| child.has_tag_name("toolspecific") && child.attribute("tool") == Some(TOOL))
+        .flat_map(|tool| tool.children())
+        .find(|child| child.has_tag_name("duration"))
+    else {
+        return Ok(DEFAULT_DURATION);
+    };
+    let text = duration.text().unwrap_or_default().trim();
+    text.parse()
+        .map_err(|_| format!("duration {text:?} is not a number"))
+}
+
+/// Writes `nets` as a PNML document, one page per segment, the inverse of [`parse`].
+///
+/// Every transition consumes from a place, marked while it is enabled and shared with
+/// the transitions it disables or is disabled by, and produces into the places of the
+/// transitions it enables. Values beyond enabled and disabled are lost on the way.
+pub fn write(nets: &[model::Net]) -> String {
+    let transitions = nets
+        .iter()
+        .enumerate()
+        .flat_map(|(segment, net)| net.transitions.iter().map(move |t| (segment, t)))
+        .collect::<Vec<_>>();
+    let segment_of = transitions
+        .iter()
+        .map(|(segment, transition)| (transition.id, *segment))
+        .collect::<HashMap<_, _>>();
+
+    // the place of a transition is named after the lowest transition sharing it
+    let mut place_of = segment_of
+        .keys()
+        .map(|&id| (id, id))
+        .collect::<HashMap<_, _>>();
+    fn find(place_of: &mut HashMap<usize, usize>, id: usize) -> usize {
+        let parent = place_of[&id];
+        if parent == id {
+            return id;
+        }
+        let root = find(place_of, parent);
+        place_of.insert(id, root);
+        root
+    }
+    for (_, transition) in &transitions {
+        for instruction in instructions(transition).filter(|instruction| instruction.value > 0) {
+            if !place_of.contains_key(&instruction.transition_id) {
+                continue;
+            }
+            let (a, b) = (
+                find(&mut place_of, transition.id),
+                find(&mut place_of, instruction.transition_id),
+            );
+            place_of.insert(a.max(b), a.min(b));
+        }
+    }
+    let ids = place_of.keys().copied().collect::<Vec<_>>();
+    for id in ids {
+        find(&mut place_of, id);
+    }
+    let marked = transitions
+        .iter()
+        .filter(|(_, transition)| transition.value <= 0)
+        .map(|(_, transition)| place_of[&transition.id])
+        .collect::<BTreeSet<_>>();
+
+    let mut document = String::new();
+    document.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    document.push_str("<pnml xmlns=\"http://www.pnml.org/version-2009/grammar/pnml\">\n");
+    document
+        .push_str("  <net id=\"petri\" type=\"http://www.pnml.org/version-2009/grammar/ptnet\">\n");
+    for segment in 0..nets.len() {
+        let _ = writeln!(document, "    <page id=\"segment{segment}\">");
+        let places = transitions
+            .iter()
+            .map(|(_, transition)| place_of[&transition.id])
+            .filter(|place| segment_of[place] == segment)
+            .collect::<BTreeSet<_>>();
+        for place in &places {
+            if marked.contains(place) {
+                let _ = writeln!(
+                    document,
+                    "      <place id=\"p{place}\"><initialMarking><text>1</text></initialMarking></place>"
+                );
+            } else {
+                let _ = writeln!(document, "      <place id=\"p{place}\"/>");
+            }
+        }
+
+        let mut references = BTreeSet::new();
+        let mut arcs = BTreeSet::new();
+        for (_, transition) in transitions.iter().filter(|(s, _)| *s == segment) {
+            let _ = writeln!(
+                document,
+                "      <transition id=\"t{}\"><toolspecific tool=\"{TOOL}\" \
+                 version=\"1\"><duration>{}</duration></toolspecific></transition>",
+                transition.id, transition.duration
+            );
+            let consumed = place_of[&transition.id];
+            arcs.insert((format!("p{consumed}"), format!("t{}", transition.id)));
+            for instruction in instructions(transition).filter(|instruction| instruction.value <= 0)
+            {
+                let Some(&produced) = place_of.get(&instruction.transition_id) else {
+                    continue;
+                };
+                let target = if segment_of[&produced] == segment {
+                    format!("p{produced}")
+                } else {
+                    references.insert(produced);
+                    format!("segment{segment}-p{produced}")
+                };
+                arcs.insert((format!("t{}", transition.id), target));
+            }
+        }
+        for place in references {
+            let _ = writeln!(
+                document,
+                "      <referencePlace id=\"segment{segment}-p{place}\" ref=\"p{place}\"/>"
+            );
+        }
+        for (source, target) in arcs {
+            let _ = writeln!(
+                document,
+                "      <arc id=\"{source}-{target}\" source=\"{source}\" target=\"{target}\"/>"
+            );
+        }
+        document.push_str("    </page>\n");
+    }
+    document.push_str("  </net>\n</pnml>\n");
+
+    document
+}
+
+fn instructions(transition: &model::Transition) -> impl Iterator<Item = &model::Instruction> {
+    transition
+        .immediate_instructions
+        .iter()
+        .chain(&transition.delayed_instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    /// A page of places p0 and p1, p0 marked, joined by transitions t0 and t1 going round,
+    /// with `arcs` added.
+    fn document(arcs: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<pnml xmlns="http://www.pnml.org/version-2009/grammar/pnml">
+  <net id="net" type="http://www.pnml.org/version-2009/grammar/ptnet">
+    <page id="page">
+      <place id="p0"><initialMarking><text>1</text></initialMarking></place>
+      <place id="p1"/>
+      <transition id="t0">
+        <toolspecific tool="petri" version="1"><duration>3</duration></toolspecific>
+      </transition>
+      <transition id="t1"/>
+      <arc id="a0" source="p0" target="t0"><inscription><text>1</text></inscription></arc>
+      <arc id="a1" source="t0" target="p1"/>
+      <arc id="a2" source="p1" target="t1"/>
+      <arc id="a3" source="t1" target="p0"/>
+      {arcs}
+    </page>
+  </net>
+</pnml>"#
+        )
+    }
+
+    #[test]
+    fn reads_markings_inscriptions_and_durations() {
+        let nets = parse(&document("")).expect("Failed to parse the document");
+        assert_eq!(nets.len(), 1);
+        let [t0, t1] = &nets[0].ia_red[..] else {
+            panic!("{:?}", nets[0].ia_red);
+        };
+        // t0 is enabled by the token in p0, t1 waits for one in p1
+        assert_eq!(
+            (t0.ii_idglobal, t0.ii_valor, t0.ii_duracion_disparo),
+            (0, 0, 3)
+        );
+        assert_eq!(
+            (t1.ii_idglobal, t1.ii_valor, t1.ii_duracion_disparo),
+            (1, 1, 1)
+        );
+        assert_eq!(t0.ii_listactes_iul, [(0, 1)]);
+        assert_eq!(t0.ii_listactes_pul, [(1, 0)]);
+        assert_eq!(t1.ii_listactes_iul, [(1, 1)]);
+        assert_eq!(t1.ii_listactes_pul, [(0, 0)]);
+    }
+
+    #[test]
+    fn rejects_an_arc_to_nothing() {
+        let error = parse(&document(r#"<arc id="a4" source="t1" target="p9"/>"#))
+            .expect_err("Parsed an arc to an unknown place");
+        assert!(error.contains("arc a4") && error.contains("p9"), "{error}");
+    }
+}
@@ -0,0 +1,418 @@
+// the service's own messages only go with --link grpc
+#![cfg_attr(not(feature = "grpc"), allow(dead_code))]
+
+use crate::model;
+
+// generated from proto/petri.proto, for --link grpc and --wire-format protobuf alike
+include!(concat!(env!("OUT_DIR"), "/petri.rs"));
+
+impl From<model::WireMessage> for WireMessage {
+    fn from(value: model::WireMessage) -> Self {
+        use model::Control;
+        use wire_message::Kind;
+
+        let kind = match value {
+            model::WireMessage::Active(event) => Kind::Active(event.into()),
+            model::WireMessage::Passive(event) => Kind::Passive(event.into()),
+            model::WireMessage::Control(Control::ClockRequest(request)) => {
+                Kind::ClockRequest(request.into())
+            }
+            model::WireMessage::Control(Control::GvtToken(token)) => Kind::GvtToken(token.into()),
+            model::WireMessage::Control(Control::DeadlockMarker(marker)) => {
+                Kind::DeadlockMarker(marker.into())
+            }
+            model::WireMessage::Control(Control::BarrierReport(report)) => {
+                Kind::BarrierReport(report.into())
+            }
+            model::WireMessage::Control(Control::BarrierGrant(grant)) => {
+                Kind::BarrierGrant(grant.into())
+            }
+            model::WireMessage::Control(Control::SnapshotMarker(marker)) => {
+                Kind::SnapshotMarker(marker.into())
+            }
+            model::WireMessage::Control(Control::Ready(ready)) => Kind::Ready(ready.into()),
+            model::WireMessage::Control(Control::Handoff(handoff)) => Kind::Handoff(handoff.into()),
+            model::WireMessage::Control(Control::TerminationMarker(marker)) => {
+                Kind::TerminationMarker(marker.into())
+            }
+            model::WireMessage::Batch(messages) => Kind::Batch(Batch {
+                messages: messages.into_iter().map(Into::into).collect(),
+            }),
+        };
+
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<WireMessage> for model::WireMessage {
+    type Error = String;
+
+    /// Fails on a message of no kind this build knows, such as one from a newer schema.
+    fn try_from(value: WireMessage) -> Result<Self, Self::Error> {
+        use model::Control;
+        use wire_message::Kind;
+
+        Ok(match value.kind.ok_or("message of unknown kind")? {
+            Kind::Active(event) => Self::Active(event.into()),
+            Kind::Passive(event) => Self::Passive(event.into()),
+            Kind::ClockRequest(request) => Self::Control(Control::ClockRequest(request.into())),
+            Kind::GvtToken(token) => Self::Control(Control::GvtToken(token.into())),
+            Kind::DeadlockMarker(marker) => Self::Control(Control::DeadlockMarker(marker.into())),
+            Kind::BarrierReport(report) => Self::Control(Control::BarrierReport(report.into())),
+            Kind::BarrierGrant(grant) => Self::Control(Control::BarrierGrant(grant.into())),
+            Kind::SnapshotMarker(marker) => Self::Control(Control::SnapshotMarker(marker.into())),
+            Kind::Ready(ready) => Self::Control(Control::Ready(ready.into())),
+            Kind::Handoff(handoff) => Self::Control(Control::Handoff(handoff.into())),
+            Kind::TerminationMarker(marker) => {
+                Self::Control(Control::TerminationMarker(marker.into()))
+            }
+            Kind::Batch(batch) => Self::Batch(
+                batch
+                    .messages
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+}
+
+impl From<model::ActiveEvent> for ActiveEvent {
+    fn from(value: model::ActiveEvent) -> Self {
+        Self {
+            feeding_node: value.feeding_node.to_string(),
+            transition_id: value.transition_id as u64,
+            value: value.value as i64,
+            clock: value.clock as u64,
+            anti: value.anti,
+            color: value.color as u64,
+            seq: value.seq as u64,
+            vector_clock: std::collections::BTreeMap::from(value.vector_clock)
+                .into_iter()
+                .map(|(node, count)| (node.to_string(), count as u64))
+                .collect(),
+            channel_seq: value.channel_seq as u64,
+            lamport: value.lamport as u64,
+            trace: value.trace.map(Into::into),
+        }
+    }
+}
+
+impl From<ActiveEvent> for model::ActiveEvent {
+    fn from(value: ActiveEvent) -> Self {
+        Self {
+            feeding_node: value.feeding_node.into(),
+            transition_id: value.transition_id as usize,
+            value: value.value as isize,
+            clock: value.clock as usize,
+            anti: value.anti,
+            color: value.color as usize,
+            seq: value.seq as usize,
+            vector_clock: value
+                .vector_clock
+                .into_iter()
+                .map(|(node, count)| (node.into(), count as usize))
+                .collect::<std::collections::BTreeMap<_, _>>()
+                .into(),
+            channel_seq: value.channel_seq as usize,
+            lamport: value.lamport as usize,
+            // a malformed context only loses the trace, not the event
+            trace: value.trace.and_then(|trace| trace.try_into().ok()),
+        }
+    }
+}
+
+impl From<model::TraceContext> for TraceContext {
+    fn from(value: model::TraceContext) -> Self {
+        Self {
+            trace_id: value.trace_id.to_vec(),
+            span_id: value.span_id.to_vec(),
+            sent_at: value.sent_at,
+        }
+    }
+}
+
+impl TryFrom<TraceContext> for model::TraceContext {
+    type Error = std::array::TryFromSliceError;
+
+    fn try_from(value: TraceContext) -> Result<Self, Self::Error> {
+        Ok(Self {
+            trace_id: value.trace_id.as_slice().try_into()?,
+            span_id: value.span_id.as_slice().try_into()?,
+            sent_at: value.sent_at,
+        })
+    }
+}
+
+impl From<model::PassiveEvent> for PassiveEvent {
+    fn from(value: model::PassiveEvent) -> Self {
+        Self {
+            feeding_node: value.feeding_node.to_string(),
+            clock: value.clock as u64,
+            lookahead: value.lookahead as u64,
+            channel_seq: value.channel_seq as u64,
+            lamport: value.lamport as u64,
+        }
+    }
+}
+
+impl From<PassiveEvent> for model::PassiveEvent {
+    fn from(value: PassiveEvent) -> Self {
+        Self {
+            feeding_node: value.feeding_node.into(),
+            clock: value.clock as usize,
+            lookahead: value.lookahead as usize,
+            channel_seq: value.channel_seq as usize,
+            lamport: value.lamport as usize,
+        }
+    }
+}
+
+impl From<model::ClockRequest> for ClockRequest {
+    fn from(value: model::ClockRequest) -> Self {
+        Self {
+            requesting_node: value.requesting_node,
+            clock: value.clock as u64,
+        }
+    }
+}
+
+impl From<ClockRequest> for model::ClockRequest {
+    fn from(value: ClockRequest) -> Self {
+        Self {
+            requesting_node: value.requesting_node,
+            clock: value.clock as usize,
+        }
+    }
+}
+
+impl From<model::GvtToken> for GvtToken {
+    fn from(value: model::GvtToken) -> Self {
+        Self {
+            round: value.round as u64,
+            in_transit: value.in_transit as i64,
+            min_clock: value.min_clock as u64,
+            gvt: value.gvt.map(|gvt| gvt as u64),
+        }
+    }
+}
+
+impl From<GvtToken> for model::GvtToken {
+    fn from(value: GvtToken) -> Self {
+        Self {
+            round: value.round as usize,
+            in_transit: value.in_transit as isize,
+            min_clock: value.min_clock as usize,
+            gvt: value.gvt.map(|gvt| gvt as usize),
+        }
+    }
+}
+
+impl From<model::DeadlockMarker> for DeadlockMarker {
+    fn from(value: model::DeadlockMarker) -> Self {
+        Self {
+            pass: value.pass as u64,
+            all_blocked: value.all_blocked,
+            in_transit: value.in_transit as i64,
+            min_next: value.min_next as u64,
+            recovery: value.recovery.map(|recovery| recovery as u64),
+        }
+    }
+}
+
+impl From<DeadlockMarker> for model::DeadlockMarker {
+    fn from(value: DeadlockMarker) -> Self {
+        Self {
+            pass: value.pass as usize,
+            all_blocked: value.all_blocked,
+            in_transit: value.in_transit as isize,
+            min_next: value.min_next as usize,
+            recovery: value.recovery.map(|recovery| recovery as usize),
+        }
+    }
+}
+
+impl From<model::TerminationMarker> for TerminationMarker {
+    fn from(value: model::TerminationMarker) -> Self {
+        Self {
+            round: value.round as u64,
+            all_idle: value.all_idle,
+            channels: value.channels.into_iter().map(Into::into).collect(),
+            terminated: value.terminated,
+        }
+    }
+}
+
+impl From<TerminationMarker> for model::TerminationMarker {
+    fn from(value: TerminationMarker) -> Self {
+        Self {
+            round: value.round as usize,
+            all_idle: value.all_idle,
+            channels: value.channels.into_iter().map(Into::into).collect(),
+            terminated: value.terminated,
+        }
+    }
+}
+
+impl From<model::ChannelCount> for ChannelCount {
+    fn from(value: model::ChannelCount) -> Self {
+        Self {
+            feeding_node: value.feeding_node,
+            fed_node: value.fed_node,
+            sent: value.sent as u64,
+            received: value.received as u64,
+        }
+    }
+}
+
+impl From<ChannelCount> for model::ChannelCount {
+    fn from(value: ChannelCount) -> Self {
+        Self {
+            feeding_node: value.feeding_node,
+            fed_node: value.fed_node,
+            sent: value.sent as usize,
+            received: value.received as usize,
+        }
+    }
+}
+
+impl From<model::BarrierReport> for BarrierReport {
+    fn from(value: model::BarrierReport) -> Self {
+        Self {
+            reporting_node: value.reporting_node,
+            next_clock: value.next_clock as u64,
+            sent: value
+                .sent
+                .into_iter()
+                .map(|(node, sent)| (node, sent as u64))
+                .collect(),
+            min_sent_clock: value.min_sent_clock as u64,
+        }
+    }
+}
+
+impl From<BarrierReport> for model::BarrierReport {
+    fn from(value: BarrierReport) -> Self {
+        Self {
+            reporting_node: value.reporting_node,
+            next_clock: value.next_clock as usize,
+            sent: value
+                .sent
+                .into_iter()
+                .map(|(node, sent)| (node, sent as usize))
+                .collect(),
+            min_sent_clock: value.min_sent_clock as usize,
+        }
+    }
+}
+
+impl From<model::BarrierGrant> for BarrierGrant {
+    fn from(value: model::BarrierGrant) -> Self {
+        Self {
+            granted_clock: value.granted_clock as u64,
+            expected: value.expected as u64,
+            membership: value.membership.map(|nodes| Membership { nodes }),
+        }
+    }
+}
+
+impl From<BarrierGrant> for model::BarrierGrant {
+    fn from(value: BarrierGrant) -> Self {
+        Self {
+            granted_clock: value.granted_clock as usize,
+            expected: value.expected as usize,
+            membership: value.membership.map(|membership| membership.nodes),
+        }
+    }
+}
+
+impl From<model::SnapshotMarker> for SnapshotMarker {
+    fn from(value: model::SnapshotMarker) -> Self {
+        Self {
+            snapshot_id: value.snapshot_id,
+            sender: value.sender,
+            sent: value.sent.map(|sent| sent as u64),
+        }
+    }
+}
+
+impl From<SnapshotMarker> for model::SnapshotMarker {
+    fn from(value: SnapshotMarker) -> Self {
+        Self {
+            snapshot_id: value.snapshot_id,
+            sender: value.sender,
+            sent: value.sent.map(|sent| sent as usize),
+        }
+    }
+}
+
+impl From<model::Ready> for Ready {
+    fn from(value: model::Ready) -> Self {
+        Self {
+            ready_node: value.ready_node,
+            run_id: value.run_id,
+            resume_from: value.resume_from.map(|resume_from| resume_from as u64),
+            recovered: value.recovered,
+        }
+    }
+}
+
+impl From<Ready> for model::Ready {
+    fn from(value: Ready) -> Self {
+        Self {
+            ready_node: value.ready_node,
+            run_id: value.run_id,
+            resume_from: value.resume_from.map(|resume_from| resume_from as usize),
+            recovered: value.recovered,
+        }
+    }
+}
+
+impl From<model::Handoff> for Handoff {
+    fn from(value: model::Handoff) -> Self {
+        Self {
+            sender: value.sender,
+            run_id: value.run_id,
+            marking: value
+                .marking
+                .into_iter()
+                .map(|(id, (clock, value))| {
+                    let marking = Marking {
+                        clock: clock as u64,
+                        value: value as i64,
+                    };
+                    (id as u64, marking)
+                })
+                .collect(),
+            internal_active_events: value
+                .internal_active_events
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+impl From<Handoff> for model::Handoff {
+    fn from(value: Handoff) -> Self {
+        Self {
+            sender: value.sender,
+            run_id: value.run_id,
+            marking: value
+                .marking
+                .into_iter()
+                .map(|(id, marking)| {
+                    (
+                        id as usize,
+                        (marking.clock as usize, marking.value as isize),
+                    )
+                })
+                .collect(),
+            internal_active_events: value
+                .internal_active_events
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
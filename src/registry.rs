@@ -0,0 +1,174 @@
+use crate::address::NodeAddr;
+use crate::config::{Config, Link};
+use crate::error::{AppError, Result};
+use crate::logging;
+use crate::retry::RetryPolicy;
+use crate::transport::{is_compatible, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::time::Duration;
+use tracing::warn;
+
+/// Longest the registry waits for a node that connected to tell its address
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sent by a node to the registry, with the address its peers are to dial.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Registration {
+    version: u32,
+    node: String,
+}
+
+/// Sent by the registry to every node once the expected number registered. Segments are
+/// assigned by node order, so the membership settles which nets every node simulates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Membership {
+    version: u32,
+    nodes: Vec<String>,
+}
+
+/// Runs the registry at `address` until `expected` nodes registered, then hands the
+/// membership out to all of them.
+pub fn serve(address: &NodeAddr, expected: usize) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Registry listening on {}", listener.local_addr()?);
+
+    let mut registered = BTreeMap::<String, TcpStream>::new();
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!(target: logging::NETWORK, %error, "REGISTRATION NOT ACCEPTED");
+                continue;
+            }
+        };
+        let registration = match read_line::<Registration>(&stream, REGISTRATION_TIMEOUT) {
+            Ok(registration) => registration,
+            Err(error) => {
+                warn!(target: logging::NETWORK, %error, "DROPPED REGISTRATION");
+                continue;
+            }
+        };
+        if !is_compatible(registration.version) {
+            warn!(
+                target: logging::NETWORK,
+                peer = %registration.node,
+                version = registration.version,
+                min_version = MIN_PROTOCOL_VERSION,
+                reason = "protocol version too old",
+                "REJECTED"
+            );
+            // an empty membership tells the node, so it does not wait until its startup
+            // timeout
+            let _ = write_line(&mut stream, &membership(Vec::new()));
+            continue;
+        }
+
+        // a node registering again, e.g. once restarted, replaces its previous registration
+        registered.insert(registration.node.clone(), stream);
+        println!(
+            "Registered {} ({}/{expected})",
+            registration.node,
+            registered.len()
+        );
+        if registered.len() >= expected {
+            break;
+        }
+    }
+
+    let membership = membership(registered.keys().cloned().collect());
+    for (node, mut stream) in registered {
+        if let Err(error) = write_line(&mut stream, &membership) {
+            warn!(target: logging::NETWORK, to = %node, %error, "UNDELIVERED");
+        }
+    }
+
+    Ok(())
+}
+
+/// Registers this node with `registry` and waits for the membership, which fills in
+/// `nodes`.
+pub fn join(mut config: Config, registry: &NodeAddr) -> Result<Config> {
+    settle_port(&mut config)?;
+
+    let startup_timeout = Duration::from_secs(config.startup_timeout);
+    let policy = RetryPolicy {
+        max_attempts: usize::MAX,
+        deadline: startup_timeout,
+        ..RetryPolicy::new(&config)
+    };
+    let mut stream = policy.connect(&registry.to_string(), registry)?;
+    write_line(
+        &mut stream,
+        &Registration {
+            version: PROTOCOL_VERSION,
+            node: config.node.clone(),
+        },
+    )?;
+    let membership = read_line::<Membership>(&stream, startup_timeout)?;
+    if !is_compatible(membership.version) {
+        return Err(AppError::ProtocolMismatch {
+            node: registry.to_string(),
+            version: membership.version,
+        });
+    }
+    if membership.nodes.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{registry} rejected protocol version {PROTOCOL_VERSION}"),
+        )
+        .into());
+    }
+    config.nodes = membership.nodes;
+
+    Ok(config)
+}
+
+/// Turns a port 0 in the bind address, and in the advertised one, into whatever free port
+/// the OS picks, so that peers learn the one to dial.
+pub fn settle_port(config: &mut Config) -> Result<()> {
+    let bind_addr = match &config.bind_addr {
+        Some(bind_addr) => bind_addr.clone(),
+        None => config.node.parse::<NodeAddr>()?,
+    };
+    if bind_addr.port() == Some(0) {
+        // the listener binds the port again right after
+        let free_port = match config.link {
+            Link::Udp | Link::Quic => UdpSocket::bind(&bind_addr)?.local_addr()?.port(),
+            _ => TcpListener::bind(&bind_addr)?.local_addr()?.port(),
+        };
+        if let Some(node) = config
+            .node
+            .parse::<NodeAddr>()
+            .ok()
+            .filter(|node| node.port() == Some(0))
+        {
+            config.node = node.with_port(free_port).to_string();
+        }
+        config.bind_addr = Some(bind_addr.with_port(free_port));
+    }
+
+    Ok(())
+}
+
+fn membership(nodes: Vec<String>) -> Membership {
+    Membership {
+        version: PROTOCOL_VERSION,
+        nodes,
+    }
+}
+
+fn read_line<T: for<'de> Deserialize<'de>>(stream: &TcpStream, timeout: Duration) -> Result<T> {
+    stream.set_read_timeout(Some(timeout))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn write_line<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<()> {
+    let line = serde_json::to_string(message)?;
+    stream.write_all(format!("{line}\n").as_bytes())?;
+    Ok(stream.flush()?)
+}
@@ -0,0 +1,70 @@
+use crate::address::NodeAddr;
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use rand::Rng;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How outgoing connections are retried: exponential backoff with jitter, bounded both
+/// by a number of attempts and by an overall deadline.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    /// Fraction of each delay randomly added or removed, so peers do not retry in lockstep
+    pub jitter: f64,
+    pub deadline: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_attempts: config.connect_attempts.max(1),
+            base_delay: Duration::from_millis(config.connect_base_delay),
+            jitter: config.connect_jitter.clamp(0.0, 1.0),
+            deadline: Duration::from_millis(config.connect_deadline),
+        }
+    }
+
+    /// Connects to `node` at `address`, since peers may not be listening yet or may be
+    /// restarting.
+    pub fn connect(&self, node: &str, address: &NodeAddr) -> Result<TcpStream> {
+        self.retry(node, || TcpStream::connect(address))
+    }
+
+    /// Makes `attempt` to reach `node` until it succeeds or the policy gives up.
+    pub fn retry<T>(
+        &self,
+        node: &str,
+        mut attempt: impl FnMut() -> std::io::Result<T>,
+    ) -> Result<T> {
+        let deadline = Instant::now() + self.deadline;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let error = match attempt() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if attempts >= self.max_attempts || remaining.is_zero() {
+                return Err(AppError::Unreachable {
+                    node: node.to_string(),
+                    attempts,
+                    error,
+                });
+            }
+            thread::sleep(self.delay(attempts).min(remaining));
+        }
+    }
+
+    /// Delay after the given failed attempt, doubling every time.
+    fn delay(&self, attempts: usize) -> Duration {
+        let exponent = (attempts - 1).min(16) as i32;
+        let delay = self.base_delay.as_secs_f64() * 2f64.powi(exponent);
+        let jitter = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        Duration::from_secs_f64(delay * (1.0 + jitter))
+    }
+}
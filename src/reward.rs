@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::model::Transition;
+
+/// Accumulates firing costs and rate rewards of the local transitions over a run.
+#[derive(Debug, Default, Clone)]
+pub struct Rewards {
+    per_transition: BTreeMap<usize, TransitionReward>,
+    elapsed: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TransitionReward {
+    pub firings: usize,
+    pub cost: f64,
+    pub reward: f64,
+}
+
+impl Rewards {
+    pub fn new(transitions: &[Transition]) -> Self {
+        let per_transition = transitions
+            .iter()
+            .filter(|transition| transition.reward_rate != 0.0 || transition.firing_cost != 0.0)
+            .map(|transition| (transition.id, TransitionReward::default()))
+            .collect();
+
+        Self {
+            per_transition,
+            elapsed: 0,
+        }
+    }
+
+    /// Accumulates for `transitions` too, as this node took them over; those it gave up
+    /// keep what they accumulated while hosted here.
+    pub fn track(&mut self, transitions: &[Transition]) {
+        transitions
+            .iter()
+            .filter(|transition| transition.reward_rate != 0.0 || transition.firing_cost != 0.0)
+            .for_each(|transition| {
+                self.per_transition.entry(transition.id).or_default();
+            });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.per_transition.is_empty()
+    }
+
+    pub fn fire(&mut self, transition: &Transition) {
+        if let Some(reward) = self.per_transition.get_mut(&transition.id) {
+            reward.firings += 1;
+            reward.cost += transition.firing_cost;
+        }
+    }
+
+    /// Integrates rate rewards over `elapsed` clock units; a transition earns
+    /// its rate while it is enabled (value <= 0).
+    pub fn accrue(&mut self, transitions: &[Transition], elapsed: usize) {
+        self.elapsed += elapsed;
+        transitions
+            .iter()
+            .filter(|transition| transition.value <= 0)
+            .for_each(|transition| {
+                if let Some(reward) = self.per_transition.get_mut(&transition.id) {
+                    reward.reward += transition.reward_rate * elapsed as f64;
+                }
+            });
+    }
+
+    pub fn total(&self) -> TransitionReward {
+        self.per_transition
+            .values()
+            .fold(TransitionReward::default(), |acc, reward| {
+                TransitionReward {
+                    firings: acc.firings + reward.firings,
+                    cost: acc.cost + reward.cost,
+                    reward: acc.reward + reward.reward,
+                }
+            })
+    }
+
+    fn average(&self, value: f64) -> f64 {
+        if self.elapsed == 0 {
+            0.0
+        } else {
+            value / self.elapsed as f64
+        }
+    }
+}
+
+impl Display for Rewards {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = self
+            .per_transition
+            .iter()
+            .map(|(id, reward)| {
+                format!(
+                    "id={} firings={} cost={} reward={} avg_cost={:.4} avg_reward={:.4}",
+                    id,
+                    reward.firings,
+                    reward.cost,
+                    reward.reward,
+                    self.average(reward.cost),
+                    self.average(reward.reward)
+                )
+            })
+            .collect::<Vec<_>>();
+        let total = self.total();
+
+        write!(
+            f,
+            "{} |___| total elapsed={} firings={} cost={} reward={} avg_cost={:.4} avg_reward={:.4}",
+            lines.join(" |___| "),
+            self.elapsed,
+            total.firings,
+            total.cost,
+            total.reward,
+            self.average(total.cost),
+            self.average(total.reward)
+        )
+    }
+}
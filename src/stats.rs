@@ -0,0 +1,117 @@
+use crate::dot::{edges, Partition};
+use crate::model::Net;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Writes the structural metrics of `nets` spread over the owners of `partition`: the
+/// instructions of every segment, how many nodes every node feeds and is fed by, the
+/// instructions crossing between nodes and how balanced the nodes are.
+///
+/// Traffic is estimated as if every transition fired once per duration, which bounds the
+/// events a crossing delayed instruction sends per clock unit.
+pub fn write(nets: &[Net], partition: &Partition) -> String {
+    let mut report = String::new();
+    let width = partition
+        .names
+        .iter()
+        .map(String::len)
+        .max()
+        .unwrap_or_default()
+        .max("node".len());
+
+    let _ = writeln!(
+        report,
+        "{:<7}  {:<width$}  {:>11}  {:>8}  {:>8}  {:>9}  {:>7}",
+        "segment", "node", "transitions", "internal", "external", "immediate", "delayed"
+    );
+    for (segment, net) in nets.iter().enumerate() {
+        let instructions = net.transitions.iter().flat_map(edges).collect::<Vec<_>>();
+        let external = instructions
+            .iter()
+            .filter(|(instruction, _)| instruction.is_external)
+            .count();
+        let immediate = instructions
+            .iter()
+            .filter(|(_, immediate)| *immediate)
+            .count();
+        let _ = writeln!(
+            report,
+            "{:<7}  {:<width$}  {:>11}  {:>8}  {:>8}  {:>9}  {:>7}",
+            segment,
+            partition.names[partition.owner(segment)],
+            net.transitions.len(),
+            instructions.len() - external,
+            external,
+            immediate,
+            instructions.len() - immediate
+        );
+    }
+
+    // events per clock unit sent along every crossing delayed instruction
+    let mut traffic = BTreeMap::<(usize, usize), f64>::new();
+    for transition in nets.iter().flat_map(|net| &net.transitions) {
+        for (instruction, immediate) in edges(transition) {
+            if immediate || !partition.crosses(transition, instruction) {
+                continue;
+            }
+            if let (Some(from), Some(to)) = (
+                partition.owner_of(transition.id),
+                partition.owner_of(instruction.transition_id),
+            ) {
+                *traffic.entry((from, to)).or_default() += 1.0 / transition.duration.max(1) as f64;
+            }
+        }
+    }
+    let feeds = partition.feeds(nets);
+
+    let _ = writeln!(report);
+    let _ = writeln!(
+        report,
+        "{:<width$}  {:>11}  {:>6}  {:>7}",
+        "node", "transitions", "fan-in", "fan-out"
+    );
+    let sizes = (0..partition.names.len())
+        .map(|owner| partition.transitions_of(nets, owner).count())
+        .collect::<Vec<_>>();
+    for (owner, name) in partition.names.iter().enumerate() {
+        let fan_in = feeds.keys().filter(|(_, to)| *to == owner).count();
+        let fan_out = feeds.keys().filter(|(from, _)| *from == owner).count();
+        let _ = writeln!(
+            report,
+            "{:<width$}  {:>11}  {:>6}  {:>7}",
+            name, sizes[owner], fan_in, fan_out
+        );
+    }
+
+    let _ = writeln!(report);
+    let _ = writeln!(
+        report,
+        "{:<width$}  {:<width$}  {:>12}  {:>15}",
+        "from", "to", "instructions", "events per unit"
+    );
+    for ((from, to), instructions) in &feeds {
+        let _ = writeln!(
+            report,
+            "{:<width$}  {:<width$}  {:>12}  {:>15.2}",
+            partition.names[*from],
+            partition.names[*to],
+            instructions,
+            traffic.get(&(*from, *to)).copied().unwrap_or_default()
+        );
+    }
+
+    let largest = sizes.iter().copied().max().unwrap_or_default();
+    let mean = sizes.iter().sum::<usize>() as f64 / sizes.len().max(1) as f64;
+    let _ = writeln!(report);
+    let _ = writeln!(
+        report,
+        "balance: largest node {largest} transitions, mean {mean:.1}, {:.2}x the mean",
+        if mean > 0.0 {
+            largest as f64 / mean
+        } else {
+            1.0
+        }
+    );
+
+    report
+}
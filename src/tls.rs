@@ -0,0 +1,203 @@
+use crate::address::NodeAddr;
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// A connection between two nodes, plain or encrypted.
+pub trait Stream: Read + Write + Send {
+    /// In non-blocking mode, reads with nothing to read fail with `WouldBlock`.
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+
+    /// Reads taking longer than `timeout` fail, none ever does without one.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Optional mutual TLS wrapped around every connection: each node presents its own
+/// certificate, on both ends, and verifies its peers' against the cluster CA.
+#[derive(Debug, Clone, Default)]
+pub struct Tls {
+    #[cfg(feature = "tls")]
+    configs: Option<rustls_configs::Configs>,
+}
+
+#[cfg(not(feature = "tls"))]
+impl Tls {
+    pub fn new(config: &Config) -> Result<Self> {
+        if config.tls_cert.is_some() || config.tls_key.is_some() || config.tls_ca.is_some() {
+            return Err(AppError::Tls(
+                "built without TLS support, rebuild with --features tls".to_string(),
+            ));
+        }
+
+        Ok(Self::default())
+    }
+
+    pub fn connect(&self, stream: TcpStream, _address: &NodeAddr) -> Result<Box<dyn Stream>> {
+        Ok(Box::new(stream))
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> Result<Box<dyn Stream>> {
+        Ok(Box::new(stream))
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Tls {
+    pub fn new(config: &Config) -> Result<Self> {
+        let configs = match (&config.tls_cert, &config.tls_key, &config.tls_ca) {
+            (None, None, None) => None,
+            (Some(cert), Some(key), Some(ca)) => Some(rustls_configs::Configs::new(cert, key, ca)?),
+            _ => {
+                return Err(AppError::Tls(
+                    "--tls-cert, --tls-key and --tls-ca go together".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self { configs })
+    }
+
+    pub fn connect(&self, stream: TcpStream, address: &NodeAddr) -> Result<Box<dyn Stream>> {
+        match &self.configs {
+            Some(configs) => Ok(Box::new(configs.connect(stream, address)?)),
+            None => Ok(Box::new(stream)),
+        }
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> Result<Box<dyn Stream>> {
+        match &self.configs {
+            Some(configs) => Ok(Box::new(configs.accept(stream)?)),
+            None => Ok(Box::new(stream)),
+        }
+    }
+
+    #[cfg(feature = "quic")]
+    pub(crate) fn configs(&self) -> Option<&rustls_configs::Configs> {
+        self.configs.as_ref()
+    }
+}
+
+#[cfg(feature = "tls")]
+pub(crate) mod rustls_configs {
+    use super::{NodeAddr, Stream};
+    use crate::error::{AppError, Result};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{
+        ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+    };
+    use std::net::TcpStream;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    impl Stream for StreamOwned<ClientConnection, TcpStream> {
+        fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+            self.sock.set_nonblocking(nonblocking)
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            self.sock.set_read_timeout(timeout)
+        }
+    }
+
+    impl Stream for StreamOwned<ServerConnection, TcpStream> {
+        fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+            self.sock.set_nonblocking(nonblocking)
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            self.sock.set_read_timeout(timeout)
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Configs {
+        pub client: Arc<ClientConfig>,
+        pub server: Arc<ServerConfig>,
+    }
+
+    impl Configs {
+        pub fn new(cert: &Path, key: &Path, ca: &Path) -> Result<Self> {
+            let certs = CertificateDer::pem_file_iter(cert)
+                .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+                .map_err(|error| AppError::Tls(format!("{}: {}", cert.display(), error)))?;
+            let key = PrivateKeyDer::from_pem_file(key)
+                .map_err(|error| AppError::Tls(format!("{}: {}", key.display(), error)))?;
+
+            let mut roots = RootCertStore::empty();
+            CertificateDer::pem_file_iter(ca)
+                .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+                .map_err(|error| AppError::Tls(format!("{}: {}", ca.display(), error)))?
+                .into_iter()
+                .try_for_each(|cert| roots.add(cert))?;
+
+            let roots = Arc::new(roots);
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            // peers connecting in have to present a certificate signed by the cluster CA too
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::clone(&roots),
+                Arc::clone(&provider),
+            )
+            .build()
+            .map_err(|error| AppError::Tls(error.to_string()))?;
+            let client = ClientConfig::builder_with_provider(Arc::clone(&provider))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_client_auth_cert(certs.clone(), key.clone_key())?;
+            let server = ServerConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(certs, key)?;
+
+            Ok(Self {
+                client: Arc::new(client),
+                server: Arc::new(server),
+            })
+        }
+
+        /// The peer's certificate has to be valid for the host part of its address.
+        pub fn connect(
+            &self,
+            stream: TcpStream,
+            address: &NodeAddr,
+        ) -> Result<StreamOwned<ClientConnection, TcpStream>> {
+            let server_name = ServerName::try_from(address.host())
+                .map_err(|error| AppError::Tls(format!("{}: {}", address, error)))?;
+            let connection = ClientConnection::new(Arc::clone(&self.client), server_name)?;
+            Ok(StreamOwned::new(connection, stream))
+        }
+
+        pub fn accept(
+            &self,
+            stream: TcpStream,
+        ) -> Result<StreamOwned<ServerConnection, TcpStream>> {
+            let connection = ServerConnection::new(Arc::clone(&self.server))?;
+            Ok(StreamOwned::new(connection, stream))
+        }
+    }
+}
@@ -0,0 +1,1470 @@
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "quic")]
+mod quic;
+mod shared_memory;
+mod udp;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "zeromq")]
+mod zeromq;
+
+use crate::address::NodeAddr;
+use crate::config::{Config, Link, WireFormat};
+use crate::error::{AppError, Result};
+use crate::logging;
+use crate::model::WireMessage;
+use crate::retry::RetryPolicy;
+use crate::tls::{Stream, Tls};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Unacknowledged messages after which the sender reads the acknowledgements received
+const ACK_POLL: usize = 64;
+
+/// Retransmissions attempted for the last unacknowledged messages once nothing else
+/// is left to send
+const CLOSING_RETRANSMISSIONS: usize = 3;
+
+/// Connections waiting to be accepted before new ones are refused
+const LISTEN_BACKLOG: i32 = 128;
+
+/// Prefix of a compressed message, which no JSON message starts with
+const COMPRESSED: char = '~';
+
+/// Length above which a bincode frame is taken for a corrupted length prefix
+const MAX_FRAME: usize = 1 << 26;
+
+/// Longest hello read from a peer, far more than any node sends
+const MAX_HELLO: u64 = 4 << 10;
+
+/// Longest a peer may take to answer the challenge, whatever --read-timeout is
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version of the messages exchanged between nodes, bumped whenever they change in a way
+/// the previous version cannot read, such as a new kind of message. Fields are only ever
+/// added with a default, and ignored by builds that do not know them.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Oldest version this build still reads, and writes to peers that speak no newer one,
+/// migrating its messages, so a cluster can be upgraded a node at a time
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// First line of every connection, sent by the listener.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Challenge {
+    #[serde(default)]
+    version: u32,
+    /// Oldest version the listener reads, `version` only if absent
+    #[serde(default)]
+    min_version: Option<u32>,
+    /// Random bytes, hex encoded, the sender signs to prove it knows the cluster secret
+    nonce: String,
+    /// Formats the listener reads messages in, JSON only if absent
+    #[serde(default)]
+    wire_formats: Vec<WireFormat>,
+    /// Run the listener takes part in, if it knows it yet
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+/// Sent in reply to the listener's challenge, telling it who is sending, in which version
+/// and, when the cluster shares a secret, proving it. The listener replies with how many messages it
+/// already received from that node, and keeps acknowledging its running count, so after
+/// a reconnect only the missing ones are resent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Hello {
+    /// Older of both ends' versions, which every message that follows is written in
+    #[serde(default)]
+    version: u32,
+    from: String,
+    /// HMAC-SHA256 of the challenge and `from` under the cluster secret, hex encoded
+    #[serde(default)]
+    proof: String,
+    /// Format of every message that follows
+    #[serde(default)]
+    wire_format: WireFormat,
+    /// Run the sender takes part in, if it knows it yet
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+/// Everything connections are set up with, on both ends.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    link: Link,
+    wire_format: WireFormat,
+    pub socket_options: SocketOptions,
+    pub tls: Tls,
+    /// Address listened on, none with --link mqtt, whose node names need not be addresses
+    bind_addr: Option<NodeAddr>,
+    /// Longest a message may go unacknowledged before it is retransmitted, if at all
+    ack_timeout: Option<Duration>,
+    /// Cluster members, the only nodes whose connections are accepted
+    members: Arc<RwLock<Vec<String>>>,
+    /// Run this node takes part in, once known, the only one whose messages are accepted
+    run_id: Arc<OnceLock<String>>,
+    secret: Option<Arc<str>>,
+    /// Whether every message carries an HMAC of its sender and content
+    sign_messages: bool,
+    /// Length above which messages are compressed, if any
+    compress_above: Option<usize>,
+    /// Channels going through shared memory rather than the link, if any
+    shared_memory: Arc<shared_memory::SharedMemory>,
+    /// Endpoint every QUIC connection goes through, with --link quic
+    #[cfg(feature = "quic")]
+    quic: Option<quic::Quic>,
+    /// Connection to the broker every message goes through, with --link mqtt
+    #[cfg(feature = "mqtt")]
+    broker: Option<mqtt::Broker>,
+    /// Runtime of every gRPC call, with --link grpc
+    #[cfg(feature = "grpc")]
+    grpc: Option<grpc::Grpc>,
+}
+
+impl Transport {
+    pub fn new(config: &Config, members: &[String]) -> Result<Self> {
+        if matches!(
+            config.link,
+            Link::Udp | Link::ZeroMq | Link::Mqtt | Link::Grpc
+        ) && config.tls_cert.is_some()
+        {
+            return Err(AppError::Tls(
+                "not available with --link udp, zeromq, mqtt or grpc".to_string(),
+            ));
+        }
+        if matches!(config.link, Link::ZeroMq | Link::Mqtt | Link::Grpc)
+            && config.ack_timeout.is_some()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--ack-timeout is not available with --link zeromq, mqtt or grpc",
+            )
+            .into());
+        }
+        // messages are protobuf rather than lines, and HTTP/2 already frames them
+        if config.link == Link::Grpc && (config.sign_messages || config.compress_above.is_some()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--sign-messages and --compress-above are not available with --link grpc",
+            )
+            .into());
+        }
+        // frames are written on connections as they are, which only TCP ones read back
+        if config.wire_format != WireFormat::Json
+            && (config.link != Link::Tcp
+                || config.sign_messages
+                || config.compress_above.is_some()
+                || !config.shared_memory.is_empty())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "--wire-format bincode, msgpack and protobuf only go with --link tcp, without \
+                 --sign-messages, --compress-above or --shared-memory",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "protobuf"))]
+        if config.wire_format == WireFormat::Protobuf {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without Protocol Buffers support, rebuild with --features protobuf",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "quic"))]
+        if config.link == Link::Quic {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without QUIC support, rebuild with --features quic",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "websocket"))]
+        if config.link == Link::WebSocket {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without WebSocket support, rebuild with --features websocket",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "zeromq"))]
+        if config.link == Link::ZeroMq {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without ZeroMQ support, rebuild with --features zeromq",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "mqtt"))]
+        if config.link == Link::Mqtt {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without MQTT support, rebuild with --features mqtt",
+            )
+            .into());
+        }
+        #[cfg(not(feature = "grpc"))]
+        if config.link == Link::Grpc {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without gRPC support, rebuild with --features grpc",
+            )
+            .into());
+        }
+
+        // MQTT node names are only ever told to the broker, whereas other links dial them
+        let (bind_addr, addresses) = match config.link {
+            Link::Mqtt => (None, Vec::new()),
+            _ => (
+                Some(match &config.bind_addr {
+                    Some(bind_addr) => bind_addr.clone(),
+                    None => config.node.parse()?,
+                }),
+                members
+                    .iter()
+                    .map(|node| node.parse())
+                    .collect::<std::io::Result<Vec<NodeAddr>>>()?,
+            ),
+        };
+        if addresses
+            .iter()
+            .chain(&bind_addr)
+            .any(|address| matches!(address, NodeAddr::Unix(_)))
+            && (!cfg!(unix) || config.link != Link::Tcp || config.tls_cert.is_some())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix socket paths only go with --link tcp, without TLS, on Unix",
+            )
+            .into());
+        }
+
+        let tls = Tls::new(config)?;
+        let socket_options = SocketOptions::new(config);
+        Ok(Self {
+            link: config.link,
+            wire_format: config.wire_format,
+            socket_options,
+            #[cfg(feature = "quic")]
+            quic: match (config.link, &bind_addr) {
+                (Link::Quic, Some(bind_addr)) => {
+                    Some(quic::Quic::bind(bind_addr, &tls, &socket_options)?)
+                }
+                _ => None,
+            },
+            #[cfg(feature = "mqtt")]
+            broker: match config.link {
+                Link::Mqtt => Some(mqtt::Broker::connect(
+                    &config.node,
+                    config.broker.as_ref().expect("required with --link mqtt"),
+                    config.send_queue,
+                )?),
+                _ => None,
+            },
+            #[cfg(feature = "grpc")]
+            grpc: match config.link {
+                Link::Grpc => Some(grpc::Grpc::new()?),
+                _ => None,
+            },
+            tls,
+            bind_addr,
+            ack_timeout: config.ack_timeout.map(Duration::from_millis),
+            members: Arc::new(RwLock::new(members.to_vec())),
+            run_id: Arc::new(OnceLock::new()),
+            secret: config.cluster_secret.as_deref().map(Arc::from),
+            sign_messages: config.sign_messages,
+            compress_above: config.compress_above,
+            shared_memory: Arc::new(shared_memory::SharedMemory::new(config, members)?),
+        })
+    }
+
+    /// HMAC of the challenge and sender under the cluster secret, if there is one.
+    fn mac(&self, challenge: &str, from: &str) -> Option<Hmac<Sha256>> {
+        let secret = self.secret.as_deref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(challenge.as_bytes());
+        mac.update(from.as_bytes());
+        Some(mac)
+    }
+
+    /// Turns `message`, a line, into what is written: compressed if long enough, then
+    /// followed by its HMAC after a tab if messages are signed.
+    fn frame(&self, from: &str, message: String) -> String {
+        let compressed = self
+            .compress_above
+            .filter(|&threshold| message.len() > threshold)
+            .and_then(|_| compress(message.trim_end()));
+        let message = match compressed {
+            Some(compressed) => format!("{COMPRESSED}{compressed}\n"),
+            None => message,
+        };
+        if !self.sign_messages {
+            return message;
+        }
+        let payload = message.trim_end();
+        match self.mac(payload, from) {
+            Some(mac) => format!("{payload}\t{}\n", hex(&mac.finalize().into_bytes())),
+            None => message,
+        }
+    }
+
+    /// Appends to `frame` the bytes written on a connection for `message`: a framed line,
+    /// or with binary wire formats its length as 4 little-endian bytes followed by its
+    /// encoding. Messages are serialized straight into `frame`, only compressed or signed
+    /// lines going through a String.
+    fn encode(&self, from: &str, message: &WireMessage, frame: &mut Vec<u8>) -> Result<()> {
+        if self.wire_format == WireFormat::Json {
+            if self.compress_above.is_none() && !self.sign_messages {
+                return write_line(frame, message);
+            }
+            frame.extend_from_slice(self.frame(from, line(message)?).as_bytes());
+            return Ok(());
+        }
+
+        let start = frame.len();
+        frame.extend_from_slice(&[0; 4]);
+        match self.wire_format {
+            WireFormat::Bincode => bincode::serialize_into(&mut *frame, message)?,
+            WireFormat::MessagePack => rmp_serde::encode::write_named(&mut *frame, message)?,
+            #[cfg(feature = "protobuf")]
+            WireFormat::Protobuf => {
+                prost::Message::encode(&crate::proto::WireMessage::from(message.clone()), frame)
+                    .expect("A Vec grows to fit any message")
+            }
+            #[cfg(not(feature = "protobuf"))]
+            WireFormat::Protobuf => unreachable!("rejected by Transport::new"),
+            WireFormat::Json => unreachable!("written as a line above"),
+        }
+        let length = (frame.len() - start - 4) as u32;
+        frame[start..start + 4].copy_from_slice(&length.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Undoes `frame`, or tells why the message has to be dropped. Compressed messages
+    /// are recognized whether or not this node compresses its own.
+    fn unframe<'a>(&self, from: &str, line: &'a str) -> std::result::Result<Cow<'a, str>, String> {
+        let payload = if self.sign_messages {
+            line.rsplit_once('\t')
+                .filter(|(payload, signature)| {
+                    let mac = self.mac(payload, from);
+                    let signature = unhex(signature).unwrap_or_default();
+                    mac.is_some_and(|mac| mac.verify_slice(&signature).is_ok())
+                })
+                .map(|(payload, _)| payload)
+                .ok_or("failing its HMAC")?
+        } else {
+            line
+        };
+
+        match payload.strip_prefix(COMPRESSED) {
+            Some(compressed) => decompress(compressed)
+                .map(Cow::Owned)
+                .ok_or_else(|| "failing to decompress".to_string()),
+            None => Ok(Cow::Borrowed(payload)),
+        }
+    }
+
+    fn proof(&self, challenge: &str, from: &str) -> String {
+        self.mac(challenge, from)
+            .map(|mac| hex(&mac.finalize().into_bytes()))
+            .unwrap_or_default()
+    }
+
+    /// Accepts connections from `nodes` as well from now on, on every clone of this
+    /// transport, as they joined the cluster.
+    pub fn admit(&self, nodes: &[String]) {
+        let mut members = self.members.write().unwrap();
+        nodes.iter().for_each(|node| {
+            if !members.contains(node) {
+                members.push(node.clone());
+            }
+        });
+    }
+
+    /// Takes part in `run_id` from now on, on every clone of this transport, unless
+    /// already taking part in another run.
+    pub fn join_run(&self, node: &str, run_id: &str) -> Result<()> {
+        let joined = self.run_id.get_or_init(|| run_id.to_string());
+        if joined != run_id {
+            return Err(AppError::OtherRun {
+                node: node.to_string(),
+                run_id: run_id.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.get().map(String::as_str)
+    }
+
+    /// Fails unless `node` takes part in the same run, as far as both ends know theirs.
+    fn check_run(&self, node: &str, run_id: Option<&str>) -> Result<()> {
+        match (self.run_id(), run_id) {
+            (Some(own), Some(other)) if own != other => Err(AppError::OtherRun {
+                node: node.to_string(),
+                run_id: other.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_member(&self, node: &str) -> bool {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .any(|member| member == node)
+    }
+
+    /// Accepts a sender only if it is a cluster member that knows the secret, if any.
+    fn authenticate(&self, challenge: &str, hello: &Hello) -> Result<()> {
+        if !self.is_member(&hello.from) {
+            return Err(AppError::Unauthenticated(format!(
+                "{} is not a cluster member",
+                hello.from
+            )));
+        }
+        if let Some(mac) = self.mac(challenge, &hello.from) {
+            let proof = unhex(&hello.proof).unwrap_or_default();
+            // compared in constant time, so the proof cannot be guessed byte by byte
+            mac.verify_slice(&proof).map_err(|_| {
+                AppError::Unauthenticated(format!("{} failed the challenge", hello.from))
+            })?;
+        }
+
+        self.check_run(&hello.from, hello.run_id.as_deref())
+    }
+
+    /// Opens a stream to `node`, at `address`, over the link, retrying with `policy`.
+    fn connect(
+        &self,
+        node: &str,
+        address: &NodeAddr,
+        policy: RetryPolicy,
+    ) -> Result<Box<dyn Stream>> {
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            return policy.retry(node, || quic.connect(node, address));
+        }
+
+        #[cfg(unix)]
+        if let NodeAddr::Unix(path) = address {
+            let socket = policy.retry(node, || UnixStream::connect(path))?;
+            socket.set_write_timeout(self.socket_options.write_timeout)?;
+            return Ok(Box::new(socket));
+        }
+
+        let socket = policy.connect(node, address)?;
+        self.socket_options.apply_outgoing(&socket)?;
+        let stream = self.tls.connect(socket, address)?;
+        #[cfg(feature = "websocket")]
+        if self.link == Link::WebSocket {
+            return websocket::connect(stream, node);
+        }
+
+        Ok(stream)
+    }
+}
+
+/// Options applied to every connection, incoming and outgoing alike
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    /// Incoming connections only, so a half-open one is eventually dropped
+    pub read_timeout: Option<Duration>,
+    /// Outgoing connections only, so a stuck peer is eventually reconnected to
+    pub write_timeout: Option<Duration>,
+    /// Listening sockets on an IPv6 address only, so they accept IPv4 peers too
+    pub dual_stack: bool,
+}
+
+impl SocketOptions {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            nodelay: config.tcp_nodelay,
+            keepalive: config.tcp_keepalive.map(Duration::from_secs),
+            read_timeout: config.read_timeout.map(Duration::from_millis),
+            write_timeout: config.write_timeout.map(Duration::from_millis),
+            dual_stack: config.dual_stack,
+        }
+    }
+
+    /// Socket of the address family of `address`, bound to it.
+    fn bind(&self, address: &NodeAddr, kind: Type, protocol: Protocol) -> std::io::Result<Socket> {
+        let address = address.resolve()?;
+        let socket = Socket::new(Domain::for_address(address), kind, Some(protocol))?;
+        if address.is_ipv6() {
+            socket.set_only_v6(!self.dual_stack)?;
+        }
+        // as std does, so a restarted node can bind again right away
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&address.into())?;
+
+        Ok(socket)
+    }
+
+    fn bind_tcp(&self, address: &NodeAddr) -> std::io::Result<TcpListener> {
+        let socket = self.bind(address, Type::STREAM, Protocol::TCP)?;
+        socket.listen(LISTEN_BACKLOG)?;
+
+        Ok(socket.into())
+    }
+
+    fn bind_udp(&self, address: &NodeAddr) -> std::io::Result<UdpSocket> {
+        Ok(self.bind(address, Type::DGRAM, Protocol::UDP)?.into())
+    }
+
+    fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            let params = TcpKeepalive::new()
+                .with_time(keepalive)
+                .with_interval(keepalive);
+            SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_incoming(&self, stream: &TcpStream) -> std::io::Result<()> {
+        self.apply(stream)?;
+        stream.set_read_timeout(self.read_timeout)
+    }
+
+    fn apply_outgoing(&self, stream: &TcpStream) -> std::io::Result<()> {
+        self.apply(stream)?;
+        stream.set_write_timeout(self.write_timeout)
+    }
+}
+
+/// Outgoing messages to one peer, written by a dedicated thread so a slow peer only
+/// stalls the simulation once its bounded queue is full.
+#[derive(Debug)]
+pub struct Outbox {
+    queue: Option<Queue>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+/// Messages waiting for the thread writing them to one peer.
+#[derive(Debug)]
+enum Queue {
+    /// Lines, framed by the link
+    Lines(SyncSender<String>),
+    /// Messages encoded by the connection, in the version the peer speaks
+    Messages(SyncSender<WireMessage>),
+}
+
+impl Outbox {
+    /// Connects with `connect_policy`, and with `reconnect_policy` whenever the connection breaks.
+    pub fn new(
+        from: &str,
+        node: &str,
+        connect_policy: RetryPolicy,
+        reconnect_policy: RetryPolicy,
+        transport: Transport,
+        capacity: usize,
+    ) -> Self {
+        if !transport.shared_memory.connects(node)
+            && matches!(transport.link, Link::Tcp | Link::Quic | Link::WebSocket)
+        {
+            return Self::connect(
+                from,
+                node,
+                connect_policy,
+                reconnect_policy,
+                transport,
+                capacity,
+            );
+        }
+
+        let (queue, messages) = sync_channel::<String>(capacity);
+        if transport.shared_memory.connects(node) {
+            let shared_memory = Arc::clone(&transport.shared_memory);
+            let node = node.to_string();
+            let worker = thread::spawn(move || {
+                shared_memory.deliver(&node, messages, connect_policy, reconnect_policy)
+            });
+            return Self {
+                queue: Some(Queue::Lines(queue)),
+                worker: Some(worker),
+            };
+        }
+
+        if transport.link == Link::Udp {
+            let ack_timeout = transport.ack_timeout;
+            let datagrams = udp::Datagrams::new(from, node, transport);
+            let worker = thread::spawn(move || -> Result<()> {
+                datagrams?.deliver(messages, connect_policy, reconnect_policy, ack_timeout)
+            });
+            return Self {
+                queue: Some(Queue::Lines(queue)),
+                worker: Some(worker),
+            };
+        }
+
+        #[cfg(feature = "zeromq")]
+        if transport.link == Link::ZeroMq {
+            let (from, node) = (from.to_string(), node.to_string());
+            let worker = thread::spawn(move || {
+                zeromq::deliver(&from, &node, messages, connect_policy, transport)
+            });
+            return Self {
+                queue: Some(Queue::Lines(queue)),
+                worker: Some(worker),
+            };
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(broker) = transport.broker.clone() {
+            let node = node.to_string();
+            let worker =
+                thread::spawn(move || broker.deliver(&node, messages, connect_policy, transport));
+            return Self {
+                queue: Some(Queue::Lines(queue)),
+                worker: Some(worker),
+            };
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = transport.grpc.clone() {
+            let (from, node) = (from.to_string(), node.to_string());
+            let worker = thread::spawn(move || {
+                grpc.deliver(
+                    &from,
+                    &node,
+                    messages,
+                    connect_policy,
+                    reconnect_policy,
+                    transport,
+                )
+            });
+            return Self {
+                queue: Some(Queue::Lines(queue)),
+                worker: Some(worker),
+            };
+        }
+
+        unreachable!("Every link has its outbox")
+    }
+
+    /// Outbox over a connection of its own, with --link tcp, quic or websocket.
+    fn connect(
+        from: &str,
+        node: &str,
+        connect_policy: RetryPolicy,
+        reconnect_policy: RetryPolicy,
+        transport: Transport,
+        capacity: usize,
+    ) -> Self {
+        let (queue, messages) = sync_channel::<WireMessage>(capacity);
+        let connection = Connection::new(from, node, transport);
+        let worker = thread::spawn(move || -> Result<()> {
+            let mut connection = connection?;
+            connection.open(connect_policy)?;
+            if let Some(ack_timeout) = connection.transport.ack_timeout {
+                return connection.deliver_reliably(messages, reconnect_policy, ack_timeout);
+            }
+            for message in messages {
+                connection.deliver(message, reconnect_policy)?;
+            }
+
+            Ok(())
+        });
+
+        Self {
+            queue: Some(Queue::Messages(queue)),
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues a message, blocking while the queue is full.
+    pub fn send(&mut self, message: WireMessage) -> Result<()> {
+        let queued = match &self.queue {
+            Some(Queue::Lines(queue)) => queue.send(line(&message)?).is_ok(),
+            Some(Queue::Messages(queue)) => queue.send(message).is_ok(),
+            None => false,
+        };
+        if !queued {
+            self.close()?;
+            unreachable!("The sender thread only stops early on an error");
+        }
+
+        Ok(())
+    }
+
+    /// Returns why the sender thread stopped, if it already has, e.g. the peer was unreachable.
+    pub fn check(&mut self) -> Result<()> {
+        if self.worker.as_ref().is_some_and(JoinHandle::is_finished) {
+            return self.close();
+        }
+
+        Ok(())
+    }
+
+    /// Waits until every queued message is written, returning why the thread stopped early if it did.
+    pub fn close(&mut self) -> Result<()> {
+        self.queue.take();
+        match self.worker.take() {
+            Some(worker) => worker.join().expect("Sender thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sending side of a connection, keeping every message until the peer acknowledges it.
+struct Connection {
+    from: String,
+    node: String,
+    address: NodeAddr,
+    transport: Transport,
+    stream: Option<BufReader<Box<dyn Stream>>>,
+    /// Version the peer was last found to speak
+    version: u32,
+    /// Messages written but not acknowledged yet, oldest first, as written
+    unacked: VecDeque<Vec<u8>>,
+    /// Number of messages sent before the oldest unacknowledged one
+    first_unacked: usize,
+    /// Running count of messages the peer acknowledged
+    acked: usize,
+    /// Acknowledgement read only partially so far
+    ack_line: String,
+    /// Buffers of acknowledged messages, cleared, for the next ones to be written into
+    spare: Vec<Vec<u8>>,
+    /// Since when the oldest unacknowledged message has been waiting for its acknowledgement
+    waiting_since: Instant,
+}
+
+impl Connection {
+    fn new(from: &str, node: &str, transport: Transport) -> std::io::Result<Self> {
+        Ok(Self {
+            from: from.to_string(),
+            node: node.to_string(),
+            address: node.parse()?,
+            transport,
+            stream: None,
+            version: PROTOCOL_VERSION,
+            unacked: VecDeque::new(),
+            first_unacked: 0,
+            acked: 0,
+            ack_line: String::new(),
+            spare: Vec::new(),
+            waiting_since: Instant::now(),
+        })
+    }
+
+    /// (Re)connects, learns how many messages the peer already has and resends the rest.
+    fn open(&mut self, policy: RetryPolicy) -> Result<()> {
+        // a peer dying with the connection not accepted yet resets it, it may be restarting
+        let mut resets = 0;
+        let (mut stream, challenge) = loop {
+            let mut stream =
+                BufReader::new(self.transport.connect(&self.node, &self.address, policy)?);
+            let mut challenge = String::new();
+            match stream.read_line(&mut challenge) {
+                Err(error)
+                    if error.kind() == std::io::ErrorKind::ConnectionReset
+                        && resets + 1 < policy.max_attempts =>
+                {
+                    resets += 1;
+                }
+                read => {
+                    read?;
+                    break (stream, challenge);
+                }
+            }
+        };
+        let challenge = serde_json::from_str::<Challenge>(&challenge).unwrap_or(Challenge {
+            version: 0,
+            min_version: None,
+            nonce: String::new(),
+            wire_formats: Vec::new(),
+            run_id: None,
+        });
+        self.transport
+            .check_run(&self.node, challenge.run_id.as_deref())?;
+        // both ends speak the older version, as long as each of them still reads it
+        let version = PROTOCOL_VERSION.min(challenge.version);
+        if version < MIN_PROTOCOL_VERSION
+            || version < challenge.min_version.unwrap_or(challenge.version)
+        {
+            return Err(AppError::ProtocolMismatch {
+                node: self.node.clone(),
+                version: challenge.version,
+            });
+        }
+        let wire_format = self.transport.wire_format;
+        if wire_format != WireFormat::Json && !challenge.wire_formats.contains(&wire_format) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} does not read {wire_format:?} messages", self.node),
+            )
+            .into());
+        }
+        // bincode does not tell fields apart, a field added since shifts all those after it
+        if wire_format == WireFormat::Bincode && version != PROTOCOL_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!(
+                    "{} speaks protocol version {version}, bincode needs both ends on {}",
+                    self.node, PROTOCOL_VERSION
+                ),
+            )
+            .into());
+        }
+        self.version = version;
+        let hello = serde_json::to_string(&Hello {
+            version,
+            from: self.from.clone(),
+            proof: self.transport.proof(&challenge.nonce, &self.from),
+            wire_format,
+            run_id: self.transport.run_id().map(str::to_string),
+        })?;
+        stream
+            .get_mut()
+            .write_all(format!("{hello}\n").as_bytes())?;
+        stream.get_mut().flush()?;
+
+        let mut received = String::new();
+        stream.read_line(&mut received)?;
+        let received = received.trim().parse().unwrap_or_default();
+        // either end restarted since, counting afresh: what is left unacknowledged is
+        // numbered on from the peer's count, resent in full below
+        if self.stream.is_none() || received < self.acked {
+            self.first_unacked = received;
+            self.acked = received;
+        }
+        self.acked = self.acked.max(received);
+        self.ack_line.clear();
+        self.trim();
+
+        self.unacked
+            .iter()
+            .try_for_each(|message| stream.get_mut().write_all(message))?;
+        stream.get_mut().flush()?;
+        self.stream = Some(stream);
+        self.waiting_since = Instant::now();
+
+        Ok(())
+    }
+
+    /// Writes `message` as the peer reads it, migrated to the version it speaks, into a
+    /// spare buffer when there is one, reconnecting with `policy` if the write fails.
+    fn deliver(&mut self, message: WireMessage, policy: RetryPolicy) -> Result<()> {
+        for message in message.migrate(self.version) {
+            let mut frame = self.spare.pop().unwrap_or_default();
+            self.transport.encode(&self.from, &message, &mut frame)?;
+            if self.write(frame).is_err() {
+                self.open(policy)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every message as it comes, and retransmits whatever the peer does not
+    /// acknowledge within `ack_timeout`, so no message is lost with a connection that
+    /// died silently. Once every message is queued, waits for the last acknowledgements.
+    fn deliver_reliably(
+        &mut self,
+        messages: Receiver<WireMessage>,
+        policy: RetryPolicy,
+        ack_timeout: Duration,
+    ) -> Result<()> {
+        let poll = ack_timeout / 4;
+        loop {
+            match messages.recv_timeout(poll) {
+                Ok(message) => self.deliver(message, policy)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            // acknowledgements may simply not have been read yet
+            if self.is_overdue(ack_timeout)
+                && (self.poll_acks().is_err() || self.is_overdue(ack_timeout))
+            {
+                warn!(
+                    target: logging::NETWORK,
+                    to = %self.node,
+                    unacknowledged = self.unacked.len(),
+                    "RETRANSMITTING"
+                );
+                self.open(policy)?;
+            }
+        }
+
+        // the peer may be done and gone by now, so giving up is only reported
+        for _ in 0..CLOSING_RETRANSMISSIONS {
+            let deadline = Instant::now() + ack_timeout;
+            while !self.unacked.is_empty() && Instant::now() < deadline {
+                if self.poll_acks().is_err() {
+                    break;
+                }
+                thread::sleep(poll);
+            }
+            if self.unacked.is_empty() || self.open(policy).is_err() {
+                break;
+            }
+        }
+        if !self.unacked.is_empty() {
+            warn!(
+                target: logging::NETWORK,
+                to = %self.node,
+                unacknowledged = self.unacked.len(),
+                "NEVER ACKNOWLEDGED"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn is_overdue(&self, ack_timeout: Duration) -> bool {
+        !self.unacked.is_empty() && self.waiting_since.elapsed() >= ack_timeout
+    }
+
+    fn write(&mut self, message: Vec<u8>) -> std::io::Result<()> {
+        if self.unacked.is_empty() {
+            self.waiting_since = Instant::now();
+        }
+        self.unacked.push_back(message);
+        let message = self.unacked.back().expect("Message was just pushed");
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        };
+        stream.get_mut().write_all(message)?;
+        stream.get_mut().flush()?;
+
+        if self.unacked.len() >= ACK_POLL {
+            self.poll_acks()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads whatever acknowledgements arrived, without waiting for more.
+    fn poll_acks(&mut self) -> std::io::Result<()> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(());
+        };
+
+        stream.get_ref().set_nonblocking(true)?;
+        let polled = loop {
+            match stream.read_line(&mut self.ack_line) {
+                Ok(0) => break Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {
+                    if let Ok(count) = self.ack_line.trim().parse() {
+                        self.acked = self.acked.max(count);
+                    }
+                    self.ack_line.clear();
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
+        stream.get_ref().set_nonblocking(false)?;
+        self.trim();
+
+        polled
+    }
+
+    fn trim(&mut self) {
+        let first_unacked = self.first_unacked;
+        while self.first_unacked < self.acked {
+            let Some(mut frame) = self.unacked.pop_front() else {
+                break;
+            };
+            self.first_unacked += 1;
+            // no more kept than can be waiting for acknowledgement between two polls
+            if self.spare.len() < ACK_POLL {
+                frame.clear();
+                self.spare.push(frame);
+            }
+        }
+        if self.first_unacked != first_unacked {
+            self.waiting_since = Instant::now();
+        }
+    }
+}
+
+/// Where a node receives its messages, depending on the link.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    Udp(UdpSocket),
+    #[cfg(feature = "quic")]
+    Quic(quic::Quic),
+    #[cfg(feature = "websocket")]
+    WebSocket(TcpListener),
+    #[cfg(feature = "zeromq")]
+    ZeroMq(zeromq::Inbox),
+    #[cfg(feature = "mqtt")]
+    Mqtt(mqtt::Broker),
+    #[cfg(feature = "grpc")]
+    Grpc(TcpListener),
+}
+
+impl Listener {
+    /// Listens on the address of `transport`, through its link.
+    pub fn bind(transport: &Transport) -> std::io::Result<Self> {
+        match (transport.link, &transport.bind_addr) {
+            #[cfg(unix)]
+            (Link::Tcp, Some(NodeAddr::Unix(path))) => {
+                // left behind by a previous run, as nothing removes it on exit
+                if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path).map(Self::Unix)
+            }
+            (Link::Tcp, Some(address)) => transport.socket_options.bind_tcp(address).map(Self::Tcp),
+            (Link::Udp, Some(address)) => transport.socket_options.bind_udp(address).map(Self::Udp),
+            #[cfg(feature = "quic")]
+            (Link::Quic, _) => Ok(Self::Quic(
+                transport.quic.clone().expect("bound with the link"),
+            )),
+            #[cfg(not(feature = "quic"))]
+            (Link::Quic, _) => unreachable!("rejected by Transport::new"),
+            #[cfg(feature = "websocket")]
+            (Link::WebSocket, Some(address)) => transport
+                .socket_options
+                .bind_tcp(address)
+                .map(Self::WebSocket),
+            #[cfg(not(feature = "websocket"))]
+            (Link::WebSocket, _) => unreachable!("rejected by Transport::new"),
+            #[cfg(feature = "zeromq")]
+            (Link::ZeroMq, Some(address)) => zeromq::Inbox::bind(address).map(Self::ZeroMq),
+            #[cfg(not(feature = "zeromq"))]
+            (Link::ZeroMq, _) => unreachable!("rejected by Transport::new"),
+            #[cfg(feature = "mqtt")]
+            (Link::Mqtt, _) => Ok(Self::Mqtt(
+                transport.broker.clone().expect("connected with the link"),
+            )),
+            #[cfg(not(feature = "mqtt"))]
+            (Link::Mqtt, _) => unreachable!("rejected by Transport::new"),
+            #[cfg(feature = "grpc")]
+            (Link::Grpc, Some(address)) => {
+                transport.socket_options.bind_tcp(address).map(Self::Grpc)
+            }
+            #[cfg(not(feature = "grpc"))]
+            (Link::Grpc, _) => unreachable!("rejected by Transport::new"),
+            (_, None) => {
+                unreachable!("every link but MQTT has an address, parsed by Transport::new")
+            }
+        }
+    }
+
+    /// Forwards every message received to `message_tx`, those through shared memory
+    /// included.
+    pub fn serve(self, message_tx: Sender<WireMessage>, transport: Transport) {
+        // links other than TCP hand lines over, parsed on a thread of their own
+        let (line_tx, lines) = channel::<String>();
+        let parsed_tx = message_tx.clone();
+        thread::spawn(move || {
+            for message in lines.iter().filter_map(|line| parse(&line)) {
+                if parsed_tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        transport.shared_memory.serve(line_tx.clone());
+        match self {
+            Self::Tcp(tcp_listener) => accept_connections(tcp_listener, message_tx, transport),
+            #[cfg(unix)]
+            Self::Unix(unix_listener) => {
+                accept_unix_connections(unix_listener, message_tx, transport)
+            }
+            Self::Udp(socket) => udp::receive_datagrams(socket, line_tx, transport),
+            #[cfg(feature = "quic")]
+            Self::Quic(quic) => quic.serve(message_tx, transport),
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(tcp_listener) => websocket::serve(tcp_listener, message_tx, transport),
+            #[cfg(feature = "zeromq")]
+            Self::ZeroMq(inbox) => inbox.serve(line_tx, transport),
+            #[cfg(feature = "mqtt")]
+            Self::Mqtt(broker) => broker.serve(line_tx, transport),
+            #[cfg(feature = "grpc")]
+            Self::Grpc(tcp_listener) => transport.grpc.clone().expect("built with the link").serve(
+                tcp_listener,
+                line_tx,
+                transport,
+            ),
+        }
+    }
+}
+
+/// Every peer keeps a single connection open, each one is read on its own thread so a
+/// burst from one peer never holds back the others. Every line or frame read is a message.
+fn accept_connections(
+    tcp_listener: TcpListener,
+    message_tx: Sender<WireMessage>,
+    transport: Transport,
+) {
+    let received = Received::default();
+    tcp_listener.incoming().flatten().for_each(|stream| {
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        if let Err(error) = transport.socket_options.apply_incoming(&stream) {
+            warn!(target: logging::NETWORK, %peer, %error, "SOCKET OPTIONS NOT SET");
+        }
+        let message_tx = message_tx.clone();
+        let received = Arc::clone(&received);
+        let transport = transport.clone();
+        spawn_connection(&peer, move || {
+            let stream = transport.tls.accept(stream)?;
+            read_connection(stream, message_tx, received, &transport)
+        });
+    });
+}
+
+/// Same as `accept_connections`, for nodes on the same host addressed by socket path.
+#[cfg(unix)]
+fn accept_unix_connections(
+    unix_listener: UnixListener,
+    message_tx: Sender<WireMessage>,
+    transport: Transport,
+) {
+    let received = Received::default();
+    unix_listener.incoming().flatten().for_each(|stream| {
+        let message_tx = message_tx.clone();
+        let received = Arc::clone(&received);
+        let transport = transport.clone();
+        // peers connect from unnamed sockets, they only introduce themselves in their hello
+        spawn_connection("a local peer", move || {
+            read_connection(Box::new(stream), message_tx, received, &transport)
+        });
+    });
+}
+
+/// Messages received per sending node, surviving reconnections
+type Received = Arc<Mutex<HashMap<String, usize>>>;
+
+/// Handles a connection from `peer` on a thread of its own, reporting why it was rejected
+/// if it was.
+fn spawn_connection(peer: &str, handle: impl FnOnce() -> Result<()> + Send + 'static) {
+    let name = format!("connection from {peer}");
+    let rejected = peer.to_string();
+    let spawned = thread::Builder::new().name(name).spawn(move || {
+        if let Err(
+            error @ (AppError::Unauthenticated(_)
+            | AppError::Tls(_)
+            | AppError::ProtocolMismatch { .. }
+            | AppError::OtherRun { .. }),
+        ) = handle()
+        {
+            warn!(target: logging::NETWORK, peer = %rejected, %error, "REJECTED");
+        }
+    });
+    if let Err(error) = spawned {
+        warn!(target: logging::NETWORK, %peer, %error, "CONNECTION NOT HANDLED");
+    }
+}
+
+fn read_connection(
+    stream: Box<dyn Stream>,
+    message_tx: Sender<WireMessage>,
+    received: Received,
+    transport: &Transport,
+) -> Result<()> {
+    // until authenticated, a peer neither holds the thread nor fills memory at will
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let challenge = serde_json::to_string(&Challenge {
+        version: PROTOCOL_VERSION,
+        min_version: Some(MIN_PROTOCOL_VERSION),
+        nonce: hex(&nonce),
+        wire_formats: WireFormat::value_variants()
+            .iter()
+            .copied()
+            .filter(|&wire_format| {
+                cfg!(feature = "protobuf") || wire_format != WireFormat::Protobuf
+            })
+            .collect(),
+        run_id: transport.run_id().map(str::to_string),
+    })?;
+    reader
+        .get_mut()
+        .write_all(format!("{challenge}\n").as_bytes())?;
+    reader.get_mut().flush()?;
+
+    let mut hello = String::new();
+    reader.by_ref().take(MAX_HELLO).read_line(&mut hello)?;
+    if hello.len() as u64 == MAX_HELLO && !hello.ends_with('\n') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("hello longer than {MAX_HELLO} bytes"),
+        )
+        .into());
+    }
+    let hello = serde_json::from_str::<Hello>(&hello)?;
+    if !is_compatible(hello.version) {
+        return Err(AppError::ProtocolMismatch {
+            node: hello.from,
+            version: hello.version,
+        });
+    }
+    transport.authenticate(&hex(&nonce), &hello)?;
+    reader
+        .get_ref()
+        .set_read_timeout(transport.socket_options.read_timeout)?;
+    // every sender picks its own format, whatever this node writes in
+    let wire_format = hello.wire_format;
+    let from = hello.from;
+
+    let count = *received.lock().unwrap().entry(from.clone()).or_default();
+    reader
+        .get_mut()
+        .write_all(format!("{count}\n").as_bytes())?;
+    reader.get_mut().flush()?;
+
+    let mut line = String::new();
+    let mut frame = vec![];
+    loop {
+        // dropped messages are still counted, the sender must not resend them
+        let message = match wire_format {
+            WireFormat::Json => {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    break;
+                }
+                match transport.unframe(&from, line.trim_end()) {
+                    Ok(message) => parse(&message),
+                    Err(reason) => {
+                        warn!(
+                            target: logging::NETWORK,
+                            %from,
+                            %reason,
+                            line = line.trim_end(),
+                            "DROPPED MESSAGE"
+                        );
+                        None
+                    }
+                }
+            }
+            WireFormat::Bincode | WireFormat::MessagePack | WireFormat::Protobuf => {
+                if !read_frame(&mut reader, &mut frame)? {
+                    break;
+                }
+                match decode(wire_format, &frame) {
+                    Ok(message) => Some(message),
+                    Err(error) => {
+                        warn!(
+                            target: logging::NETWORK,
+                            %from,
+                            %error,
+                            reason = unreadable(&error),
+                            "DROPPED MESSAGE"
+                        );
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(message) = message {
+            if message_tx.send(message).is_err() {
+                break;
+            }
+        }
+
+        let count = {
+            let mut received = received.lock().unwrap();
+            let count = received.entry(from.clone()).or_default();
+            *count += 1;
+            *count
+        };
+        // acknowledge once caught up, so bursts are acknowledged once
+        if reader.buffer().is_empty() {
+            reader
+                .get_mut()
+                .write_all(format!("{count}\n").as_bytes())?;
+            reader.get_mut().flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the next frame written in a binary wire format into `frame`, reused from one
+/// frame to the next, false once the peer closed the connection.
+fn read_frame(reader: &mut impl Read, frame: &mut Vec<u8>) -> std::io::Result<bool> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+        read => read?,
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {length} bytes, the connection is out of step"),
+        ));
+    }
+    frame.resize(length, 0);
+    reader.read_exact(frame)?;
+
+    Ok(true)
+}
+
+/// Decodes a frame, or tells why it is not a message.
+fn decode(wire_format: WireFormat, frame: &[u8]) -> std::result::Result<WireMessage, String> {
+    match wire_format {
+        WireFormat::Json => serde_json::from_slice(frame).map_err(|error| error.to_string()),
+        WireFormat::Bincode => bincode::deserialize(frame).map_err(|error| error.to_string()),
+        WireFormat::MessagePack => rmp_serde::from_slice(frame).map_err(|error| error.to_string()),
+        #[cfg(feature = "protobuf")]
+        WireFormat::Protobuf => <crate::proto::WireMessage as prost::Message>::decode(frame)
+            .map_err(|error| error.to_string())?
+            .try_into(),
+        // never offered in the challenge
+        #[cfg(not(feature = "protobuf"))]
+        WireFormat::Protobuf => Err("built without Protocol Buffers support".to_string()),
+    }
+}
+
+/// `message` as a line, the listening side considering \n as a message terminator.
+fn line(message: &WireMessage) -> Result<String> {
+    let mut line = Vec::new();
+    write_line(&mut line, message)?;
+    Ok(String::from_utf8(line).expect("JSON is UTF-8"))
+}
+
+/// Appends `message` to `buffer` as a line, without an intermediate String.
+fn write_line(buffer: &mut Vec<u8>, message: &WireMessage) -> Result<()> {
+    serde_json::to_writer(&mut *buffer, message)?;
+    buffer.push(b'\n');
+    Ok(())
+}
+
+/// Parses a line received, reporting it if it is not a message.
+fn parse(line: &str) -> Option<WireMessage> {
+    match serde_json::from_str(line) {
+        Ok(message) => Some(message),
+        Err(error) => {
+            let error = error.to_string();
+            warn!(
+                target: logging::NETWORK,
+                line,
+                %error,
+                reason = unreadable(&error),
+                "DROPPED MESSAGE"
+            );
+            None
+        }
+    }
+}
+
+/// Why a message could not be read, given the error: serde tells of variants added by
+/// newer versions, which are skipped like malformed messages.
+fn unreadable(error: &str) -> &'static str {
+    if error.starts_with("unknown variant") {
+        "newer than this build"
+    } else {
+        "malformed"
+    }
+}
+
+/// Whether messages written in `version` are read, those of newer builds as far as this
+/// one knows their fields and kinds.
+pub fn is_compatible(version: u32) -> bool {
+    version >= MIN_PROTOCOL_VERSION
+}
+
+/// `bytes` in lowercase hexadecimal, two digits each.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// zstd compressed and base64 encoded, so it still fits on a line. None if that does not
+/// make it any shorter.
+fn compress(message: &str) -> Option<String> {
+    let compressed = zstd::encode_all(message.as_bytes(), 0).ok()?;
+    let encoded = BASE64.encode(compressed);
+    (encoded.len() < message.len()).then_some(encoded)
+}
+
+fn decompress(encoded: &str) -> Option<String> {
+    let compressed = BASE64.decode(encoded).ok()?;
+    String::from_utf8(zstd::decode_all(compressed.as_slice()).ok()?).ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn rejects_a_hello_longer_than_max_hello() {
+        let nodes = ["petri-test-hello-0.sock", "petri-test-hello-1.sock"]
+            .map(|socket| std::env::temp_dir().join(socket).display().to_string());
+        let config = Config::parse_from([
+            "petri",
+            "--node",
+            &nodes[0],
+            "--nodes",
+            &nodes[0],
+            &nodes[1],
+            "--terminal-clock",
+            "1",
+        ]);
+        let transport = Transport::new(&config, &nodes).expect("Failed to set up the transport");
+        let (stream, mut peer) = UnixStream::pair().expect("Failed to connect");
+        let (message_tx, _messages) = channel();
+        let reading = thread::spawn(move || {
+            read_connection(
+                Box::new(stream),
+                message_tx,
+                Received::default(),
+                &transport,
+            )
+        });
+
+        let mut challenge = String::new();
+        BufReader::new(&peer)
+            .read_line(&mut challenge)
+            .expect("No challenge");
+        // the connection is dropped once MAX_HELLO bytes are read, so writing may fail
+        let _ = peer.write_all(&[b'x'; 2 * MAX_HELLO as usize]);
+        let read = reading.join().expect("The reading thread panicked");
+        assert!(
+            matches!(&read, Err(AppError::Io(error)) if error.kind() == std::io::ErrorKind::InvalidData),
+            "{read:?}"
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use super::{hex, is_compatible, Hello, Received, Transport, PROTOCOL_VERSION};
+use crate::config::WireFormat;
+use crate::error::{AppError, Result};
+use crate::logging;
+use crate::model::{self, Control, WireMessage};
+use crate::proto;
+use crate::retry::RetryPolicy;
+use proto::event::Kind;
+use proto::node_client::NodeClient;
+use proto::node_server::{Node, NodeServer};
+use rand::RngCore;
+use std::collections::{HashSet, VecDeque};
+use std::net::TcpListener;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::warn;
+
+/// Metadata every call carries, see proto/petri.proto
+const FROM: &str = "petri-from";
+const VERSION: &str = "petri-version";
+const NONCE: &str = "petri-nonce";
+const PROOF: &str = "petri-proof";
+const RUN: &str = "petri-run";
+
+/// Events and acknowledgements buffered per stream, either way
+const STREAM_QUEUE: usize = 64;
+
+/// Runtime driving this node's calls, both ways.
+#[derive(Debug, Clone)]
+pub struct Grpc {
+    runtime: Arc<Runtime>,
+}
+
+impl Grpc {
+    pub fn new() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Streams every event to `node`, and makes a call for every control message once
+    /// the events before it are acknowledged. Once every message is sent, waits for the
+    /// last acknowledgements.
+    pub(super) fn deliver(
+        &self,
+        from: &str,
+        node: &str,
+        messages: Receiver<String>,
+        connect_policy: RetryPolicy,
+        reconnect_policy: RetryPolicy,
+        transport: Transport,
+    ) -> Result<()> {
+        let ack_timeout = connect_policy.deadline;
+        let mut peer = Peer::new(self, from, node, transport);
+        peer.open(connect_policy)?;
+
+        for message in messages {
+            let version = peer.version;
+            let messages = serde_json::from_str::<WireMessage>(&message)?
+                .unbatch()
+                .into_iter()
+                .flat_map(|message| message.migrate(version));
+            for message in messages {
+                let event = match message {
+                    WireMessage::Active(event) => Kind::Active(event.into()),
+                    WireMessage::Passive(event) => Kind::Passive(event.into()),
+                    WireMessage::Control(control) => {
+                        let called = peer
+                            .await_acks(ack_timeout)
+                            .and_then(|()| peer.call(control.clone()));
+                        if called.is_err() {
+                            peer.open(reconnect_policy)?;
+                            peer.await_acks(ack_timeout)?;
+                            peer.call(control)?;
+                        }
+                        continue;
+                    }
+                    WireMessage::Batch(_) => unreachable!("unbatched"),
+                };
+                if peer.send(proto::Event { kind: Some(event) }).is_err() {
+                    peer.open(reconnect_policy)?;
+                }
+            }
+        }
+
+        // the peer may be done and gone by now, so giving up is only reported
+        if peer.await_acks(ack_timeout).is_err()
+            && (peer.open(reconnect_policy).is_err() || peer.await_acks(ack_timeout).is_err())
+        {
+            warn!(
+                target: logging::NETWORK,
+                to = %node,
+                unacknowledged = peer.unacked.len(),
+                "NEVER ACKNOWLEDGED"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Answers every peer's calls until the node stops.
+    pub fn serve(&self, tcp_listener: TcpListener, line_tx: Sender<String>, transport: Transport) {
+        let options = transport.socket_options;
+        let service = Service {
+            line_tx,
+            transport,
+            received: Received::default(),
+            nonces: Mutex::default(),
+        };
+        let served = self.runtime.block_on(async {
+            tcp_listener.set_nonblocking(true)?;
+            let incoming = TcpIncoming::from(tokio::net::TcpListener::from_std(tcp_listener)?)
+                .with_nodelay(Some(options.nodelay))
+                .with_keepalive(options.keepalive)
+                .with_keepalive_interval(options.keepalive);
+            Server::builder()
+                .add_service(NodeServer::new(service))
+                .serve_with_incoming(incoming)
+                .await
+                .map_err(std::io::Error::other)
+        });
+        if let Err(error) = served {
+            warn!(target: logging::NETWORK, %error, "STOPPED SERVING");
+        }
+    }
+}
+
+/// Sending side of the channel to a peer, keeping every event until the peer
+/// acknowledges it.
+struct Peer {
+    runtime: Arc<Runtime>,
+    from: String,
+    node: String,
+    transport: Transport,
+    client: Option<NodeClient<Channel>>,
+    /// Nonce of the current session, which every call proves knowledge of the secret with
+    nonce: String,
+    /// Older of both ends' versions, told on every call
+    version: u32,
+    /// Feeds the event stream currently open
+    events: Option<mpsc::Sender<proto::Event>>,
+    /// Running count of events the peer acknowledged, closed once the stream breaks
+    acked: Option<watch::Receiver<usize>>,
+    /// Events sent but not acknowledged yet, oldest first
+    unacked: VecDeque<proto::Event>,
+    /// Number of events sent before the oldest unacknowledged one
+    first_unacked: usize,
+}
+
+impl Peer {
+    fn new(grpc: &Grpc, from: &str, node: &str, transport: Transport) -> Self {
+        Self {
+            runtime: Arc::clone(&grpc.runtime),
+            from: from.to_string(),
+            node: node.to_string(),
+            transport,
+            client: None,
+            nonce: String::new(),
+            version: PROTOCOL_VERSION,
+            events: None,
+            acked: None,
+            unacked: VecDeque::new(),
+            first_unacked: 0,
+        }
+    }
+
+    /// (Re)connects, opens a new event stream, learns how many events the peer already
+    /// has and resends the rest.
+    fn open(&mut self, policy: RetryPolicy) -> Result<()> {
+        let endpoint = Endpoint::from_shared(format!("http://{}", self.node))
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?
+            .tcp_nodelay(self.transport.socket_options.nodelay)
+            .tcp_keepalive(self.transport.socket_options.keepalive);
+        let (client, challenge) = policy.retry(&self.node, || {
+            self.runtime.block_on(async {
+                let mut client =
+                    NodeClient::new(endpoint.connect().await.map_err(std::io::Error::other)?);
+                let challenge = client
+                    .challenge(proto::ChallengeRequest {})
+                    .await
+                    .map_err(std::io::Error::other)?;
+                Ok((client, challenge.into_inner()))
+            })
+        })?;
+        if !is_compatible(challenge.version) {
+            return Err(AppError::ProtocolMismatch {
+                node: self.node.clone(),
+                version: challenge.version,
+            });
+        }
+        self.nonce = challenge.nonce;
+        self.version = PROTOCOL_VERSION.min(challenge.version);
+
+        let (events, queued) = mpsc::channel(STREAM_QUEUE);
+        let request = self.request(ReceiverStream::new(queued));
+        let mut acks = self
+            .runtime
+            .block_on(client.clone().events(request))
+            .map_err(rejected)?
+            .into_inner();
+        let received = self
+            .runtime
+            .block_on(acks.message())
+            .map_err(rejected)?
+            .map_or(0, |ack| ack.received as usize);
+
+        let (acked_tx, acked) = watch::channel(self.first_unacked.max(received));
+        self.runtime.spawn(async move {
+            while let Ok(Some(ack)) = acks.message().await {
+                acked_tx.send_modify(|acked| *acked = (*acked).max(ack.received as usize));
+            }
+        });
+        self.acked = Some(acked);
+        self.trim();
+
+        for event in &self.unacked {
+            self.runtime
+                .block_on(events.send(event.clone()))
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        }
+        self.events = Some(events);
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// `message` along with who sends it and the proof that it may.
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        let version = self.version.to_string();
+        let proof = self.transport.proof(&self.nonce, &self.from);
+        for (key, value) in [
+            (FROM, self.from.as_str()),
+            (VERSION, version.as_str()),
+            (NONCE, self.nonce.as_str()),
+            (PROOF, proof.as_str()),
+            (RUN, self.transport.run_id().unwrap_or_default()),
+        ] {
+            if let Ok(value) = value.parse() {
+                request.metadata_mut().insert(key, value);
+            }
+        }
+
+        request
+    }
+
+    fn send(&mut self, event: proto::Event) -> std::io::Result<()> {
+        self.unacked.push_back(event.clone());
+        self.trim();
+        let broken = self
+            .acked
+            .as_ref()
+            .is_none_or(|acked| acked.has_changed().is_err());
+        let Some(events) = self.events.as_ref().filter(|_| !broken) else {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        };
+
+        self.runtime
+            .block_on(events.send(event))
+            .map_err(|_| std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Waits until every event sent is acknowledged, or the stream breaks.
+    fn await_acks(&mut self, timeout: Duration) -> std::io::Result<()> {
+        let sent = self.first_unacked + self.unacked.len();
+        let Some(acked) = self.acked.as_mut() else {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        };
+        let waited = self.runtime.block_on(async {
+            tokio::time::timeout(timeout, async {
+                acked.wait_for(|&acked| acked >= sent).await.map(|_| ())
+            })
+            .await
+        });
+        self.trim();
+
+        match waited {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(std::io::ErrorKind::BrokenPipe.into()),
+            Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    /// Makes the call carrying `control`.
+    fn call(&self, control: Control) -> std::io::Result<()> {
+        let Some(mut client) = self.client.clone() else {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        };
+        let called = self.runtime.block_on(async {
+            match control {
+                Control::ClockRequest(message) => {
+                    client.request_clock(self.request(message.into())).await
+                }
+                Control::GvtToken(message) => {
+                    client.pass_gvt_token(self.request(message.into())).await
+                }
+                Control::DeadlockMarker(message) => {
+                    client
+                        .pass_deadlock_marker(self.request(message.into()))
+                        .await
+                }
+                Control::BarrierReport(message) => {
+                    client.report_barrier(self.request(message.into())).await
+                }
+                Control::BarrierGrant(message) => {
+                    client.grant_barrier(self.request(message.into())).await
+                }
+                Control::SnapshotMarker(message) => {
+                    client.mark_snapshot(self.request(message.into())).await
+                }
+                Control::Ready(message) => {
+                    client.announce_ready(self.request(message.into())).await
+                }
+                Control::Handoff(message) => client.hand_off(self.request(message.into())).await,
+                Control::TerminationMarker(message) => {
+                    client
+                        .pass_termination_marker(self.request(message.into()))
+                        .await
+                }
+            }
+        });
+
+        called.map(|_| ()).map_err(std::io::Error::other)
+    }
+
+    fn trim(&mut self) {
+        let acked = self
+            .acked
+            .as_ref()
+            .map_or(self.first_unacked, |acked| *acked.borrow());
+        while self.first_unacked < acked && self.unacked.pop_front().is_some() {
+            self.first_unacked += 1;
+        }
+    }
+}
+
+/// Why the peer turned this node down, unauthenticated senders giving up right away.
+fn rejected(status: Status) -> AppError {
+    match status.code() {
+        Code::Unauthenticated => AppError::Unauthenticated(status.message().to_string()),
+        _ => std::io::Error::other(status).into(),
+    }
+}
+
+/// Receiving side, forwarding every message from a cluster member.
+struct Service {
+    line_tx: Sender<String>,
+    transport: Transport,
+    received: Received,
+    /// Nonces handed out, one per session
+    nonces: Mutex<HashSet<String>>,
+}
+
+impl Service {
+    /// The sender of a call, if it is a cluster member that knows the secret, if any.
+    fn authenticate(&self, metadata: &MetadataMap) -> std::result::Result<String, Status> {
+        let get = |key| {
+            metadata
+                .get(key)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        };
+        let hello = Hello {
+            version: get(VERSION).parse().unwrap_or_default(),
+            from: get(FROM).to_string(),
+            proof: get(PROOF).to_string(),
+            // messages are protobuf whatever the sender's wire format
+            wire_format: WireFormat::default(),
+            run_id: Some(get(RUN))
+                .filter(|run_id| !run_id.is_empty())
+                .map(str::to_string),
+        };
+        let nonce = get(NONCE);
+
+        let authenticated = if !is_compatible(hello.version) {
+            Err(AppError::ProtocolMismatch {
+                node: hello.from.clone(),
+                version: hello.version,
+            })
+        } else if !self.nonces.lock().unwrap().contains(nonce) {
+            Err(AppError::Unauthenticated(format!(
+                "{} did not ask for a challenge",
+                hello.from
+            )))
+        } else {
+            self.transport.authenticate(nonce, &hello)
+        };
+        authenticated.map_err(|error| {
+            warn!(target: logging::NETWORK, peer = %hello.from, %error, "REJECTED");
+            match error {
+                AppError::ProtocolMismatch { .. } | AppError::OtherRun { .. } => {
+                    Status::failed_precondition(error.to_string())
+                }
+                _ => Status::unauthenticated(error.to_string()),
+            }
+        })?;
+
+        Ok(hello.from)
+    }
+
+    /// Forwards the message of a control call.
+    fn forward<T, M>(
+        &self,
+        request: Request<T>,
+    ) -> std::result::Result<Response<proto::Empty>, Status>
+    where
+        M: From<T>,
+        WireMessage: From<M>,
+    {
+        self.authenticate(request.metadata())?;
+        let message = WireMessage::from(M::from(request.into_inner()));
+        let line =
+            serde_json::to_string(&message).map_err(|error| Status::internal(error.to_string()))?;
+        self.line_tx
+            .send(line)
+            .map_err(|_| Status::unavailable("the node is stopping"))?;
+
+        Ok(Response::new(proto::Empty {}))
+    }
+}
+
+#[tonic::async_trait]
+impl Node for Service {
+    async fn challenge(
+        &self,
+        _request: Request<proto::ChallengeRequest>,
+    ) -> std::result::Result<Response<proto::ChallengeReply>, Status> {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex(&nonce);
+        self.nonces.lock().unwrap().insert(nonce.clone());
+
+        Ok(Response::new(proto::ChallengeReply {
+            version: PROTOCOL_VERSION,
+            nonce,
+        }))
+    }
+
+    type EventsStream = ReceiverStream<std::result::Result<proto::Ack, Status>>;
+
+    async fn events(
+        &self,
+        request: Request<Streaming<proto::Event>>,
+    ) -> std::result::Result<Response<Self::EventsStream>, Status> {
+        let from = self.authenticate(request.metadata())?;
+        let mut events = request.into_inner();
+        let (acks, replies) = mpsc::channel(STREAM_QUEUE);
+        let count = *self
+            .received
+            .lock()
+            .unwrap()
+            .entry(from.clone())
+            .or_default();
+        let _ = acks
+            .send(Ok(proto::Ack {
+                received: count as u64,
+            }))
+            .await;
+
+        let line_tx = self.line_tx.clone();
+        let received = Arc::clone(&self.received);
+        tokio::spawn(async move {
+            while let Ok(Some(event)) = events.message().await {
+                let message = match event.kind {
+                    Some(Kind::Active(event)) => WireMessage::from(model::ActiveEvent::from(event)),
+                    Some(Kind::Passive(event)) => {
+                        WireMessage::from(model::PassiveEvent::from(event))
+                    }
+                    None => WireMessage::Batch(Vec::new()),
+                };
+                match serde_json::to_string(&message) {
+                    Ok(line) => {
+                        if line_tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    // still counted, the sender must not resend it
+                    Err(error) => warn!(
+                        target: logging::NETWORK,
+                        %from,
+                        %error,
+                        "DROPPED MESSAGE"
+                    ),
+                }
+
+                let count = {
+                    let mut received = received.lock().unwrap();
+                    let count = received.entry(from.clone()).or_default();
+                    *count += 1;
+                    *count
+                };
+                let ack = proto::Ack {
+                    received: count as u64,
+                };
+                if acks.send(Ok(ack)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(replies)))
+    }
+
+    async fn request_clock(
+        &self,
+        request: Request<proto::ClockRequest>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::ClockRequest>(request)
+    }
+
+    async fn pass_gvt_token(
+        &self,
+        request: Request<proto::GvtToken>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::GvtToken>(request)
+    }
+
+    async fn pass_deadlock_marker(
+        &self,
+        request: Request<proto::DeadlockMarker>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::DeadlockMarker>(request)
+    }
+
+    async fn report_barrier(
+        &self,
+        request: Request<proto::BarrierReport>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::BarrierReport>(request)
+    }
+
+    async fn grant_barrier(
+        &self,
+        request: Request<proto::BarrierGrant>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::BarrierGrant>(request)
+    }
+
+    async fn mark_snapshot(
+        &self,
+        request: Request<proto::SnapshotMarker>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::SnapshotMarker>(request)
+    }
+
+    async fn announce_ready(
+        &self,
+        request: Request<proto::Ready>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::Ready>(request)
+    }
+
+    async fn hand_off(
+        &self,
+        request: Request<proto::Handoff>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::Handoff>(request)
+    }
+
+    async fn pass_termination_marker(
+        &self,
+        request: Request<proto::TerminationMarker>,
+    ) -> std::result::Result<Response<proto::Empty>, Status> {
+        self.forward::<_, model::TerminationMarker>(request)
+    }
+}
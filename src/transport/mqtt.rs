@@ -0,0 +1,246 @@
+use super::{is_compatible, Transport, PROTOCOL_VERSION};
+use crate::address::NodeAddr;
+use crate::error::{AppError, Result};
+use crate::logging;
+use crate::retry::RetryPolicy;
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS};
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Topics of the channels, one per sending and receiving node
+const CHANNELS: &str = "petri/channels";
+
+/// Topics of the nodes' presence
+const NODES: &str = "petri/nodes";
+
+/// Largest message accepted, either way, such as large batches
+const MAX_PACKET: usize = 64 * 1024 * 1024;
+
+/// Wait before the client reconnects to a broker it lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Connection of this node to the broker, shared by its outboxes and its listener.
+/// Every channel is a topic, and every node's presence a retained message holding its
+/// protocol version, cleared by the broker once the node goes away.
+#[derive(Clone)]
+pub struct Broker {
+    node: String,
+    client: Client,
+    /// Taken by the listener, whose thread drives the connection
+    connection: Arc<Mutex<Option<Connection>>>,
+    /// Protocol version of every node present, which is subscribed to its channels
+    present: Arc<(Mutex<HashMap<String, u32>>, Condvar)>,
+    /// Messages published but not acknowledged by the broker yet
+    unacked: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl std::fmt::Debug for Broker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Broker")
+            .field("node", &self.node)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Broker {
+    /// Connects to the broker at `address`, host:port, in the background.
+    pub fn connect(node: &str, address: &NodeAddr, capacity: usize) -> Result<Self> {
+        let port = address.port().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("broker address {address} is not host:port"),
+            )
+        })?;
+        let mut options = MqttOptions::new(node, address.host(), port);
+        options
+            .set_keep_alive(Duration::from_secs(5))
+            .set_clean_session(true)
+            .set_max_packet_size(MAX_PACKET, MAX_PACKET)
+            .set_last_will(LastWill::new(
+                presence_topic(node),
+                Vec::new(),
+                QoS::AtLeastOnce,
+                true,
+            ));
+        let (client, connection) = Client::new(options, capacity);
+
+        Ok(Self {
+            node: node.to_string(),
+            client,
+            connection: Arc::new(Mutex::new(Some(connection))),
+            present: Arc::default(),
+            unacked: Arc::default(),
+        })
+    }
+
+    /// Publishes every message on the channel to `node`, once it is present, then waits
+    /// for the broker to acknowledge them.
+    pub(super) fn deliver(
+        &self,
+        node: &str,
+        messages: Receiver<String>,
+        connect_policy: RetryPolicy,
+        transport: Transport,
+    ) -> Result<()> {
+        self.await_presence(node, connect_policy.deadline)?;
+
+        let topic = channel_topic(&self.node, node);
+        for message in messages {
+            let frame = transport.frame(&self.node, message);
+            *self.unacked.0.lock().unwrap() += 1;
+            self.client
+                .publish(&topic, QoS::ExactlyOnce, false, frame.trim_end())
+                .map_err(std::io::Error::other)?;
+        }
+
+        // the broker may be gone by now, so giving up is only reported
+        let (unacked, acked) = &*self.unacked;
+        let (unacked, _) = acked
+            .wait_timeout_while(
+                unacked.lock().unwrap(),
+                connect_policy.deadline,
+                |unacked| *unacked > 0,
+            )
+            .unwrap();
+        if *unacked > 0 {
+            warn!(
+                target: logging::NETWORK,
+                to = "the broker",
+                unacknowledged = *unacked,
+                "NEVER ACKNOWLEDGED"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Waits until `node` is subscribed to its channels, as the broker drops whatever is
+    /// published before.
+    fn await_presence(&self, node: &str, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let (present, arrived) = &*self.present;
+        let mut present = present.lock().unwrap();
+        loop {
+            match present.get(node) {
+                Some(&version) if is_compatible(version) => return Ok(()),
+                Some(&version) => {
+                    return Err(AppError::ProtocolMismatch {
+                        node: node.to_string(),
+                        version,
+                    })
+                }
+                None => {}
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(AppError::Unreachable {
+                    node: node.to_string(),
+                    attempts: 1,
+                    error: std::io::ErrorKind::TimedOut.into(),
+                });
+            }
+            present = arrived.wait_timeout(present, remaining).unwrap().0;
+        }
+    }
+
+    /// Drives the connection, forwarding every message on this node's channels.
+    pub fn serve(self, line_tx: Sender<String>, transport: Transport) {
+        let Some(mut connection) = self.connection.lock().unwrap().take() else {
+            return;
+        };
+        for notification in connection.iter() {
+            match notification {
+                // every connection starts a clean session, so (re)subscribe, then show up
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    let subscribed = self
+                        .client
+                        .try_subscribe(format!("{CHANNELS}/+/{}", self.node), QoS::ExactlyOnce)
+                        .and_then(|()| {
+                            self.client
+                                .try_subscribe(format!("{NODES}/+"), QoS::AtLeastOnce)
+                        })
+                        .and_then(|()| {
+                            self.client.try_publish(
+                                presence_topic(&self.node),
+                                QoS::AtLeastOnce,
+                                true,
+                                PROTOCOL_VERSION.to_string(),
+                            )
+                        });
+                    if let Err(error) = subscribed {
+                        warn!(target: logging::NETWORK, %error, "SUBSCRIPTION FAILED");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let payload = String::from_utf8_lossy(&publish.payload);
+                    if let Some(node) = publish.topic.strip_prefix(&format!("{NODES}/")) {
+                        let (present, arrived) = &*self.present;
+                        match payload.parse() {
+                            Ok(version) => {
+                                present.lock().unwrap().insert(node.to_string(), version)
+                            }
+                            Err(_) => present.lock().unwrap().remove(node),
+                        };
+                        arrived.notify_all();
+                        continue;
+                    }
+
+                    let Some(from) = publish
+                        .topic
+                        .strip_prefix(&format!("{CHANNELS}/"))
+                        .and_then(|channel| channel.split_once('/'))
+                        .map(|(from, _)| from)
+                    else {
+                        continue;
+                    };
+                    if !transport.is_member(from) {
+                        warn!(
+                            target: logging::NETWORK,
+                            from,
+                            reason = "not a cluster member",
+                            "DROPPED MESSAGE"
+                        );
+                        continue;
+                    }
+                    match transport.unframe(from, &payload) {
+                        Ok(message) => {
+                            if line_tx.send(message.into_owned()).is_err() {
+                                return;
+                            }
+                        }
+                        Err(reason) => warn!(
+                            target: logging::NETWORK,
+                            from,
+                            %reason,
+                            "DROPPED MESSAGE"
+                        ),
+                    }
+                }
+                Ok(Event::Incoming(Packet::PubComp(_))) => {
+                    let (unacked, acked) = &*self.unacked;
+                    let mut unacked = unacked.lock().unwrap();
+                    *unacked = unacked.saturating_sub(1);
+                    acked.notify_all();
+                }
+                Ok(_) => {}
+                // the next notification reconnects
+                Err(error) => {
+                    warn!(target: logging::NETWORK, %error, "BROKER LOST");
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+}
+
+fn channel_topic(from: &str, to: &str) -> String {
+    format!("{CHANNELS}/{from}/{to}")
+}
+
+fn presence_topic(node: &str) -> String {
+    format!("{NODES}/{node}")
+}
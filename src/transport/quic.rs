@@ -0,0 +1,212 @@
+use super::{read_connection, spawn_connection, Received, SocketOptions, Transport};
+use crate::address::NodeAddr;
+use crate::error::{AppError, Result};
+use crate::logging;
+use crate::model::WireMessage;
+use crate::tls::{Stream, Tls};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{
+    ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig,
+    TokioRuntime,
+};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tracing::warn;
+
+/// Sent by the opening side of every stream, which only reaches the peer once written to,
+/// whereas the accepting side speaks first.
+const OPENING: u8 = b'\n';
+
+/// QUIC endpoint of this node: a single UDP socket carrying a connection to every peer,
+/// each reconnection of an outbox being a new stream on the same connection.
+#[derive(Debug, Clone)]
+pub struct Quic {
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+    /// Outgoing connections by node, opened once
+    connections: Arc<Mutex<HashMap<String, Connection>>>,
+}
+
+impl Quic {
+    /// Binds the endpoint to this node's address. QUIC always encrypts, with the TLS
+    /// certificates of the cluster.
+    pub fn bind(address: &NodeAddr, tls: &Tls, socket_options: &SocketOptions) -> Result<Self> {
+        let configs = tls.configs().ok_or_else(|| {
+            AppError::Tls("--link quic needs --tls-cert, --tls-key and --tls-ca".to_string())
+        })?;
+        let client = QuicClientConfig::try_from(Arc::clone(&configs.client))
+            .map_err(|error| AppError::Tls(error.to_string()))?;
+        let server = QuicServerConfig::try_from(Arc::clone(&configs.server))
+            .map_err(|error| AppError::Tls(error.to_string()))?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let mut endpoint = {
+            let _context = runtime.enter();
+            Endpoint::new(
+                EndpointConfig::default(),
+                Some(ServerConfig::with_crypto(Arc::new(server))),
+                socket_options.bind_udp(address)?,
+                Arc::new(TokioRuntime),
+            )?
+        };
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(client)));
+
+        Ok(Self {
+            runtime: Arc::new(runtime),
+            endpoint,
+            connections: Arc::default(),
+        })
+    }
+
+    /// Opens a stream to `node`, at `address`, connecting first if there is no live
+    /// connection yet.
+    pub fn connect(&self, node: &str, address: &NodeAddr) -> std::io::Result<Box<dyn Stream>> {
+        let connection = self.connection(node, address)?;
+        let (mut send, recv) = self
+            .runtime
+            .block_on(connection.open_bi())
+            .map_err(std::io::Error::from)?;
+        self.runtime
+            .block_on(send.write_all(&[OPENING]))
+            .map_err(std::io::Error::from)?;
+
+        Ok(Box::new(QuicStream {
+            runtime: Arc::clone(&self.runtime),
+            send,
+            recv,
+            nonblocking: Cell::new(false),
+            read_timeout: Cell::new(None),
+        }))
+    }
+
+    fn connection(&self, node: &str, address: &NodeAddr) -> std::io::Result<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections
+            .get(node)
+            .filter(|connection| connection.close_reason().is_none())
+        {
+            return Ok(connection.clone());
+        }
+
+        // connecting spawns the task driving the connection
+        let _context = self.runtime.enter();
+        let connecting = self
+            .endpoint
+            .connect(address.resolve()?, &address.host())
+            .map_err(std::io::Error::other)?;
+        let connection = self
+            .runtime
+            .block_on(connecting)
+            .map_err(std::io::Error::from)?;
+        connections.insert(node.to_string(), connection.clone());
+
+        Ok(connection)
+    }
+
+    /// Reads every stream of every incoming connection on a thread of its own.
+    pub fn serve(&self, message_tx: Sender<WireMessage>, transport: Transport) {
+        let received = Received::default();
+        while let Some(incoming) = self.runtime.block_on(self.endpoint.accept()) {
+            let peer = incoming.remote_address();
+            let runtime = Arc::clone(&self.runtime);
+            let message_tx = message_tx.clone();
+            let received = Arc::clone(&received);
+            let transport = transport.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("streams from {peer}"))
+                .spawn(move || {
+                    let connection = match runtime.block_on(async { incoming.await }) {
+                        Ok(connection) => connection,
+                        Err(error) => {
+                            warn!(target: logging::NETWORK, %peer, %error, "REJECTED");
+                            return;
+                        }
+                    };
+                    // a connection closing ends its streams, and the peer reconnects if need be
+                    while let Ok((send, recv)) = runtime.block_on(connection.accept_bi()) {
+                        let mut stream = QuicStream {
+                            runtime: Arc::clone(&runtime),
+                            send,
+                            recv,
+                            nonblocking: Cell::new(false),
+                            read_timeout: Cell::new(None),
+                        };
+                        let message_tx = message_tx.clone();
+                        let received = Arc::clone(&received);
+                        let transport = transport.clone();
+                        spawn_connection(&peer.to_string(), move || {
+                            stream.read_exact(&mut [0])?;
+                            read_connection(Box::new(stream), message_tx, received, &transport)
+                        });
+                    }
+                });
+            if let Err(error) = spawned {
+                warn!(target: logging::NETWORK, %peer, %error, "CONNECTION NOT HANDLED");
+            }
+        }
+    }
+}
+
+/// A QUIC stream used from blocking code.
+struct QuicStream {
+    runtime: Arc<Runtime>,
+    send: SendStream,
+    recv: RecvStream,
+    nonblocking: Cell<bool>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl Read for QuicStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let nonblocking = self.nonblocking.get();
+        let read_timeout = self.read_timeout.get();
+        let recv = &mut self.recv;
+        self.runtime.block_on(async {
+            // reads are cancel-safe, so giving up loses nothing
+            let read = if nonblocking {
+                tokio::time::timeout(Duration::ZERO, recv.read(buf))
+                    .await
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::WouldBlock))?
+            } else if let Some(read_timeout) = read_timeout {
+                tokio::time::timeout(read_timeout, recv.read(buf))
+                    .await
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))?
+            } else {
+                recv.read(buf).await
+            };
+            Ok(read.map_err(std::io::Error::from)?.unwrap_or(0))
+        })
+    }
+}
+
+impl Write for QuicStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.runtime
+            .block_on(self.send.write(buf))
+            .map_err(std::io::Error::from)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for QuicStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+}
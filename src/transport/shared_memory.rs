@@ -0,0 +1,340 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::logging;
+use crate::retry::RetryPolicy;
+use memmap2::MmapRaw;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// First word of every ring file
+const MAGIC: u64 = u64::from_le_bytes(*b"petriRNG");
+
+/// Offsets in a ring file, each word on a cache line of its own so the writer and the
+/// reader do not contend for them: bytes ever written, bytes ever read, when the reader
+/// was last alive in milliseconds since the epoch, then the data itself
+const WRITTEN: usize = 64;
+const READ: usize = 128;
+const HEARTBEAT: usize = 192;
+const DATA: usize = 256;
+
+/// Bytes of data in a ring, messages longer than that going through in several parts
+const CAPACITY: usize = 4 * 1024 * 1024;
+
+/// How often the reader tells it is alive, and how long without hearing from it before
+/// writers give up on it
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spins, then yields, before an idle ring is polled at this pace
+const IDLE_SPINS: u32 = 64;
+const IDLE_YIELDS: u32 = 64;
+const IDLE_SLEEP: Duration = Duration::from_micros(100);
+
+/// Channels to and from nodes on the same host, each a ring buffer in a memory-mapped
+/// file that its receiver creates and its sender writes to, with no system call either
+/// way while messages flow.
+#[derive(Debug)]
+pub struct SharedMemory {
+    node: String,
+    dir: PathBuf,
+    /// Peers whose channels, both ways, go through shared memory
+    peers: Vec<String>,
+}
+
+impl SharedMemory {
+    pub fn new(config: &Config, members: &[String]) -> std::io::Result<Self> {
+        if let Some(peer) = config
+            .shared_memory
+            .iter()
+            .find(|peer| !members.contains(peer))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("--shared-memory {peer} is not a cluster member"),
+            ));
+        }
+
+        Ok(Self {
+            node: config.node.clone(),
+            dir: config.shared_memory_dir.clone(),
+            peers: config
+                .shared_memory
+                .iter()
+                .filter(|peer| **peer != config.node)
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Whether the channel to `node` goes through shared memory.
+    pub fn connects(&self, node: &str) -> bool {
+        self.peers.iter().any(|peer| peer == node)
+    }
+
+    /// Writes every message to the ring of the channel to `node`, then waits for `node`
+    /// to read them. Should `node` restart, whatever it had not read yet is lost.
+    pub(super) fn deliver(
+        &self,
+        node: &str,
+        messages: Receiver<String>,
+        connect_policy: RetryPolicy,
+        reconnect_policy: RetryPolicy,
+    ) -> Result<()> {
+        let path = self.path(&self.node, node);
+        let mut ring = connect_policy.retry(node, || Ring::open(&path))?;
+        for message in messages {
+            if ring.write_all(message.as_bytes()).is_err() {
+                ring = reconnect_policy.retry(node, || Ring::open(&path))?;
+                ring.write_all(message.as_bytes())?;
+            }
+        }
+
+        // the peer may be done and gone by now, so giving up is only reported
+        let unread = ring.drain(connect_policy.deadline);
+        if unread > 0 {
+            warn!(target: logging::NETWORK, to = %node, unread, "NEVER READ");
+        }
+
+        Ok(())
+    }
+
+    /// Creates the ring of every peer's channel to this node, then forwards every line
+    /// written to them, each on a thread of its own.
+    pub fn serve(&self, line_tx: Sender<String>) {
+        for peer in &self.peers {
+            let ring = match Ring::create(&self.path(peer, &self.node)) {
+                Ok(ring) => ring,
+                Err(error) => {
+                    warn!(target: logging::NETWORK, from = %peer, %error, "RING NOT CREATED");
+                    continue;
+                }
+            };
+            let (peer, line_tx) = (peer.clone(), line_tx.clone());
+            let spawned = thread::Builder::new()
+                .name(format!("ring from {peer}"))
+                .spawn(move || {
+                    for line in BufReader::new(ring).lines() {
+                        match line {
+                            Ok(line) => {
+                                if line_tx.send(line).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(error) => {
+                                warn!(
+                                    target: logging::NETWORK,
+                                    from = %peer,
+                                    %error,
+                                    "RING NOT READ"
+                                );
+                                break;
+                            }
+                        }
+                    }
+                });
+            if let Err(error) = spawned {
+                warn!(target: logging::NETWORK, %error, "RING NOT READ");
+            }
+        }
+    }
+
+    fn path(&self, from: &str, to: &str) -> PathBuf {
+        let name = |node: &str| node.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        self.dir
+            .join(format!("petri-{}-{}.ring", name(from), name(to)))
+    }
+}
+
+/// Ring buffer in a memory-mapped file, read as a stream of bytes by one process and
+/// written by another.
+struct Ring {
+    map: MmapRaw,
+}
+
+impl Ring {
+    /// Creates the ring at `path`, replacing any left behind by a previous run. Writers
+    /// only ever see it whole, as it is set up under another name first.
+    fn create(path: &Path) -> std::io::Result<Self> {
+        let setup = path.with_extension("setup");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&setup)?;
+        file.set_len((DATA + CAPACITY) as u64)?;
+        let ring = Self {
+            map: MmapRaw::map_raw(&file)?,
+        };
+        ring.word(0).store(MAGIC, Ordering::Relaxed);
+        ring.beat();
+        std::fs::rename(&setup, path)?;
+
+        Ok(ring)
+    }
+
+    /// Opens the ring at `path`, as long as its reader is alive.
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() <= DATA as u64 {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        let ring = Self {
+            map: MmapRaw::map_raw(&file)?,
+        };
+        if ring.word(0).load(Ordering::Relaxed) != MAGIC {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        if !ring.is_alive() {
+            return Err(std::io::ErrorKind::ConnectionRefused.into());
+        }
+
+        Ok(ring)
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: every offset is within the header and 8-byte aligned, as the mapping is
+        // page aligned, and the mapping lives as long as `self`
+        unsafe { &*(self.map.as_mut_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    fn capacity(&self) -> usize {
+        self.map.len() - DATA
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    /// Tells writers the reader is alive, at most every `HEARTBEAT_INTERVAL`.
+    fn beat(&self) {
+        let now = Self::now();
+        let heartbeat = self.word(HEARTBEAT);
+        if now.saturating_sub(heartbeat.load(Ordering::Relaxed))
+            >= HEARTBEAT_INTERVAL.as_millis() as u64
+        {
+            heartbeat.store(now, Ordering::Relaxed);
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        Self::now().saturating_sub(self.word(HEARTBEAT).load(Ordering::Relaxed))
+            < HEARTBEAT_TIMEOUT.as_millis() as u64
+    }
+
+    /// Where `position` is in the data, and how many of `len` bytes fit before it wraps
+    /// around.
+    fn split(&self, position: u64, len: usize) -> (usize, usize) {
+        let offset = (position % self.capacity() as u64) as usize;
+        (offset, len.min(self.capacity() - offset))
+    }
+
+    fn copy_in(&self, position: u64, bytes: &[u8]) {
+        let (offset, first) = self.split(position, bytes.len());
+        // SAFETY: both parts are within the data, and the writer only ever touches the
+        // free part of the ring while the reader only touches the written one
+        unsafe {
+            let data = self.map.as_mut_ptr().add(DATA);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(offset), first);
+            std::ptr::copy_nonoverlapping(bytes[first..].as_ptr(), data, bytes.len() - first);
+        }
+    }
+
+    fn copy_out(&self, position: u64, bytes: &mut [u8]) {
+        let (offset, first) = self.split(position, bytes.len());
+        // SAFETY: as in `copy_in`
+        unsafe {
+            let data = self.map.as_ptr().add(DATA);
+            std::ptr::copy_nonoverlapping(data.add(offset), bytes.as_mut_ptr(), first);
+            let rest = &mut bytes[first..];
+            std::ptr::copy_nonoverlapping(data, rest.as_mut_ptr(), rest.len());
+        }
+    }
+
+    /// Waits until the reader has read everything, for at most `timeout`, returning how
+    /// many bytes are left unread.
+    fn drain(&self, timeout: Duration) -> u64 {
+        let deadline = Instant::now() + timeout;
+        let mut idle = Idle::default();
+        loop {
+            let unread = self.word(WRITTEN).load(Ordering::Relaxed)
+                - self.word(READ).load(Ordering::Acquire);
+            if unread == 0 || !self.is_alive() || Instant::now() >= deadline {
+                return unread;
+            }
+            idle.wait();
+        }
+    }
+}
+
+impl Read for Ring {
+    /// Waits until there is something to read, as there is no end to a ring.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.word(READ).load(Ordering::Relaxed);
+        let mut idle = Idle::default();
+        loop {
+            self.beat();
+            let written = self.word(WRITTEN).load(Ordering::Acquire);
+            let len = ((written - read) as usize).min(buf.len());
+            if len > 0 {
+                self.copy_out(read, &mut buf[..len]);
+                self.word(READ).store(read + len as u64, Ordering::Release);
+                return Ok(len);
+            }
+            idle.wait();
+        }
+    }
+}
+
+impl Write for Ring {
+    /// Waits until there is room for part of `buf`, failing once the reader is gone.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.word(WRITTEN).load(Ordering::Relaxed);
+        let mut idle = Idle::default();
+        loop {
+            if !self.is_alive() {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            let read = self.word(READ).load(Ordering::Acquire);
+            let free = self.capacity() - (written - read) as usize;
+            let len = free.min(buf.len());
+            if len > 0 || buf.is_empty() {
+                self.copy_in(written, &buf[..len]);
+                self.word(WRITTEN)
+                    .store(written + len as u64, Ordering::Release);
+                return Ok(len);
+            }
+            idle.wait();
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Backs off while a ring stays idle: spinning at first, sleeping in the end.
+#[derive(Default)]
+struct Idle(u32);
+
+impl Idle {
+    fn wait(&mut self) {
+        self.0 += 1;
+        if self.0 <= IDLE_SPINS {
+            std::hint::spin_loop();
+        } else if self.0 <= IDLE_SPINS + IDLE_YIELDS {
+            thread::yield_now();
+        } else {
+            thread::sleep(IDLE_SLEEP);
+        }
+    }
+}
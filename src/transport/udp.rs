@@ -0,0 +1,343 @@
+use super::{is_compatible, Transport, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::address::NodeAddr;
+use crate::error::Result;
+use crate::logging;
+use crate::retry::RetryPolicy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Largest payload a datagram can carry
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Retransmissions attempted for the last unacknowledged messages once nothing else
+/// is left to send
+const CLOSING_RETRANSMISSIONS: usize = 3;
+
+/// First line of every datagram, the framed message following it. There is no
+/// connection to introduce the sender once, so every datagram does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    from: String,
+    /// Position among all datagrams sent by `from` to this node
+    seq: usize,
+    /// Run the sender takes part in, if it knows it yet
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+/// Sending side, one datagram per message from an unbound socket of its own, so the
+/// peer's acknowledgements (its count of contiguous datagrams received) come back to it.
+pub(super) struct Datagrams {
+    from: String,
+    node: String,
+    address: NodeAddr,
+    transport: Transport,
+    socket: UdpSocket,
+    /// Datagrams sent but not acknowledged yet, oldest first
+    unacked: VecDeque<Vec<u8>>,
+    /// Number of datagrams sent before the oldest unacknowledged one
+    first_unacked: usize,
+    /// Since when the oldest unacknowledged datagram has been waiting for its acknowledgement
+    waiting_since: Instant,
+    /// Whether the peer acknowledged anything yet, until then datagrams are likely lost
+    answered: bool,
+}
+
+impl Datagrams {
+    pub(super) fn new(from: &str, node: &str, transport: Transport) -> Result<Self> {
+        let mut datagrams = Self {
+            from: from.to_string(),
+            node: node.to_string(),
+            address: node.parse()?,
+            transport,
+            socket: UdpSocket::bind(unspecified(Ipv4Addr::UNSPECIFIED.into()))?,
+            unacked: VecDeque::new(),
+            first_unacked: 0,
+            waiting_since: Instant::now(),
+            answered: false,
+        };
+        // a name that does not resolve yet is looked up again on every retransmission
+        let _ = datagrams.reconnect();
+
+        Ok(datagrams)
+    }
+
+    /// Points the socket at whatever address the peer resolves to now, with a socket of
+    /// that address family.
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        let peer = self.address.resolve()?;
+        if self.socket.local_addr()?.is_ipv4() != peer.is_ipv4() {
+            self.socket = UdpSocket::bind(unspecified(peer.ip()))?;
+        }
+        self.socket.connect(peer)
+    }
+
+    /// Sends every message as it comes. Datagrams are retransmitted with
+    /// `connect_policy` until the peer first answers and, when `ack_timeout` is set,
+    /// with `reconnect_policy` whenever they go unacknowledged that long; otherwise
+    /// lost ones stay lost.
+    pub(super) fn deliver(
+        mut self,
+        messages: Receiver<String>,
+        connect_policy: RetryPolicy,
+        reconnect_policy: RetryPolicy,
+        ack_timeout: Option<Duration>,
+    ) -> Result<()> {
+        let poll = ack_timeout.map_or(Duration::from_millis(100), |timeout| timeout / 4);
+        loop {
+            match messages.recv_timeout(poll) {
+                Ok(message) => self.send(message)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            self.poll_acks()?;
+            if !self.answered {
+                self.retransmit(connect_policy)?;
+            } else if let Some(ack_timeout) = ack_timeout {
+                if !self.unacked.is_empty() && self.waiting_since.elapsed() >= ack_timeout {
+                    warn!(
+                        target: logging::NETWORK,
+                        to = %self.node,
+                        unacknowledged = self.unacked.len(),
+                        "RETRANSMITTING"
+                    );
+                    self.retransmit(reconnect_policy)?;
+                }
+            }
+            if ack_timeout.is_none() && self.answered {
+                self.first_unacked += self.unacked.len();
+                self.unacked.clear();
+            }
+        }
+
+        // the peer may be done and gone by now, so giving up is only reported
+        if let Some(ack_timeout) = ack_timeout {
+            for _ in 0..CLOSING_RETRANSMISSIONS {
+                let deadline = Instant::now() + ack_timeout;
+                while !self.unacked.is_empty() && Instant::now() < deadline {
+                    self.poll_acks()?;
+                    thread::sleep(poll);
+                }
+                if self.unacked.is_empty() {
+                    break;
+                }
+                self.unacked
+                    .iter()
+                    .try_for_each(|datagram| self.socket.send(datagram).map(|_| ()))?;
+            }
+            if !self.unacked.is_empty() {
+                warn!(
+                    target: logging::NETWORK,
+                    to = %self.node,
+                    unacknowledged = self.unacked.len(),
+                    "NEVER ACKNOWLEDGED"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send(&mut self, message: String) -> Result<()> {
+        let header = serde_json::to_string(&Header {
+            version: PROTOCOL_VERSION,
+            from: self.from.clone(),
+            seq: self.first_unacked + self.unacked.len(),
+            run_id: self.transport.run_id().map(str::to_string),
+        })?;
+        let datagram = format!("{header}\n{}", self.transport.frame(&self.from, message));
+        if datagram.len() > MAX_DATAGRAM {
+            return Err(std::io::Error::other(format!(
+                "{} bytes do not fit in a datagram, try --compress-above",
+                datagram.len()
+            ))
+            .into());
+        }
+
+        if self.unacked.is_empty() {
+            self.waiting_since = Instant::now();
+        }
+        // a peer that is not listening yet makes sends fail, which retransmission covers
+        let _ = self.socket.send(datagram.as_bytes());
+        self.unacked.push_back(datagram.into_bytes());
+
+        Ok(())
+    }
+
+    /// Resends every unacknowledged datagram until some are acknowledged.
+    fn retransmit(&mut self, policy: RetryPolicy) -> Result<()> {
+        let node = self.node.clone();
+        let acked = self.first_unacked;
+        policy.retry(&node, || {
+            self.reconnect()?;
+            self.unacked
+                .iter()
+                .try_for_each(|datagram| self.socket.send(datagram).map(|_| ()))?;
+            thread::sleep(policy.base_delay);
+            self.poll_acks()?;
+            if self.first_unacked == acked && !self.unacked.is_empty() {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            Ok(())
+        })
+    }
+
+    /// Reads whatever acknowledgements arrived, without waiting for more.
+    fn poll_acks(&mut self) -> std::io::Result<()> {
+        self.socket.set_nonblocking(true)?;
+        let mut buffer = [0; 32];
+        let polled = loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => {
+                    self.answered = true;
+                    let count = std::str::from_utf8(&buffer[..len])
+                        .ok()
+                        .and_then(|count| count.trim().parse().ok())
+                        .unwrap_or_default();
+                    while self.first_unacked < count && self.unacked.pop_front().is_some() {
+                        self.first_unacked += 1;
+                        self.waiting_since = Instant::now();
+                    }
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => break Ok(()),
+                // the peer is not listening yet, reported on a later send
+                Err(error) if error.kind() == std::io::ErrorKind::ConnectionRefused => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
+        self.socket.set_nonblocking(false)?;
+
+        polled
+    }
+}
+
+/// Datagrams received from one sender
+#[derive(Debug, Default)]
+struct Received {
+    /// Count of contiguous datagrams received, acknowledged back
+    contiguous: usize,
+    /// Datagrams received past a missing one
+    ahead: BTreeSet<usize>,
+}
+
+impl Received {
+    /// Whether the datagram is new, as retransmissions may repeat it.
+    fn insert(&mut self, seq: usize) -> bool {
+        if seq < self.contiguous || !self.ahead.insert(seq) {
+            return false;
+        }
+        while self.ahead.remove(&self.contiguous) {
+            self.contiguous += 1;
+        }
+        true
+    }
+}
+
+/// Reads every datagram on `socket`, forwarding each new message and acknowledging it.
+pub(super) fn receive_datagrams(socket: UdpSocket, line_tx: Sender<String>, transport: Transport) {
+    let mut received = HashMap::<String, Received>::new();
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(error) => {
+                warn!(target: logging::NETWORK, %error, "RECEIVE FAILED");
+                continue;
+            }
+        };
+        let Some((header, frame)) = std::str::from_utf8(&buffer[..len])
+            .ok()
+            .and_then(|datagram| datagram.split_once('\n'))
+        else {
+            warn!(
+                target: logging::NETWORK,
+                %peer,
+                reason = "malformed",
+                "DROPPED DATAGRAM"
+            );
+            continue;
+        };
+        let header = match serde_json::from_str::<Header>(header) {
+            Ok(header) if is_compatible(header.version) => header,
+            Ok(header) => {
+                warn!(
+                    target: logging::NETWORK,
+                    from = %header.from,
+                    version = header.version,
+                    min_version = MIN_PROTOCOL_VERSION,
+                    reason = "protocol version too old",
+                    "DROPPED DATAGRAM"
+                );
+                continue;
+            }
+            Err(error) => {
+                warn!(
+                    target: logging::NETWORK,
+                    %peer,
+                    %error,
+                    reason = "malformed",
+                    "DROPPED DATAGRAM"
+                );
+                continue;
+            }
+        };
+        if !transport.is_member(&header.from) {
+            warn!(
+                target: logging::NETWORK,
+                from = %header.from,
+                reason = "not a cluster member",
+                "DROPPED DATAGRAM"
+            );
+            continue;
+        }
+        if let Err(error) = transport.check_run(&header.from, header.run_id.as_deref()) {
+            warn!(
+                target: logging::NETWORK,
+                from = %header.from,
+                %error,
+                "DROPPED DATAGRAM"
+            );
+            continue;
+        }
+
+        let sender = received.entry(header.from.clone()).or_default();
+        if sender.insert(header.seq) {
+            match transport.unframe(&header.from, frame.trim_end()) {
+                Ok(message) => {
+                    if line_tx.send(message.into_owned()).is_err() {
+                        return;
+                    }
+                }
+                Err(reason) => warn!(
+                    target: logging::NETWORK,
+                    from = %header.from,
+                    %reason,
+                    "DROPPED MESSAGE"
+                ),
+            }
+        }
+        if let Err(error) = socket.send_to(sender.contiguous.to_string().as_bytes(), peer) {
+            warn!(
+                target: logging::NETWORK,
+                to = %header.from,
+                %error,
+                "ACKNOWLEDGEMENT FAILED"
+            );
+        }
+    }
+}
+
+/// Any local address of the family of `ip`, on any port.
+fn unspecified(ip: IpAddr) -> SocketAddr {
+    match ip {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
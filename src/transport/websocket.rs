@@ -0,0 +1,155 @@
+use super::{read_connection, spawn_connection, Received, Transport};
+use crate::error::Result;
+use crate::logging;
+use crate::model::WireMessage;
+use crate::tls::Stream;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::{Message, WebSocket};
+
+/// Path dashboards subscribe on, every other one being a peer's connection
+const EVENTS_PATH: &str = "/events";
+
+/// Upgrades a connection to `node` to a WebSocket.
+pub fn connect(stream: Box<dyn Stream>, node: &str) -> Result<Box<dyn Stream>> {
+    let (socket, _) = tungstenite::client(format!("ws://{node}/"), stream)
+        .map_err(|error| std::io::Error::other(error.to_string()))?;
+
+    Ok(Box::new(WebSocketStream::new(socket)))
+}
+
+/// Reads every peer's WebSocket like a TCP connection, and forwards every message received
+/// to the dashboards subscribed as well.
+pub fn serve(tcp_listener: TcpListener, message_tx: Sender<WireMessage>, transport: Transport) {
+    let subscribers = Arc::new(Mutex::new(Vec::<Sender<String>>::new()));
+    let (received_tx, received_rx) = channel::<WireMessage>();
+    let fan_out = Arc::clone(&subscribers);
+    thread::spawn(move || {
+        for message in received_rx {
+            let mut subscribers = fan_out.lock().unwrap();
+            // dashboards read JSON whatever the wire format, encoded only if one listens
+            if !subscribers.is_empty() {
+                if let Ok(line) = serde_json::to_string(&message) {
+                    subscribers.retain(|subscriber| subscriber.send(line.clone()).is_ok());
+                }
+            }
+            drop(subscribers);
+            if message_tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let received = Received::default();
+    tcp_listener.incoming().flatten().for_each(|stream| {
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        if let Err(error) = transport.socket_options.apply_incoming(&stream) {
+            warn!(target: logging::NETWORK, %peer, %error, "SOCKET OPTIONS NOT SET");
+        }
+        let message_tx = received_tx.clone();
+        let subscribers = Arc::clone(&subscribers);
+        let received = Arc::clone(&received);
+        let transport = transport.clone();
+        spawn_connection(&peer, move || {
+            let mut path = String::new();
+            // the error response is tungstenite's to choose
+            #[allow(clippy::result_large_err)]
+            let remember_path = |request: &Request, response: Response| {
+                path = request.uri().path().to_string();
+                Ok(response)
+            };
+            let socket = tungstenite::accept_hdr(transport.tls.accept(stream)?, remember_path)
+                .map_err(|error| std::io::Error::other(error.to_string()))?;
+
+            if path == EVENTS_PATH {
+                let (subscriber, lines) = channel();
+                subscribers.lock().unwrap().push(subscriber);
+                return feed(socket, lines.into_iter());
+            }
+            let stream = Box::new(WebSocketStream::new(socket));
+            read_connection(stream, message_tx, received, &transport)
+        });
+    });
+}
+
+/// Sends a dashboard every line as a text message, until it goes away.
+fn feed(mut socket: WebSocket<Box<dyn Stream>>, lines: impl Iterator<Item = String>) -> Result<()> {
+    for line in lines {
+        socket
+            .send(Message::Text(line))
+            .map_err(|error| std::io::Error::other(error.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// A WebSocket read and written as a stream of bytes, each write being a binary message.
+struct WebSocketStream {
+    socket: WebSocket<Box<dyn Stream>>,
+    /// Rest of the last message read, not consumed yet
+    pending: Vec<u8>,
+}
+
+impl WebSocketStream {
+    fn new(socket: WebSocket<Box<dyn Stream>>) -> Self {
+        Self {
+            socket,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Read for WebSocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.socket.read() {
+                Ok(Message::Binary(data)) => self.pending = data,
+                Ok(Message::Text(text)) => self.pending = text.into_bytes(),
+                Ok(Message::Close(_)) => return Ok(0),
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(0)
+                }
+                Err(tungstenite::Error::Io(error)) => return Err(error),
+                Err(error) => return Err(std::io::Error::other(error.to_string())),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for WebSocketStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.socket.send(Message::Binary(buf.to_vec())) {
+            Ok(()) => Ok(buf.len()),
+            Err(tungstenite::Error::Io(error)) => Err(error),
+            Err(error) => Err(std::io::Error::other(error.to_string())),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for WebSocketStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.socket.get_ref().set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.socket.get_ref().set_read_timeout(timeout)
+    }
+}
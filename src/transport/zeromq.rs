@@ -0,0 +1,153 @@
+use super::{is_compatible, Transport, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::address::NodeAddr;
+use crate::error::Result;
+use crate::logging;
+use crate::retry::RetryPolicy;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{Receiver, Sender};
+use tracing::warn;
+
+/// First frame of every message, the framed message being the second. PULL sockets do not
+/// tell who sent what, so every message does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    from: String,
+    /// Run the sender takes part in, if it knows it yet
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+/// Pushes every message to the PULL socket of `node`. ZeroMQ connects in the background,
+/// queueing messages until it does, and reconnects on its own.
+pub(super) fn deliver(
+    from: &str,
+    node: &str,
+    messages: Receiver<String>,
+    connect_policy: RetryPolicy,
+    transport: Transport,
+) -> Result<()> {
+    let socket = zmq::Context::new().socket(zmq::PUSH).map_err(io)?;
+    socket
+        .set_reconnect_ivl(connect_policy.base_delay.as_millis() as i32)
+        .map_err(io)?;
+    // the peer may be done and gone by the end, so closing only waits that long for it
+    socket
+        .set_linger(connect_policy.deadline.as_millis() as i32)
+        .map_err(io)?;
+    let address = node.parse::<NodeAddr>()?;
+    socket.set_ipv6(address.is_ipv6()).map_err(io)?;
+    socket.connect(&endpoint(&address)).map_err(io)?;
+
+    for message in messages {
+        // the run is only known once the cluster is ready
+        let header = serde_json::to_string(&Header {
+            version: PROTOCOL_VERSION,
+            from: from.to_string(),
+            run_id: transport.run_id().map(str::to_string),
+        })?;
+        let frame = transport.frame(from, message);
+        socket
+            .send_multipart([header.as_bytes(), frame.trim_end().as_bytes()], 0)
+            .map_err(io)?;
+    }
+
+    Ok(())
+}
+
+/// Where a node's peers push their messages.
+pub struct Inbox {
+    socket: zmq::Socket,
+}
+
+impl Inbox {
+    pub fn bind(address: &NodeAddr) -> std::io::Result<Self> {
+        let socket = zmq::Context::new().socket(zmq::PULL).map_err(io)?;
+        // ZeroMQ always binds IPv6 addresses dual-stack, once told to take them
+        socket.set_ipv6(address.is_ipv6()).map_err(io)?;
+        socket.bind(&endpoint(address)).map_err(io)?;
+
+        Ok(Self { socket })
+    }
+
+    /// Forwards every message pushed by a cluster member.
+    pub fn serve(self, line_tx: Sender<String>, transport: Transport) {
+        loop {
+            let frames = match self.socket.recv_multipart(0) {
+                Ok(frames) => frames,
+                Err(error) => {
+                    warn!(target: logging::NETWORK, %error, "RECEIVE FAILED");
+                    continue;
+                }
+            };
+            let [header, frame] = frames.as_slice() else {
+                warn!(target: logging::NETWORK, reason = "malformed", "DROPPED MESSAGE");
+                continue;
+            };
+            let header = match serde_json::from_slice::<Header>(header) {
+                Ok(header) if is_compatible(header.version) => header,
+                Ok(header) => {
+                    warn!(
+                        target: logging::NETWORK,
+                        from = %header.from,
+                        version = header.version,
+                        min_version = MIN_PROTOCOL_VERSION,
+                        reason = "protocol version too old",
+                        "DROPPED MESSAGE"
+                    );
+                    continue;
+                }
+                Err(error) => {
+                    warn!(
+                        target: logging::NETWORK,
+                        %error,
+                        reason = "malformed",
+                        "DROPPED MESSAGE"
+                    );
+                    continue;
+                }
+            };
+            if !transport.is_member(&header.from) {
+                warn!(
+                    target: logging::NETWORK,
+                    from = %header.from,
+                    reason = "not a cluster member",
+                    "DROPPED MESSAGE"
+                );
+                continue;
+            }
+            if let Err(error) = transport.check_run(&header.from, header.run_id.as_deref()) {
+                warn!(
+                    target: logging::NETWORK,
+                    from = %header.from,
+                    %error,
+                    "DROPPED MESSAGE"
+                );
+                continue;
+            }
+
+            let frame = String::from_utf8_lossy(frame);
+            match transport.unframe(&header.from, &frame) {
+                Ok(message) => {
+                    if line_tx.send(message.into_owned()).is_err() {
+                        return;
+                    }
+                }
+                Err(reason) => warn!(
+                    target: logging::NETWORK,
+                    from = %header.from,
+                    %reason,
+                    "DROPPED MESSAGE"
+                ),
+            }
+        }
+    }
+}
+
+fn endpoint(address: &NodeAddr) -> String {
+    format!("tcp://{address}")
+}
+
+fn io(error: zmq::Error) -> std::io::Error {
+    std::io::Error::other(error)
+}
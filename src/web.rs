@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::dashboard::Status;
+use crate::error::Result;
+
+#[cfg(feature = "web")]
+use std::io::{Read, Write};
+#[cfg(feature = "web")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "web")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "web")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "web")]
+use std::thread;
+#[cfg(feature = "web")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "web")]
+use tracing::info;
+#[cfg(feature = "web")]
+use tungstenite::Message;
+
+/// Page served at /, drawing the node it came from and the peers in its ?peers= query
+#[cfg(feature = "web")]
+const PAGE: &str = include_str!("web.html");
+
+/// How often every page is sent the status, when it changed
+#[cfg(feature = "web")]
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Serves a page animating the marking and clocks of this node over HTTP, pushing its
+/// status over a WebSocket at /events, until dropped once the run is over.
+pub struct WebDashboard {
+    #[cfg(feature = "web")]
+    shared: Arc<Shared>,
+}
+
+#[cfg(feature = "web")]
+#[derive(Default)]
+struct Shared {
+    /// Latest status along with how many came before it
+    status: Mutex<(usize, Status)>,
+    /// Pages connected
+    pages: AtomicUsize,
+}
+
+impl WebDashboard {
+    #[cfg(not(feature = "web"))]
+    pub fn start(_address: &str, _config: &Config) -> Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "built without web dashboard support, rebuild with --features web",
+        )
+        .into())
+    }
+
+    #[cfg(feature = "web")]
+    pub fn start(address: &str, config: &Config) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        info!(address, "WEB DASHBOARD");
+
+        let shared = Arc::new(Shared::default());
+        shared.status.lock().unwrap().1 = Status {
+            node: config.node.clone(),
+            terminal_clock: config.terminal_clock.unwrap_or(usize::MAX),
+            ..Status::default()
+        };
+        let served = shared.clone();
+        thread::spawn(move || {
+            listener.incoming().flatten().for_each(|stream| {
+                let shared = served.clone();
+                thread::spawn(move || serve(stream, &shared));
+            })
+        });
+
+        Ok(Self { shared })
+    }
+
+    #[cfg(not(feature = "web"))]
+    pub fn update(&self, _status: Status) {}
+
+    #[cfg(feature = "web")]
+    pub fn update(&self, status: Status) {
+        let mut latest = self.shared.status.lock().unwrap();
+        *latest = (latest.0 + 1, status);
+    }
+}
+
+#[cfg(feature = "web")]
+impl Drop for WebDashboard {
+    /// Gives the pages a moment to be told the run is over.
+    fn drop(&mut self) {
+        {
+            let mut latest = self.shared.status.lock().unwrap();
+            latest.0 += 1;
+            latest.1.finished = true;
+        }
+        let since = Instant::now();
+        while self.shared.pages.load(Ordering::SeqCst) > 0 && since.elapsed() < 10 * REFRESH {
+            thread::sleep(REFRESH);
+        }
+    }
+}
+
+/// Answers a request for the page or for its stream of statuses.
+#[cfg(feature = "web")]
+fn serve(mut stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let mut head = [0; 1024];
+    let read = stream.peek(&mut head)?;
+    let head = String::from_utf8_lossy(&head[..read]);
+    match head.split_whitespace().nth(1) {
+        Some("/events") => {
+            shared.pages.fetch_add(1, Ordering::SeqCst);
+            let pushed = push(stream, shared);
+            shared.pages.fetch_sub(1, Ordering::SeqCst);
+            pushed
+        }
+        Some(path) => {
+            let _ = stream.read(&mut [0; 1024])?;
+            let (status, body) = if path == "/" || path.starts_with("/?") {
+                ("200 OK", PAGE)
+            } else {
+                ("404 Not Found", "")
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+/// Sends the status to a page whenever it changed, until the run is over.
+#[cfg(feature = "web")]
+fn push(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let mut socket = tungstenite::accept(stream).map_err(std::io::Error::other)?;
+    let mut sent = None;
+    loop {
+        let (version, status) = shared.status.lock().unwrap().clone();
+        if sent != Some(version) {
+            socket
+                .send(Message::Text(serde_json::to_string(&status)?))
+                .map_err(std::io::Error::other)?;
+            sent = Some(version);
+            if status.finished {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return Ok(());
+            }
+        }
+        thread::sleep(REFRESH);
+    }
+}
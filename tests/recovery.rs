@@ -0,0 +1,138 @@
+//! Kills a node halfway through a run and restarts it with --recover, on Unix sockets in
+//! a temporary folder so the test neither competes for a port nor needs a network.
+
+#![cfg(unix)]
+
+use petri::generate;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long either node may take to get where the test waits for it
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ticks the node to be killed logs before it is
+const TICKS_BEFORE_KILL: usize = 10;
+
+/// Starts `node` of `nodes` on the net in `nets_folder`, paced so it is still running
+/// when killed, with `args`.
+fn start(nets_folder: &Path, nodes: &[String], node: &str, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_petri"))
+        .arg("--node")
+        .arg(node)
+        .arg("--nodes")
+        .args(nodes)
+        .arg("--nets-folder")
+        .arg(nets_folder)
+        .args([
+            "--terminal-clock",
+            "60",
+            "--real-time-factor",
+            "20",
+            "--write-ahead-log",
+        ])
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start the node")
+}
+
+/// Waits until `done` holds, failing the test after `TIMEOUT`.
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done() {
+        assert!(Instant::now() < deadline, "Timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Waits for `node` to exit, failing the test after `TIMEOUT`.
+fn finish(node: &mut Child) -> ExitStatus {
+    let mut status = None;
+    wait_until("a node to finish", || {
+        status = node.try_wait().expect("Failed to wait on the node");
+        status.is_some()
+    });
+    status.expect("The node finished")
+}
+
+/// Every record in the write-ahead log of `node`.
+fn records(node: &str) -> Vec<Value> {
+    fs::read_to_string(format!("{node}.wal"))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Sequence numbers of the messages from `from` that `node` consumed, in order.
+fn consumed(node: &str, from: &str) -> Vec<u64> {
+    records(node)
+        .iter()
+        .filter_map(|record| record["tick"]["events"].as_array())
+        .flatten()
+        .filter_map(|event| event.as_object()?.values().next())
+        .filter(|event| event["feeding_node"] == from)
+        .filter_map(|event| event["channel_seq"].as_u64())
+        .collect()
+}
+
+/// Summary `node` wrote once it finished.
+fn summary(node: &str) -> Value {
+    let summary = fs::read_to_string(format!("{node}.summary.json"))
+        .unwrap_or_else(|error| panic!("{node} wrote no summary: {error}"));
+    serde_json::from_str(&summary).expect("Unreadable summary")
+}
+
+#[test]
+fn recovers_a_killed_node() {
+    let folder = std::env::temp_dir().join(format!("petri-test-recovery-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    let nets_folder: PathBuf = folder.join("nets");
+    generate::write(&nets_folder, &generate::generate(20, 2, 2, 2))
+        .expect("Failed to write the generated net");
+    let nodes = ["a", "b"]
+        .map(|node| folder.join(format!("{node}.sock")).display().to_string())
+        .to_vec();
+    let (a, b) = (nodes[0].as_str(), nodes[1].as_str());
+
+    let mut node_a = start(&nets_folder, &nodes, a, &[]);
+    let mut node_b = start(&nets_folder, &nodes, b, &[]);
+    wait_until("b to get under way", || {
+        records(b)
+            .iter()
+            .filter(|record| record.get("tick").is_some())
+            .count()
+            >= TICKS_BEFORE_KILL
+    });
+    node_b.kill().expect("Failed to kill b");
+    node_b.wait().expect("Failed to reap b");
+    let mut node_b = start(&nets_folder, &nodes, b, &["--recover"]);
+
+    assert!(finish(&mut node_a).success(), "a failed");
+    assert!(finish(&mut node_b).success(), "b failed after recovering");
+    for node in [a, b] {
+        let summary = summary(node);
+        assert_eq!(summary["exit_reason"], "terminal_clock", "{node}");
+        assert_eq!(summary["clock"], 60, "{node}");
+    }
+    let log = fs::read_to_string(format!("{b}.log")).expect("b wrote no log");
+    assert!(log.contains("WAL REPLAYED"), "b did not replay its log");
+
+    // what either end lost to the crash was resent, and nothing was consumed twice
+    for (node, from) in [(a, b), (b, a)] {
+        let consumed = consumed(node, from);
+        assert!(!consumed.is_empty(), "{node} consumed nothing from {from}");
+        assert_eq!(
+            consumed,
+            (0..consumed.len() as u64).collect::<Vec<_>>(),
+            "{node} consumed from {from}"
+        );
+    }
+
+    let _ = fs::remove_dir_all(&folder);
+}